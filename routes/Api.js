@@ -98,7 +98,9 @@ const getPrettifiedDate = (unixTime) => {
 
     const date = new Date(unixTime);
     const month = months[date.getMonth()];
-    const day = days[date.getDay()];
+
+    /*- getDay() is sunday first, the days array is monday first -*/
+    const day = days[(date.getDay() + 6) % 7];
 
     return `${day}, ${month} ${date.getDate()} - ${date.getFullYear()}`;
 }
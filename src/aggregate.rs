@@ -0,0 +1,124 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Bson, Document };
+use crate::{ commands, editor, output_handler, users };
+
+/*- Shown when opening the editor -*/
+static PIPELINE_TEMPLATE: &str = "[\n    { \"$match\": {} }\n]\n";
+
+/*- Parse a pipeline from JSON. A single object is treated as a pipeline with one stage -*/
+pub fn parse_pipeline(input:&str) -> Result<Vec<Document>, String> {
+    let json:serde_json::Value = serde_json::from_str(input)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+    /*- Extended JSON like {"$oid": "..."} is converted to the real bson types -*/
+    let stages = match Bson::try_from(json).map_err(|e| format!("Invalid pipeline: {}", e))? {
+        Bson::Array(stages) => stages,
+        Bson::Document(stage) => vec![Bson::Document(stage)],
+        _ => return Err(String::from("A pipeline must be an array of stages")),
+    };
+
+    return stages.into_iter()
+        .map(|stage| match stage {
+            Bson::Document(stage) => Ok(stage),
+            other => Err(format!("Stage {} is not a document", other)),
+        })
+        .collect();
+}
+
+/*- Field paths in pipelines are prefixed with $ -*/
+fn field_path(field:&str) -> String {
+    return format!("${}", field.trim_start_matches('$'));
+}
+
+/*- Expand the shorthand verbs into a pipeline -*/
+fn shorthand(argv:&[String]) -> Option<Result<Vec<Document>, String>> {
+    let args:Vec<&str> = argv.iter().map(|a| a.as_str()).collect();
+
+    return Some(match args.as_slice() {
+        /*- aggregate group by role count -*/
+        ["group", "by", field, "count"] => Ok(vec![
+            doc! { "$group": { "_id": field_path(field), "count": { "$sum": 1 } } },
+            doc! { "$sort": { "count": -1, "_id": 1 } },
+        ]),
+
+        /*- aggregate distinct role -*/
+        ["distinct", field] => Ok(vec![
+            doc! { "$group": { "_id": field_path(field) } },
+            doc! { "$sort": { "_id": 1 } },
+        ]),
+
+        /*- aggregate top 10 role, the most common values -*/
+        ["top", amount, field] => match amount.parse::<i64>() {
+            Ok(amount) if amount > 0 => Ok(vec![
+                doc! { "$group": { "_id": field_path(field), "count": { "$sum": 1 } } },
+                doc! { "$sort": { "count": -1, "_id": 1 } },
+                doc! { "$limit": amount },
+            ]),
+            _ => Err(format!("<{}> is not a positive number", amount)),
+        },
+
+        /*- aggregate lookup friends, resolves the friend suids without leaking secrets -*/
+        ["lookup", "friends"] => Ok(vec![
+            doc! { "$lookup": {
+                "from": users::USERS_COLLECTION,
+                "localField": "friends",
+                "foreignField": "suid",
+                "as": "friends_data",
+            } },
+            doc! { "$project": {
+                "username": 1,
+                "suid": 1,
+                "friends": 1,
+                "friends_data.username": 1,
+                "friends_data.suid": 1,
+            } },
+        ]),
+
+        _ => return None,
+    });
+}
+
+/*- Run an aggregation pipeline on the current collection -*/
+pub fn aggregate(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let pipeline = if let Some(pipeline) = shorthand(&argv) {
+        pipeline
+    }else if &argv[0] == "file" && argv.len() == 2 {
+        std::fs::read_to_string(&argv[1])
+            .map_err(|e| format!("Failed to read {}: {}", &argv[1], e))
+            .and_then(|input| parse_pipeline(&input))
+    }else if &argv[0] == "edit" && argv.len() == 1 {
+        editor::edit(PIPELINE_TEMPLATE, "json").and_then(|input| parse_pipeline(&input))
+    }else {
+        /*- The input was split on whitespace, join it back to get the JSON -*/
+        parse_pipeline(&argv.join(" "))
+    };
+
+    let pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            output_handler::throw_res(Color::Red, "Write <help aggregate> for further information.");
+            return;
+        }
+    };
+
+    let cursor = match commands::current_collection().aggregate(pipeline) {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Aggregation failed: {}", e).as_str());
+            return;
+        }
+    };
+
+    let mut amount = 0;
+    for doc in cursor {
+        output_handler::throw_document(&doc);
+        amount += 1;
+    }
+
+    output_handler::throw_res(Color::Cyan, format!("{} result(s)", amount).as_str());
+}
@@ -0,0 +1,278 @@
+use termcolor::{ Color };
+use reqwest::blocking::{ Client, RequestBuilder, multipart };
+use serde::{ Deserialize, Serialize, de::DeserializeOwned };
+use std::fmt;
+use std::path::Path;
+use crate::{ commands, config, output_handler, selftest };
+
+/*- Everything that can go wrong when talking to the API -*/
+#[derive(Debug)]
+pub enum ApiError {
+    /*- Couldn't connect, timed out and similar -*/
+    Http(reqwest::Error),
+
+    /*- The HTTP status wasn't a success -*/
+    HttpStatus { status: u16, body: String },
+
+    /*- The HTTP status was fine, but the "status" field of the JSON body wasn't 200 -*/
+    Status { status: u16, message: String },
+
+    /*- The body couldn't be parsed -*/
+    Decode(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            ApiError::Http(e) => write!(f, "Request failed: {}", e),
+            ApiError::HttpStatus { status, body } => write!(f, "HTTP {}: {}", status, body),
+            ApiError::Status { status, message } => write!(f, "API status {}: {}", status, message),
+            ApiError::Decode(e) => write!(f, "Invalid response: {}", e),
+        };
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e:reqwest::Error) -> ApiError {
+        return ApiError::Http(e);
+    }
+}
+
+/*- Every JSON response of the API looks like this -*/
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    status: u16,
+    message: Option<String>,
+    data: Option<T>,
+}
+
+/*- Headers for api/create-account -*/
+#[derive(Debug, Clone, Serialize)]
+pub struct CreateAccount {
+    pub email: String,
+    pub username: String,
+    pub displayname: String,
+    pub password: String,
+}
+
+/*- Returned by api/create-account and api/login -*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountData {
+    pub uid: String,
+    pub suid: String,
+    pub username: String,
+    pub displayname: String,
+    pub profile: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Joined {
+    pub prettified: Option<String>,
+    pub unix: Option<i64>,
+}
+
+/*- The SafeUser model, returned by api/profile-data and api/get-friends-data -*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafeUser {
+    pub suid: String,
+    pub username: Option<String>,
+    pub displayname: Option<String>,
+    pub joined: Option<Joined>,
+    pub role: Option<String>,
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub friends: Vec<String>,
+}
+
+/*- Returned by api/profile-data/image/:suid -*/
+#[derive(Debug, Clone)]
+pub struct ProfileImage {
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/*- Typed client for every route in routes/Api.js -*/
+pub struct ApiClient {
+    base_url: String,
+    http: Client,
+}
+
+impl ApiClient {
+    pub fn new(base_url:&str) -> ApiClient {
+        return ApiClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: Client::new(),
+        };
+    }
+
+    /*- Client for the BACKEND_URL in the config -*/
+    pub fn from_config() -> ApiClient {
+        return ApiClient::new(&config::backend_url());
+    }
+
+    fn url(&self, route:&str) -> String {
+        return format!("{}/api/{}", self.base_url, route);
+    }
+
+    /*- Send a request and check the HTTP status, returns the body -*/
+    fn send(&self, request:RequestBuilder) -> Result<reqwest::blocking::Response, ApiError> {
+        let response = request.send()?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            return Err(ApiError::HttpStatus { status, body: response.text().unwrap_or_default() });
+        }
+
+        return Ok(response);
+    }
+
+    /*- Send a request that responds with the JSON envelope, and check both statuses -*/
+    fn send_json<T:DeserializeOwned>(&self, request:RequestBuilder) -> Result<Option<T>, ApiError> {
+        let body = self.send(request)?.text()?;
+        let envelope:Envelope<T> = serde_json::from_str(&body)
+            .map_err(|e| ApiError::Decode(format!("{} ({})", e, body)))?;
+
+        if envelope.status != 200 {
+            return Err(ApiError::Status {
+                status: envelope.status,
+                message: envelope.message.unwrap_or_default(),
+            });
+        }
+
+        return Ok(envelope.data);
+    }
+
+    /*- Same as send_json, for routes that always respond with data -*/
+    fn send_data<T:DeserializeOwned>(&self, request:RequestBuilder) -> Result<T, ApiError> {
+        return self.send_json(request)?
+            .ok_or(ApiError::Decode(String::from("response has no data")));
+    }
+
+    /*- POST api/create-account -*/
+    pub fn create_account(&self, account:&CreateAccount) -> Result<AccountData, ApiError> {
+        return self.send_data(self.http.post(self.url("create-account"))
+            .header("content-type", "application/json")
+            .header("email", &account.email)
+            .header("username", &account.username)
+            .header("displayname", &account.displayname)
+            .header("password", &account.password)
+        );
+    }
+
+    /*- GET api/login -*/
+    pub fn login(&self, email:&str, password:&str) -> Result<AccountData, ApiError> {
+        return self.send_data(self.http.get(self.url("login"))
+            .header("email", email)
+            .header("password", password)
+        );
+    }
+
+    /*- GET api/profile-data -*/
+    pub fn profile_data(&self, suid:&str) -> Result<SafeUser, ApiError> {
+        return self.send_data(self.http.get(self.url("profile-data")).header("suid", suid));
+    }
+
+    /*- GET api/profile-data without dropping unknown fields, to check what the API exposes -*/
+    pub fn profile_data_raw(&self, suid:&str) -> Result<serde_json::Value, ApiError> {
+        return self.send_data(self.http.get(self.url("profile-data")).header("suid", suid));
+    }
+
+    /*- GET api/profile-data/image/:suid, the API falls back to the default image -*/
+    pub fn profile_image(&self, suid:&str) -> Result<ProfileImage, ApiError> {
+        let response = self.send(self.http.get(self.url(&format!("profile-data/image/{}", suid))))?;
+
+        let content_type = response.headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        return Ok(ProfileImage { content_type, bytes: response.bytes()?.to_vec() });
+    }
+
+    /*- POST api/profile-upload, authenticated with the suid cookie -*/
+    pub fn profile_upload(&self, suid:&str, file:&Path) -> Result<(), ApiError> {
+        let form = multipart::Form::new()
+            .file("profile-file", file)
+            .map_err(|e| ApiError::Decode(format!("Failed to read {}: {}", file.display(), e)))?;
+
+        self.send(self.http.post(self.url("profile-upload"))
+            .header("cookie", format!("suid={}", suid))
+            .multipart(form)
+        )?;
+        return Ok(());
+    }
+
+    /*- POST api/add-friend, toggles <friend> in the friends of <suid> -*/
+    pub fn add_friend(&self, suid:&str, friend:&str) -> Result<(), ApiError> {
+        self.send_json::<serde_json::Value>(self.http.post(self.url("add-friend"))
+            .header("suid", suid)
+            .header("friend", friend)
+        )?;
+        return Ok(());
+    }
+
+    /*- GET api/get-friends-data -*/
+    pub fn get_friends_data(&self, suid:&str) -> Result<Vec<SafeUser>, ApiError> {
+        return self.send_data(self.http.get(self.url("get-friends-data")).header("suid", suid));
+    }
+
+    /*- GET api/get-friends-data without dropping unknown fields -*/
+    pub fn get_friends_data_raw(&self, suid:&str) -> Result<Vec<serde_json::Value>, ApiError> {
+        return self.send_data(self.http.get(self.url("get-friends-data")).header("suid", suid));
+    }
+}
+
+/*- Print a response as pretty JSON -*/
+fn throw_json<T:Serialize>(value:&T) {
+    output_handler::throw_res(Color::Green, &serde_json::to_string_pretty(value).unwrap_or_default());
+}
+
+/*- Call the API routes -*/
+pub fn api(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let junit = commands::take_option(&mut argv, "--junit");
+
+    let client = ApiClient::from_config();
+    let args:Vec<&str> = argv.iter().map(|a| a.as_str()).collect();
+
+    let result:Result<(), ApiError> = match args.as_slice() {
+        ["create-account", email, username, displayname, password] => client.create_account(&CreateAccount {
+            email: email.to_string(),
+            username: username.to_string(),
+            displayname: displayname.to_string(),
+            password: password.to_string(),
+        }).map(|data| throw_json(&data)),
+        ["login", email, password] => client.login(email, password).map(|data| throw_json(&data)),
+        ["profile-data", suid] => client.profile_data(suid).map(|data| throw_json(&data)),
+        ["profile-image", suid, file] => client.profile_image(suid).and_then(|image| {
+            std::fs::write(file, &image.bytes)
+                .map_err(|e| ApiError::Decode(format!("Failed to write {}: {}", file, e)))?;
+            output_handler::throw_res(Color::Green,
+                format!("Saved {} bytes of {} to {}", image.bytes.len(), image.content_type, file).as_str()
+            );
+            return Ok(());
+        }),
+        ["profile-upload", suid, file] => client.profile_upload(suid, Path::new(file))
+            .map(|_| output_handler::throw_res(Color::Green, "Image uploaded!")),
+        ["add-friend", suid, friend] => client.add_friend(suid, friend)
+            .map(|_| output_handler::throw_res(Color::Green, "Friend toggled!")),
+        ["get-friends-data", suid] => client.get_friends_data(suid).map(|data| throw_json(&data)),
+        ["selftest"] => {
+            selftest::run(&client, junit.as_deref().unwrap_or(selftest::DEFAULT_REPORT));
+            return;
+        },
+        _ => {
+            output_handler::throw_res(Color::Red, "Invalid syntax! Write <help api> for further information.");
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        output_handler::throw_res(Color::Red, &e.to_string());
+    }
+}
@@ -0,0 +1,74 @@
+use mongodb::bson::{ Bson, Document, DateTime };
+use serde::{ Deserialize, Serialize };
+use std::path::{ Path, PathBuf };
+
+/*- Deleted data is archived here so that it can be restored -*/
+pub static ARCHIVE_DIR: &str = "archive";
+
+/*- The manifest file every archive directory contains -*/
+static MANIFEST_FILE: &str = "manifest.json";
+
+/*- Everything needed to undo a cascading user deletion -*/
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserArchive {
+    /*- The user document as canonical extended JSON, so that no types get lost -*/
+    pub user: serde_json::Value,
+
+    /*- Suids of the users that had this user as a friend -*/
+    pub referenced_by: Vec<String>,
+
+    /*- File name of the archived profile image, inside the archive directory -*/
+    pub image: Option<String>,
+
+    pub operator: String,
+    pub archived_at: String,
+}
+
+impl UserArchive {
+    pub fn new(user:&Document, referenced_by:Vec<String>, image:Option<String>, operator:String) -> UserArchive {
+        return UserArchive {
+            user: Bson::Document(user.clone()).into_canonical_extjson(),
+            referenced_by,
+            image,
+            operator,
+            archived_at: DateTime::now().try_to_rfc3339_string().unwrap_or_default(),
+        };
+    }
+
+    /*- Convert the archived user back to a document -*/
+    pub fn user_document(&self) -> Result<Document, String> {
+        return match Bson::try_from(self.user.clone()) {
+            Ok(Bson::Document(doc)) => Ok(doc),
+            Ok(_) => Err(String::from("Archived user is not a document")),
+            Err(e) => Err(format!("Failed to parse archived user: {}", e)),
+        };
+    }
+}
+
+/*- Create a new, empty archive directory for a user -*/
+pub fn create_dir(suid:&str) -> Result<PathBuf, String> {
+    let dir = Path::new(ARCHIVE_DIR).join(format!("{}-{}", DateTime::now().timestamp_millis(), suid));
+
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create archive {}: {}", dir.display(), e))?;
+
+    return Ok(dir);
+}
+
+/*- Write the manifest into an archive directory -*/
+pub fn write(dir:&Path, archive:&UserArchive) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(archive)
+        .map_err(|e| format!("Failed to serialize archive: {}", e))?;
+
+    return std::fs::write(dir.join(MANIFEST_FILE), json)
+        .map_err(|e| format!("Failed to write archive: {}", e));
+}
+
+/*- Read the manifest of an archive directory -*/
+pub fn read(dir:&Path) -> Result<UserArchive, String> {
+    let json = std::fs::read_to_string(dir.join(MANIFEST_FILE))
+        .map_err(|e| format!("Failed to read archive {}: {}", dir.display(), e))?;
+
+    return serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse archive {}: {}", dir.display(), e));
+}
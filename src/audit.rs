@@ -0,0 +1,31 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Document, DateTime };
+use crate::{ commands, output_handler };
+
+/*- All audit entries are stored in this collection -*/
+static AUDIT_COLLECTION: &str = "audit";
+
+/*- Who is running the CLI. ACLI_OPERATOR can be set if $USER isn't descriptive enough -*/
+pub fn operator() -> String {
+    return std::env::var("ACLI_OPERATOR")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| String::from("unknown"));
+}
+
+/*- Record who changed what, details contains action specific data -*/
+pub fn record(action:&str, target:&str, details:Document) {
+    let coll = commands::get_collection(AUDIT_COLLECTION);
+
+    let entry = doc! {
+        "action": action,
+        "target": target,
+        "operator": operator(),
+        "date": DateTime::now(),
+        "details": details,
+    };
+
+    /*- Failing to audit shouldn't undo the change, but the user should know -*/
+    if let Err(e) = coll.insert_one(entry) {
+        output_handler::throw_res(Color::Red, format!("Failed to write audit entry: {}", e).as_str());
+    }
+}
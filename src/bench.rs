@@ -0,0 +1,405 @@
+use termcolor::{ Color };
+use reqwest::{ Client, RequestBuilder };
+use serde_json::{ json, Value };
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+use crate::{ commands, config, output_handler, selftest };
+
+/*- Routes that can be benchmarked -*/
+static ROUTES: [&str; 4] = ["create-account", "login", "profile-data", "get-friends-data"];
+
+/*- Used when neither --duration nor --requests is given -*/
+static DEFAULT_DURATION: Duration = Duration::from_secs(30);
+
+/*- Every generated account gets this many friends, so get-friends-data has something to look up -*/
+static FRIENDS_PER_USER: usize = 5;
+
+/*- Upper bounds of the latency histogram buckets in milliseconds, the last bucket is everything above -*/
+static BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/*- A generated account that the requests are made with -*/
+#[derive(Debug, Clone)]
+struct BenchUser {
+    email: String,
+    password: String,
+    suid: String,
+}
+
+/*- When the benchmark stops -*/
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    Duration(Duration),
+    Requests(usize),
+}
+
+/*- The result of a single request -*/
+struct Sample {
+    latency: Duration,
+
+    /*- None if the request succeeded, otherwise what went wrong -*/
+    error: Option<String>,
+
+    /*- Set for successful create-account requests so the account can be removed afterwards -*/
+    created: Option<String>,
+}
+
+/*- Parse durations like 500ms, 30s or 2m -*/
+pub fn parse_duration(input:&str) -> Result<Duration, String> {
+    let invalid = || format!("<{}> is not a duration like 500ms, 30s or 2m", input);
+    let split = input.find(|c:char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let amount = input[..split].parse::<u64>().map_err(|_| invalid())?;
+
+    return match &input[split..] {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" | "" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        _ => Err(invalid()),
+    };
+}
+
+/*- A throwaway account, the suffix keeps the usernames unique between runs -*/
+fn generated_account(index:usize, run:&str) -> (String, String, String) {
+    let username = format!("bn_{}_{}", run, index);
+    return (format!("{}@bench.invalid", username), username, format!("pw-{}", run));
+}
+
+/*- Send a request and check both the HTTP status and the status in the JSON body like api::ApiClient -*/
+async fn send(request:RequestBuilder) -> Result<Value, String> {
+    let response = request.send().await.map_err(|e| {
+        if e.is_timeout() { String::from("timeout") } else if e.is_connect() { String::from("connection failed") } else { String::from("request failed") }
+    })?;
+
+    let status = response.status().as_u16();
+    if !(200..300).contains(&status) {
+        return Err(format!("HTTP {}", status));
+    }
+
+    let body:Value = response.json().await.map_err(|_| String::from("malformed JSON"))?;
+    return match body.get("status").and_then(|status| status.as_u64()) {
+        Some(200) => Ok(body),
+        Some(status) => Err(format!("status {}: {}", status, body.get("message").and_then(|m| m.as_str()).unwrap_or(""))),
+        None => Err(String::from("no status in body")),
+    };
+}
+
+/*- POST api/create-account, returns the suid -*/
+async fn create_account(client:&Client, base:&str, email:&str, username:&str, password:&str) -> Result<String, String> {
+    let body = send(client.post(format!("{}/api/create-account", base))
+        .header("email", email)
+        .header("username", username)
+        .header("displayname", username)
+        .header("password", password)
+    ).await?;
+
+    return body.pointer("/data/suid")
+        .and_then(|suid| suid.as_str())
+        .map(|suid| suid.to_string())
+        .ok_or(String::from("no suid in body"));
+}
+
+/*- Create the accounts the other routes are driven with, and give each of them some friends -*/
+async fn setup(client:&Client, base:&str, amount:usize, run:&str) -> Result<Vec<BenchUser>, String> {
+    let mut tasks = Vec::new();
+    for index in 0..amount {
+        let (client, base, run) = (client.clone(), base.to_string(), run.to_string());
+        tasks.push(tokio::spawn(async move {
+            let (email, username, password) = generated_account(index, &run);
+            let suid = create_account(&client, &base, &email, &username, &password).await?;
+            return Ok::<BenchUser, String>(BenchUser { email, password, suid });
+        }));
+    }
+
+    let mut users = Vec::new();
+    let mut error = None;
+    for task in tasks {
+        match task.await.map_err(|e| e.to_string()).and_then(|result| result) {
+            Ok(user) => users.push(user),
+            Err(e) => error = Some(e),
+        }
+    }
+
+    /*- Don't leave the accounts that did get created behind -*/
+    if let Some(e) = error {
+        return Err(remove_created(&users, e));
+    }
+
+    let mut tasks = Vec::new();
+    for (index, user) in users.iter().enumerate() {
+        for offset in 1..=FRIENDS_PER_USER.min(amount - 1) {
+            let friend = users[(index + offset) % amount].suid.clone();
+            let request = client.post(format!("{}/api/add-friend", base))
+                .header("suid", &user.suid)
+                .header("friend", friend);
+            tasks.push(tokio::spawn(send(request)));
+        }
+    }
+    let mut error = None;
+    for task in tasks {
+        if let Err(e) = task.await.map_err(|e| e.to_string()).and_then(|result| result) {
+            error = Some(e);
+        }
+    }
+    if let Some(e) = error {
+        return Err(remove_created(&users, e));
+    }
+
+    return Ok(users);
+}
+
+/*- Clean up after a failed setup, returns the error it failed with -*/
+fn remove_created(users:&[BenchUser], error:String) -> String {
+    let suids:Vec<String> = users.iter().map(|u| u.suid.clone()).collect();
+    selftest::cleanup(&suids).ok();
+    return error;
+}
+
+/*- One request against <route> -*/
+async fn request(client:&Client, base:&str, route:&str, user:&BenchUser, index:usize, run:&str) -> Sample {
+    let start = Instant::now();
+    let mut created = None;
+
+    let result = match route {
+        "create-account" => {
+            let (email, username, password) = generated_account(index, &format!("{}c", run));
+            create_account(client, base, &email, &username, &password).await
+                .map(|suid| { created = Some(suid); })
+        },
+        "login" => send(client.get(format!("{}/api/login", base))
+            .header("email", &user.email)
+            .header("password", &user.password)
+        ).await.map(|_| ()),
+        route => send(client.get(format!("{}/api/{}", base, route))
+            .header("suid", &user.suid)
+        ).await.map(|_| ()),
+    };
+
+    return Sample { latency: start.elapsed(), error: result.err(), created };
+}
+
+/*- Run <concurrency> workers until the limit is reached -*/
+async fn drive(client:Client, base:String, route:String, users:Arc<Vec<BenchUser>>, concurrency:usize, limit:Limit, run:String) -> (Vec<Sample>, Duration) {
+    let issued = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+
+    let mut workers = Vec::new();
+    for _ in 0..concurrency {
+        let (client, base, route, users, issued, run) = (client.clone(), base.clone(), route.clone(), Arc::clone(&users), Arc::clone(&issued), run.clone());
+
+        workers.push(tokio::spawn(async move {
+            let mut samples = Vec::new();
+            loop {
+                let index = issued.fetch_add(1, Ordering::SeqCst);
+                let done = match limit {
+                    Limit::Duration(duration) => start.elapsed() >= duration,
+                    Limit::Requests(amount) => index >= amount,
+                };
+                if done { break; }
+
+                let user = &users[index % users.len()];
+                samples.push(request(&client, &base, &route, user, index, &run).await);
+            }
+            return samples;
+        }));
+    }
+
+    let mut samples = Vec::new();
+    for worker in workers {
+        samples.extend(worker.await.unwrap_or_default());
+    }
+
+    return (samples, start.elapsed());
+}
+
+/*- The latency at a percentile of sorted latencies -*/
+fn percentile(sorted:&[Duration], percentile:f64) -> Duration {
+    if sorted.is_empty() { return Duration::ZERO; }
+
+    let index = ((sorted.len() as f64 * percentile / 100.0).ceil() as usize).clamp(1, sorted.len()) - 1;
+    return sorted[index];
+}
+
+fn millis(duration:Duration) -> f64 {
+    return duration.as_secs_f64() * 1000.0;
+}
+
+/*- Summarize the samples as JSON, the same shape is printed and written with --out -*/
+fn report(route:&str, concurrency:usize, samples:&[Sample], elapsed:Duration) -> Value {
+    let mut latencies:Vec<Duration> = samples.iter().map(|s| s.latency).collect();
+    latencies.sort();
+
+    let mut errors:BTreeMap<String, usize> = BTreeMap::new();
+    for error in samples.iter().filter_map(|s| s.error.as_ref()) {
+        *errors.entry(error.clone()).or_insert(0) += 1;
+    }
+
+    let mut histogram:Vec<Value> = BUCKETS_MS.iter().map(|le| json!({ "le_ms": le, "count": 0 })).collect();
+    histogram.push(json!({ "le_ms": null, "count": 0 }));
+    for latency in &latencies {
+        let bucket = BUCKETS_MS.iter().position(|le| latency.as_millis() <= *le as u128).unwrap_or(BUCKETS_MS.len());
+        histogram[bucket]["count"] = json!(histogram[bucket]["count"].as_u64().unwrap_or(0) + 1);
+    }
+
+    let failed:usize = errors.values().sum();
+    return json!({
+        "route": route,
+        "concurrency": concurrency,
+        "requests": samples.len(),
+        "succeeded": samples.len() - failed,
+        "failed": failed,
+        "elapsed_s": elapsed.as_secs_f64(),
+        "throughput_rps": samples.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        "latency_ms": {
+            "p50": millis(percentile(&latencies, 50.0)),
+            "p90": millis(percentile(&latencies, 90.0)),
+            "p99": millis(percentile(&latencies, 99.0)),
+            "max": millis(latencies.last().copied().unwrap_or_default()),
+        },
+        "errors": errors,
+        "histogram": histogram,
+    });
+}
+
+/*- Print the report as tables -*/
+fn throw_report(report:&Value) {
+    output_handler::throw_res(Color::Cyan, format!("{} requests to {} in {:.2}s, {:.1} req/s",
+        report["requests"], report["route"].as_str().unwrap_or(""), report["elapsed_s"].as_f64().unwrap_or(0.0), report["throughput_rps"].as_f64().unwrap_or(0.0)
+    ).as_str());
+
+    let latency = &report["latency_ms"];
+    output_handler::throw_res(Color::Green, format!("p50 {:.1}ms  p90 {:.1}ms  p99 {:.1}ms  max {:.1}ms",
+        latency["p50"].as_f64().unwrap_or(0.0), latency["p90"].as_f64().unwrap_or(0.0),
+        latency["p99"].as_f64().unwrap_or(0.0), latency["max"].as_f64().unwrap_or(0.0)
+    ).as_str());
+
+    let rows:Vec<(String, usize)> = report["histogram"].as_array().unwrap().iter()
+        .map(|bucket| {
+            let label = match bucket["le_ms"].as_u64() {
+                Some(le) => format!("<= {}ms", le),
+                None => format!("> {}ms", BUCKETS_MS[BUCKETS_MS.len() - 1]),
+            };
+            return (label, bucket["count"].as_u64().unwrap_or(0) as usize);
+        })
+        .collect();
+    output_handler::throw_histogram(&rows);
+
+    match report["failed"].as_u64() {
+        Some(0) => output_handler::throw_res(Color::Green, "No errors"),
+        _ => for (error, amount) in report["errors"].as_object().unwrap() {
+            output_handler::throw_res(Color::Red, format!("{:>7}x {}", amount, error).as_str());
+        },
+    }
+}
+
+/*- Load test a route of the API -*/
+pub fn bench(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let json = commands::take_flag(&mut argv, "--json");
+    let keep = commands::take_flag(&mut argv, "--keep");
+    let out = commands::take_option(&mut argv, "--out");
+    let concurrency = commands::take_option(&mut argv, "--concurrency").map(|c| c.parse::<usize>());
+    let users = commands::take_option(&mut argv, "--users").map(|u| u.parse::<usize>());
+    let duration = commands::take_option(&mut argv, "--duration").map(|d| parse_duration(&d));
+    let requests = commands::take_option(&mut argv, "--requests").map(|r| r.parse::<usize>());
+
+    let route = match argv.as_slice() {
+        [route] if ROUTES.contains(&route.as_str()) => route.clone(),
+        _ => {
+            output_handler::throw_res(Color::Red, format!("Route must be one of {}", ROUTES.join(", ")).as_str());
+            return;
+        }
+    };
+    let concurrency = match concurrency {
+        None => 1,
+        Some(Ok(concurrency)) if concurrency > 0 => concurrency,
+        _ => {
+            output_handler::throw_res(Color::Red, "--concurrency must be a positive number");
+            return;
+        },
+    };
+    let users = match users {
+        None => concurrency.max(2),
+        Some(Ok(users)) if users > 1 => users,
+        _ => {
+            output_handler::throw_res(Color::Red, "--users must be at least 2");
+            return;
+        },
+    };
+    let limit = match (duration, requests) {
+        (Some(_), Some(_)) => {
+            output_handler::throw_res(Color::Red, "Use either --duration or --requests");
+            return;
+        },
+        (Some(Ok(duration)), None) => Limit::Duration(duration),
+        (None, Some(Ok(requests))) if requests > 0 => Limit::Requests(requests),
+        (None, None) => Limit::Duration(DEFAULT_DURATION),
+        (Some(Err(e)), None) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        },
+        _ => {
+            output_handler::throw_res(Color::Red, "--requests must be a positive number");
+            return;
+        },
+    };
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to start the runtime: {}", e).as_str());
+            return;
+        },
+    };
+
+    let base = config::backend_url();
+    let client = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    let run:String = (0..3).map(|_| format!("{:02x}", rand::random::<u8>())).collect();
+
+    /*- With --json stdout is only the report, so runs can be diffed -*/
+    let status = |msg:String| if !json { output_handler::throw_res(Color::Cyan, &msg); };
+
+    status(format!("Creating {} accounts on {}...", users, base));
+    let users = match runtime.block_on(setup(&client, &base, users, &run)) {
+        Ok(users) => Arc::new(users),
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Setup failed: {}", e).as_str());
+            return;
+        },
+    };
+
+    status(format!("Running {} with {} workers, {:?}...", route, concurrency, limit));
+    let (samples, elapsed) = runtime.block_on(drive(client, base, route.clone(), Arc::clone(&users), concurrency, limit, run));
+    let report = report(&route, concurrency, &samples, elapsed);
+
+    if json {
+        output_handler::throw_raw(&serde_json::to_string_pretty(&report).unwrap());
+    }else {
+        throw_report(&report);
+    }
+
+    if let Some(out) = out {
+        match std::fs::write(&out, serde_json::to_string_pretty(&report).unwrap()) {
+            Ok(_) => status(format!("Report written to {}", out)),
+            Err(e) => output_handler::throw_res(Color::Red, format!("Failed to write {}: {}", out, e).as_str()),
+        }
+    }
+
+    /*- Remove the generated accounts, including the ones created by the benchmark itself -*/
+    if !keep {
+        let suids:Vec<String> = users.iter().map(|u| u.suid.clone())
+            .chain(samples.into_iter().filter_map(|s| s.created))
+            .collect();
+        match selftest::cleanup(&suids) {
+            Ok(_) => status(format!("Removed {} generated accounts", suids.len())),
+            Err(e) => output_handler::throw_res(Color::Red, format!("Cleanup failed: {}", e).as_str()),
+        }
+    }
+}
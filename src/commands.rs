@@ -1,30 +1,22 @@
 use termcolor::{ Color };
-use std::process::Command;
-use crate::output_handler;
+use std::process::{ Command, Stdio };
+use std::io::Write;
+use crate::{ output_handler, store };
+use crate::store::DocumentStore;
+use crate::registry::{ self, Form };
+use crate::session::Session;
+use crate::output_handler::Held;
 use mongodb::{
     bson::doc,
     sync::Client,
 };
 use std::io::stdin;
 use rand::prelude::*;
-use reqwest::{
-    header,
-    header::{ HeaderMap },
-    blocking::{ Client as HttpClientBLOCKING }
-};
-use std::sync::Mutex;
-use lazy_static::lazy_static;
-
-lazy_static! {
-    #[allow(deprecated)]
-    static ref CURRENT_COLLECTION: Mutex<String> = Mutex::new(String::from("users"));
-    #[allow(deprecated)]
-    static ref CURRENT_DATABASE: Mutex<String> = Mutex::new(String::from("DockerMongo"));
-}
+use std::cell::{ Cell, RefCell };
 
 /*- Create random command usesthese default names n stuff -*/
-static NAMES: [&str; 25] = ["artur", "bob", "carl", "david", "emily", "frank", "gabriel", "harry", "ian", "james", "kate", "laura", "matt", "natalie", "olivia", "peter", "quinn", "rachel", "sarah", "taylor", "victoria", "wendy", "xavier", "yvonne", "zoey"];
-static LAST_NAMES: [&str; 12] = ["smith", "brown", "davis", "wilson", "williams", "bobson", "hoffman", "harrison", "beck", "jones", "jefferson", "doe"];
+pub static NAMES: [&str; 25] = ["artur", "bob", "carl", "david", "emily", "frank", "gabriel", "harry", "ian", "james", "kate", "laura", "matt", "natalie", "olivia", "peter", "quinn", "rachel", "sarah", "taylor", "victoria", "wendy", "xavier", "yvonne", "zoey"];
+pub static LAST_NAMES: [&str; 12] = ["smith", "brown", "davis", "wilson", "williams", "bobson", "hoffman", "harrison", "beck", "jones", "jefferson", "doe"];
 static PASSWORDS: [&str; 3] = ["password", "12345", "safe"];
 fn get_random(list: &Vec<&str>) -> String {
     let index = rand::thread_rng().gen_range(0..list.len());
@@ -34,10 +26,51 @@ fn get_random(list: &Vec<&str>) -> String {
 /*- IMPORTANT: Green color = output, cyan = status messages like "clearing...", yellow = input -*/
 /*- The connection URI, might want to grab it from .env later -*/
 static MONGO_URI: &str = "mongodb://mongo:27017/nodeapp";
-static BACKEND_URL: &str = "https://wss.artur.red";
-fn initialize_client() -> mongodb::sync::Database {
-    let client:mongodb::sync::Client = Client::with_uri_str(MONGO_URI).expect("Failed to initialize client");
-    return client.database(CURRENT_DATABASE.lock().unwrap().as_str());
+pub fn get_client() -> mongodb::sync::Client {
+    return Client::with_uri_str(MONGO_URI).expect("Failed to initialize client");
+}
+
+/*- Where a session works, changed with <database switch> and <collection switch> -*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub database: String,
+    pub collection: String,
+}
+
+impl Default for Location {
+    fn default() -> Location {
+        return Location { database: String::from("DockerMongo"), collection: String::from("users") };
+    }
+}
+
+/*- The session running on this thread sets its location before its commands run and reads it back after -*/
+pub fn set_location(location:Location) {
+    LOCATION.with(|cell| *cell.borrow_mut() = location);
+}
+
+pub fn location() -> Location {
+    return LOCATION.with(|cell| cell.borrow().clone());
+}
+
+/*- Get the collection the user switched to with <collection switch> -*/
+pub fn current_collection() -> Box<dyn DocumentStore> {
+    let location = location();
+    return store::open(&location.database, &location.collection);
+}
+
+/*- Get a collection by name in the current database, in mongo or the in-memory store -*/
+pub fn get_collection(name:&str) -> Box<dyn DocumentStore> {
+    return store::open(&location().database, name);
+}
+
+/*- What a command calls. Most only need their params, some change the session (tag, prev, exit) -*/
+#[derive(Debug, Clone, Copy)]
+pub enum Handler {
+    Command(fn(Vec<String>)),
+    Session(fn(&mut Session, Vec<String>)),
+
+    /*- Only after a |, gets what the stage before it printed -*/
+    Stage(fn(Vec<String>, Held)),
 }
 
 /*- All the parameters a user-variable has -*/
@@ -45,16 +78,32 @@ fn initialize_client() -> mongodb::sync::Database {
 #[derive(Clone)]
 pub struct CommandStruct<'a> {
     pub _name: &'a str,/*- Name is for calling the function via a String -*/
-    pub _usage: &'a str, /*- Usage is displayed when <help> command is triggered -*/
-    pub _param_required: bool, /*- Parameter is required or not -*/
+    pub _summary: &'a str, /*- One line shown in the <help> list -*/
+
+    /*- The ways to call the command, the dispatcher validates params against them
+        and <help name> is generated from them. See registry.rs for the pattern syntax -*/
+    pub _forms: &'a [Form<'a>],
+    pub _examples: &'a [&'a str],
 
-    /*--- Bind this to some function --
-        first = variables, second = params -*/
-    pub _bind: fn(Vec<String>) -> ()
+    /*- Bind this to some function -*/
+    pub _bind: Handler,
+}
+
+thread_local! {
+    /*- None = ask on stdin -*/
+    static CONFIRM_ANSWER: Cell<Option<bool>> = const { Cell::new(None) };
+
+    /*- Set by the session, see set_location -*/
+    static LOCATION: RefCell<Location> = RefCell::new(Location::default());
+}
+
+/*- Answer every <confirm> on this thread with <answer> instead of reading stdin -*/
+pub fn set_confirm_answer(answer:Option<bool>) {
+    CONFIRM_ANSWER.with(|cell| cell.set(answer));
 }
 
 /*- Beginning of every function that has some sort of input must use this -*/
-fn check_argv(argv: &Vec<String>) -> bool {
+pub fn check_argv(argv: &Vec<String>) -> bool {
     if argv.len() == 0 {
         output_handler::throw_res(Color::Red, "No arguments provided whilst function requires that.");
         return false;
@@ -62,8 +111,36 @@ fn check_argv(argv: &Vec<String>) -> bool {
     return true;
 }
 
+/*- Remove a flag like --mutual from argv, returns true if it was there -*/
+pub fn take_flag(argv:&mut Vec<String>, flag:&str) -> bool {
+    let len = argv.len();
+    argv.retain(|arg| arg != flag);
+    return argv.len() != len;
+}
+
+/*- Remove an option with a value like --format dot from argv, and return the value -*/
+pub fn take_option(argv:&mut Vec<String>, option:&str) -> Option<String> {
+    let index = argv.iter().position(|arg| arg == option)?;
+
+    /*- The option is useless without a value -*/
+    if index + 1 >= argv.len() {
+        argv.remove(index);
+        return None;
+    }
+
+    let value = argv.remove(index + 1);
+    argv.remove(index);
+    return Some(value);
+}
+
 /*- Confirmation function that will be used for "dangerous functions" -*/
-fn confirm(question:&str) -> bool {
+pub fn confirm(question:&str) -> bool {
+
+    /*- Sessions that can't read stdin answer for the user -*/
+    if let Some(answer) = CONFIRM_ANSWER.with(|answer| answer.get()) {
+        output_handler::throw_res(Color::Yellow, format!("{} [y/n] {}", question, if answer { "y" } else { "n" }).as_str());
+        return answer;
+    }
 
     let mut input = String::new();
 
@@ -87,58 +164,42 @@ fn confirm(question:&str) -> bool {
 
 fn help(argv:Vec<String>) {
 
-    if &argv.len() == &0 {
-        output_handler::throw_res(Color::Cyan, "Command parameters are documented like this: command <param> <param2>");
-        output_handler::throw_res(Color::Cyan, "Params are separated by spaces, and do not contain the angle brackets.");
-        output_handler::throw_res(Color::Cyan, "Sometimes parameters are optional, and are marked with a '?', like this: <param>?");
-        output_handler::throw_res(Color::Cyan, "Some functions can have diffrent input parameters. Like the get function:");
-        output_handler::throw_res(Color::Cyan, "get ['all', 'where <key> key <val>'] - these params are enclosed in square brackets.");
-        output_handler::throw_res(Color::Cyan, "The following commands are available:");
-    }
-
-    let mut all_commands:Vec<CommandStruct<'static>> = get_commands();
+    let all_commands:Vec<CommandStruct<'static>> = get_commands();
 
-    /*- If there was a command name specified then we'll output the usage -*/
+    /*- If there was a command name specified then we'll output the usage page -*/
     if argv.len() > 0 {
         let cmd_name = &argv[0];
 
-        /*- Get all commands-*/
-        for command in &all_commands {
-            if command._name == cmd_name {
-                output_handler::throw_res(Color::Green, command._usage);
-                return;
-            }
+        match all_commands.iter().find(|command| command._name == cmd_name) {
+            Some(command) => for line in registry::help_page(command) {
+                output_handler::throw_res(Color::Green, &line);
+            },
+            None => output_handler::throw_res(Color::Red, "Command not found!"),
         }
-
-        output_handler::throw_res(Color::Red, "Command not found!");
         return;
     }
 
-    /*- Get all available commands, and display their usage x spaces to the right of the name -*/
-    /*- The x is determined by the longest command name -*/
-    let mut max_len = 0;
-
-    /*- Find the longest command name -*/
-    for cmd in &all_commands {
-        if cmd._name.len() > max_len {
-            max_len = cmd._name.len();
-        }
-    }
+    output_handler::throw_res(Color::Cyan, "Command parameters are documented like this: command <param> <param2>");
+    output_handler::throw_res(Color::Cyan, "Params are separated by spaces, and do not contain the angle brackets.");
+    output_handler::throw_res(Color::Cyan, "Sometimes parameters are optional, and are marked with a '?', like this: <param>?");
+    output_handler::throw_res(Color::Cyan, "Commands marked with * need params, write <help command> to see all the ways to call it.");
+    output_handler::throw_res(Color::Cyan, "The following commands are available:");
 
-    /*- This command is special, it. doesn't exist in the command vec -*/
-    all_commands.push(CommandStruct { _name: "tag", _usage: "tag <name> - tags the input arrow", _param_required: true, _bind: help });
+    /*- Get all available commands, and display their summary x spaces to the right of the name -*/
+    /*- The x is determined by the longest command name -*/
+    let max_len = all_commands.iter().map(|cmd| cmd._name.len()).max().unwrap_or(0);
 
     /*- Display them -*/
     for cmd in &all_commands {
-        print!("| {} | {} {}", 
-            match cmd._param_required {
+        output_handler::throw_res(Color::Green, format!("| {} | {} {}{}",
+            match registry::params_required(cmd) {
                 true => "*",
                 false => "x",
             },
             cmd._name,
-            " ".repeat(max_len - cmd._name.len())
-        );
-        output_handler::throw_res(Color::Green, cmd._usage);
+            " ".repeat(max_len - cmd._name.len()),
+            cmd._summary
+        ).as_str());
     }
 }
 
@@ -153,10 +214,51 @@ fn clear<P>(_:P) {
     reset(0);
 }
 
-/*- Exit -*/
-fn exit<P>(_:P) {
-    output_handler::throw_res(Color::Cyan, "Exiting...");
-    std::process::exit(0);
+/*- Run a shell command and print what it wrote, stderr as errors -*/
+pub fn shell(command:&str, input:Option<&str>) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::inherit() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to run sh: {}", e).as_str());
+            return;
+        }
+    };
+
+    /*- Write from another thread, the command might not read everything before it writes -*/
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            let input = input.to_string();
+            Some(std::thread::spawn(move || { stdin.write_all(input.as_bytes()).ok(); }))
+        },
+        _ => None,
+    };
+
+    let result = match child.wait_with_output() {
+        Ok(result) => result,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to run sh: {}", e).as_str());
+            return;
+        }
+    };
+    if let Some(writer) = writer { writer.join().ok(); }
+
+    for line in String::from_utf8_lossy(&result.stdout).lines() {
+        output_handler::throw_res(Color::Green, line);
+    }
+    /*- Plenty of programs log to stderr, only the exit status decides if && goes on -*/
+    for line in String::from_utf8_lossy(&result.stderr).lines() {
+        output_handler::throw_res(Color::Yellow, line);
+    }
+    if !result.status.success() {
+        output_handler::throw_res(Color::Red, format!("<{}> exited with {}", command, result.status).as_str());
+    }
 }
 
 /*- Read terminal commands -*/
@@ -166,16 +268,7 @@ fn cmd(argv:Vec<String>) {
     if !check_argv(&argv) { return; }
 
     /*- The command that the user gave -*/
-    let command = argv.join(" ");
-
-    let result = Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .expect("failed to execute process");
-
-    
-    output_handler::throw_res(Color::Green, &String::from_utf8_lossy(&result.stdout));
+    shell(&argv.join(" "), None);
 }
 
 /*- Create a new document -*/
@@ -185,42 +278,25 @@ fn create(argv:Vec<String>) {
     if !check_argv(&argv) { return; }
 
     if &argv[0] == "random" {
-        let client:HttpClientBLOCKING = reqwest::blocking::Client::new();
-
-        /*- POST headers -*/
-        let mut headers:HeaderMap = HeaderMap::new();
-
-        /*- The required headers are listed in API.js -*/
-        headers.insert( header::CONTENT_TYPE, header::HeaderValue::from_static("application/json") );
-        headers.insert( "username", get_random(&NAMES.to_vec()).to_string().parse().unwrap() );
-        headers.insert( "displayname", get_random(&NAMES.to_vec()).to_string().parse().unwrap() );
-        headers.insert( "email", format!("{}@{}.com", get_random(&NAMES.to_vec()), get_random(&LAST_NAMES.to_vec())).parse().unwrap() );
-        headers.insert( "password", get_random(&PASSWORDS.to_vec()).to_string().parse().unwrap() );
-
-        /*- Post with name and email headers -*/
-        let res = client.post(format!("{}/api/create-account", BACKEND_URL))
-            .headers(headers)
-            .send()
-            .unwrap_or_else(|e| {
-                output_handler::throw_res(Color::Red, format!("{}", e).as_str());
-                std::process::exit(1);
-            });
-
-            println!("shitting");
-
-        /*- Get the response -*/
-        let body = res.text().unwrap();
-
-        /*- Check if the response was successful -*/
-        println!("{}", body);
+        let account = crate::api::CreateAccount {
+            username: get_random(&NAMES.to_vec()),
+            displayname: get_random(&NAMES.to_vec()),
+            email: format!("{}@{}.com", get_random(&NAMES.to_vec()), get_random(&LAST_NAMES.to_vec())),
+            password: get_random(&PASSWORDS.to_vec()),
+        };
+
+        /*- Create it through the API, so that it's hashed and validated like any other account -*/
+        match crate::api::ApiClient::from_config().create_account(&account) {
+            Ok(data) => output_handler::throw_res(Color::Green,
+                format!("Account created! {} ({}) password: {}", data.username, data.suid, account.password).as_str()
+            ),
+            Err(e) => output_handler::throw_res(Color::Red, &e.to_string()),
+        }
         return;
     }
 
-    /*- Get the database and the collection that we are using -*/
-    let db:mongodb::sync::Database = initialize_client();
-
-    /*- User collection -*/
-    let coll = db.collection::<mongodb::bson::Document>(CURRENT_COLLECTION.lock().unwrap().as_str());
+    /*- The collection that we are using -*/
+    let coll = current_collection();
 
     /*- A vector of keys and values that the user inputted -*/
     /*- The keys and values are strings like this - key:val-*/
@@ -245,7 +321,7 @@ fn create(argv:Vec<String>) {
     }
 
     /*- Insert the document -*/
-    coll.insert_one(doc, None).unwrap();
+    coll.insert_one(doc).unwrap();
 
     output_handler::throw_res(Color::Green, "Document created!");
 }
@@ -253,16 +329,13 @@ fn create(argv:Vec<String>) {
 /*- Delete documents -*/
 fn delete(argv:Vec<String>) {
 
-    /*- Get the database and the collection that we are using -*/
-    let db:mongodb::sync::Database = initialize_client();
-
-    /*- User collection -*/
-    let coll = db.collection::<mongodb::bson::Document>(CURRENT_COLLECTION.lock().unwrap().as_str());
+    /*- The collection that we are using -*/
+    let coll = current_collection();
 
-    let _get_where_is = |key:&String, val:&String| -> mongodb::sync::Cursor<mongodb::bson::Document> {
+    let _get_where_is = |key:&String, val:&String| -> Vec<mongodb::bson::Document> {
 
         /*- Get the document by the key and value -*/
-        let documents = coll.find(Some(doc! { key:val }), None).unwrap();
+        let documents = coll.find(doc! { key:val }, None).unwrap();
 
         /*- Print the document -*/
         return documents;
@@ -273,7 +346,7 @@ fn delete(argv:Vec<String>) {
         if confirm("Delete all documents?") == true {
 
             /*- Delete everything -*/
-            coll.delete_many(doc! { }, None).unwrap();
+            coll.delete_many(doc! { }).unwrap();
 
             output_handler::throw_res(Color::Green, "All documents deleted!");
         }
@@ -284,7 +357,7 @@ fn delete(argv:Vec<String>) {
         let val = &argv[4];
 
         /*- Get the documents -*/
-        let amount_of_documents = _get_where_is(key, val).count();
+        let amount_of_documents = _get_where_is(key, val).len();
 
         /*- If there are no documents -*/
         if &amount_of_documents == &0 {
@@ -293,7 +366,7 @@ fn delete(argv:Vec<String>) {
         }else {
             /*- Delete the documents -*/
             if confirm(format!("Delete {} document(s)?", &amount_of_documents).as_str()) == true {
-                coll.delete_many(doc! { key:val }, None).unwrap();
+                coll.delete_many(doc! { key:val }).unwrap();
                 output_handler::throw_res(Color::Green,
                     format!("{} document(s) deleted!", &amount_of_documents).as_str()
                 );
@@ -305,16 +378,13 @@ fn delete(argv:Vec<String>) {
 /*- Update documents -*/
 fn update(argv:Vec<String>) {
 
-    /*- Get the database and the collection that we are using -*/
-    let db:mongodb::sync::Database = initialize_client();
-
-    /*- User collection -*/
-    let coll = db.collection::<mongodb::bson::Document>(CURRENT_COLLECTION.lock().unwrap().as_str());
+    /*- The collection that we are using -*/
+    let coll = current_collection();
 
     //Object id as first parameter, key and value as second parameter (that we want to update)
     //Find the document by the id and update it
     //Get the document by the input id
-    let doc = coll.find_one(Some(doc! { "_id":argv[0].parse::<mongodb::bson::oid::ObjectId>().unwrap() }), None).unwrap();
+    let doc = coll.find_one(doc! { "_id":argv[0].parse::<mongodb::bson::oid::ObjectId>().unwrap() }).unwrap();
 
     /*- If the document is not found -*/
     if doc.is_none() {
@@ -342,7 +412,7 @@ fn update(argv:Vec<String>) {
         }
         
         /*- Update the document -*/
-        coll.replace_one(doc! { "_id":argv[0].parse::<mongodb::bson::oid::ObjectId>().unwrap() }, doc, None).unwrap();
+        coll.replace_one(doc! { "_id":argv[0].parse::<mongodb::bson::oid::ObjectId>().unwrap() }, doc).unwrap();
 
         output_handler::throw_res(Color::Green, "Document updated!");
     }
@@ -354,11 +424,8 @@ fn get(argv:Vec<String>) {
     /*- Validate the input -*/
     if !check_argv(&argv) { return }
     
-    /*- Get the database and the collection that we are using -*/
-    let db:mongodb::sync::Database = initialize_client();
-
-    /*- User collection -*/
-    let coll = db.collection::<mongodb::bson::Document>(CURRENT_COLLECTION.lock().unwrap().as_str());
+    /*- The collection that we are using -*/
+    let coll = current_collection();
 
     /*- What the user wants to get -*/
     let to_get = &argv[0];
@@ -368,10 +435,10 @@ fn get(argv:Vec<String>) {
     //
 
     /*- The where <k> is <v> function -*/
-    let _get_where_is = |key:&String, val:&String| -> mongodb::sync::Cursor<mongodb::bson::Document> {
+    let _get_where_is = |key:&String, val:&String| -> Vec<mongodb::bson::Document> {
 
         /*- Get the document by the key and value -*/
-        let documents = coll.find(Some(doc! { key:val }), None).unwrap();
+        let documents = coll.find(doc! { key:val }, None).unwrap();
 
         /*- Print the document -*/
         return documents;
@@ -379,15 +446,15 @@ fn get(argv:Vec<String>) {
     /*- The get-all-documents-function -*/
     let _get_all = || -> Vec<mongodb::bson::Document> {
         /*- Get all the users -*/
-        let documents = match coll.find(None, None) {
-            Ok(cursor) => cursor,
+        let documents = match coll.find(doc! { }, None) {
+            Ok(documents) => documents,
             Err(_) => {
                 output_handler::throw_res(Color::Red, "Failed to get documents!");
                 return vec![];
             }
         };
 
-        return documents.map(|doc| doc.unwrap()).collect();
+        return documents;
     };
 
     //
@@ -400,7 +467,7 @@ fn get(argv:Vec<String>) {
 
         /*- Loop through and print every document -*/
         for doc in documents {
-            output_handler::throw_res(Color::Green, &doc.to_string());
+            output_handler::throw_document(&doc);
         }
     }
 
@@ -417,7 +484,7 @@ fn get(argv:Vec<String>) {
         /*- Get the document by the key and value -*/
         let documents = _get_where_is(k, v);
         for doc in documents {
-            output_handler::throw_res(Color::Green, &doc.unwrap().to_string());
+            output_handler::throw_document(&doc);
         }
     }
 
@@ -427,7 +494,7 @@ fn get(argv:Vec<String>) {
 
         if get_of == "all" && argv.len() == 3 {
             /*- Get all the users -*/
-            let documents = coll.count_documents(None, None).unwrap();
+            let documents = coll.count(doc! { }).unwrap();
 
             /*- Print the length of all the documents -*/
             output_handler::throw_res(Color::Green, &documents.to_string());
@@ -442,7 +509,7 @@ fn get(argv:Vec<String>) {
             let v = &argv[6];
 
             /*- Get the document by the key and value -*/
-            let documents:Vec<mongodb::bson::Document> = _get_where_is(k, v).map(|doc| doc.unwrap()).collect();
+            let documents:Vec<mongodb::bson::Document> = _get_where_is(k, v);
 
             /*- Print the length of the document -*/
             output_handler::throw_res(Color::Green,
@@ -468,12 +535,12 @@ fn collection(argv:Vec<String>) {
         /*- The collection the user wants to work with -*/
         let to_coll = &argv[1];
 
-        /*- Change the CURRENT_COLLECTION -*/
-        *CURRENT_COLLECTION.lock().unwrap() = to_coll.to_string();
+        /*- Change the collection, the session keeps it after the command -*/
+        LOCATION.with(|cell| cell.borrow_mut().collection = to_coll.to_string());
     }else if &argv[0] == "get" {
         
         /*- Show the user what collection they're in -*/
-        output_handler::throw_res(Color::Green, &CURRENT_COLLECTION.lock().unwrap().to_string());
+        output_handler::throw_res(Color::Green, &location().collection);
     }
 }
 
@@ -488,13 +555,13 @@ fn database(argv:Vec<String>) {
         /*- The database the user wants to work with -*/
         let to_db = &argv[1];
 
-        /*- Change the CURRENT_DATABASE -*/
-        *CURRENT_DATABASE.lock().unwrap() = to_db.to_string();
+        /*- Change the database, the session keeps it after the command -*/
+        LOCATION.with(|cell| cell.borrow_mut().database = to_db.to_string());
 
     }else if &argv[0] == "get" {
         
         /*- Show the user what database they're working with -*/
-        output_handler::throw_res(Color::Green, &CURRENT_DATABASE.lock().unwrap().to_string());
+        output_handler::throw_res(Color::Green, &location().database);
     }
 }
 
@@ -510,17 +577,327 @@ fn shit<P>(_:P) {
 /*- Return all commands -*/
 pub fn get_commands() -> Vec<CommandStruct<'static>> {
     return vec![
-        CommandStruct { _name: "help",       _usage: "help <command name>?",                            _bind: help,            _param_required: false },
-        CommandStruct { _name: "reset",      _usage: "clear all output - same as <clear>",              _bind: reset,           _param_required: false },
-        CommandStruct { _name: "clear",      _usage: "clear all output - same as <reset>",              _bind: clear,           _param_required: false },
-        CommandStruct { _name: "exit",       _usage: "exit the CLI",                                    _bind: exit,            _param_required: false },
-        CommandStruct { _name: "cmd",        _usage: "cmd <terminal_command>",                          _bind: cmd,             _param_required: true },
-        CommandStruct { _name: "create",     _usage: "create <key:val> <some_key:some_val>",            _bind: create,          _param_required: true },
-        CommandStruct { _name: "get",        _usage: "get ['all', 'all where <key> is <val>', 'length of ['all', 'where <key> is <val>']']", _bind: get, _param_required: true },
-        CommandStruct { _name: "shit",       _usage: "only for testing.",                               _bind: shit,            _param_required: false },
-        CommandStruct { _name: "delete",     _usage: "delete [all, all where <key> is <val>]",          _bind: delete,          _param_required: true },
-        CommandStruct { _name: "update",     _usage: "update <objectid> <key:val>",                     _bind: update,          _param_required: true },
-        CommandStruct { _name: "collection", _usage: "collection ['switch <collection_name>', 'get']",  _bind: collection,      _param_required: true },
-        CommandStruct { _name: "database",   _usage: "database ['switch <database_name>', 'get']",      _bind: database,        _param_required: true },
+        CommandStruct {
+            _name: "help",
+            _summary: "show all commands, or the usage page of one",
+            _forms: &[Form { pattern: "<command>?", summary: "" }],
+            _examples: &["help", "help friends"],
+            _bind: Handler::Command(help),
+        },
+        CommandStruct {
+            _name: "tag",
+            _summary: "tags the input arrow",
+            _forms: &[Form { pattern: "<name>", summary: "" }],
+            _examples: &["tag prod"],
+            _bind: Handler::Session(crate::session::tag),
+        },
+        CommandStruct {
+            _name: "prev",
+            _summary: "run the previous command again",
+            _forms: &[Form { pattern: "", summary: "" }],
+            _examples: &[],
+            _bind: Handler::Session(crate::session::prev),
+        },
+        CommandStruct {
+            _name: "alias",
+            _summary: "short names for commands, saved in the profile config",
+            _forms: &[
+                Form { pattern: "list", summary: "" },
+                Form { pattern: "<name> = <command>...", summary: "params after the alias are appended to <command>" },
+            ],
+            _examples: &["alias gu = get all where role is", "gu user"],
+            _bind: Handler::Session(crate::session::alias),
+        },
+        CommandStruct {
+            _name: "unalias",
+            _summary: "remove an alias",
+            _forms: &[Form { pattern: "<name>", summary: "" }],
+            _examples: &["unalias gu"],
+            _bind: Handler::Session(crate::session::unalias),
+        },
+        CommandStruct {
+            _name: "let",
+            _summary: "keep the documents a command prints in a variable",
+            _forms: &[Form { pattern: "<name> = <command>...", summary: "use it in later commands as $name, $name[0] or $name.field" }],
+            _examples: &["let admin = get all where username is artur", "role revoke $admin.suid", "update $last[0]._id name:john"],
+            _bind: Handler::Session(crate::session::assign),
+        },
+        CommandStruct {
+            _name: "reset",
+            _summary: "clear all output - same as <clear>",
+            _forms: &[Form { pattern: "", summary: "" }],
+            _examples: &[],
+            _bind: Handler::Command(reset),
+        },
+        CommandStruct {
+            _name: "clear",
+            _summary: "clear all output - same as <reset>",
+            _forms: &[Form { pattern: "", summary: "" }],
+            _examples: &[],
+            _bind: Handler::Command(clear),
+        },
+        CommandStruct {
+            _name: "exit",
+            _summary: "exit the CLI",
+            _forms: &[Form { pattern: "", summary: "" }],
+            _examples: &[],
+            _bind: Handler::Session(crate::session::exit),
+        },
+        CommandStruct {
+            _name: "cmd",
+            _summary: "run a terminal command",
+            _forms: &[Form { pattern: "<terminal_command>...", summary: "" }],
+            _examples: &["cmd ls -la uploads"],
+            _bind: Handler::Command(cmd),
+        },
+        CommandStruct {
+            _name: "create",
+            _summary: "insert a document into the current collection",
+            _forms: &[
+                Form { pattern: "random", summary: "create a random account through the API" },
+                Form { pattern: "<field:keyval>...", summary: "insert a document with these fields" },
+            ],
+            _examples: &["create name:john last_name:doe", "create random"],
+            _bind: Handler::Command(create),
+        },
+        CommandStruct {
+            _name: "get",
+            _summary: "print documents of the current collection",
+            _forms: &[
+                Form { pattern: "all", summary: "every document" },
+                Form { pattern: "all where <key> is <val>", summary: "documents where <key> is <val>" },
+                Form { pattern: "length of all", summary: "the amount of documents" },
+                Form { pattern: "length of all where <key> is <val>", summary: "the amount of documents where <key> is <val>" },
+            ],
+            _examples: &["get all where role is admin", "get length of all"],
+            _bind: Handler::Command(get),
+        },
+        CommandStruct {
+            _name: "shit",
+            _summary: "only for testing.",
+            _forms: &[Form { pattern: "", summary: "" }],
+            _examples: &[],
+            _bind: Handler::Command(shit),
+        },
+        CommandStruct {
+            _name: "delete",
+            _summary: "delete documents of the current collection",
+            _forms: &[
+                Form { pattern: "all", summary: "every document" },
+                Form { pattern: "all where <key> is <val>", summary: "documents where <key> is <val>" },
+            ],
+            _examples: &["delete all where name is john"],
+            _bind: Handler::Command(delete),
+        },
+        CommandStruct {
+            _name: "update",
+            _summary: "set a field of a document",
+            _forms: &[Form { pattern: "<objectid:objectid> <field:keyval>", summary: "" }],
+            _examples: &["update 63b5c0f1d4a5e3a1b2c3d4e5 name:john"],
+            _bind: Handler::Command(update),
+        },
+        CommandStruct {
+            _name: "edit",
+            _summary: "edit documents as JSON in $EDITOR",
+            _forms: &[
+                Form { pattern: "<objectid:objectid>", summary: "" },
+                Form { pattern: "where <key> is <val>", summary: "" },
+                Form { pattern: "<filter_json>...", summary: "documents matching a JSON filter" },
+            ],
+            _examples: &["edit 63b5c0f1d4a5e3a1b2c3d4e5", "edit where username is artur", "edit {\"role\": \"admin\"}"],
+            _bind: Handler::Command(crate::edit::edit),
+        },
+        CommandStruct {
+            _name: "collection",
+            _summary: "switch the collection the commands work with",
+            _forms: &[
+                Form { pattern: "switch <collection_name>", summary: "" },
+                Form { pattern: "get", summary: "show the current collection" },
+            ],
+            _examples: &["collection switch users"],
+            _bind: Handler::Command(collection),
+        },
+        CommandStruct {
+            _name: "database",
+            _summary: "switch the database the commands work with",
+            _forms: &[
+                Form { pattern: "switch <database_name>", summary: "" },
+                Form { pattern: "get", summary: "show the current database" },
+            ],
+            _examples: &["database switch DockerMongo"],
+            _bind: Handler::Command(database),
+        },
+        CommandStruct {
+            _name: "role",
+            _summary: "manage user roles",
+            _forms: &[
+                Form { pattern: "grant <user> <role>", summary: "" },
+                Form { pattern: "revoke <user>", summary: "give the user the default role" },
+                Form { pattern: "list", summary: "every role and its amount of members" },
+                Form { pattern: "members <role>", summary: "" },
+            ],
+            _examples: &["role grant john moderator", "role members admin"],
+            _bind: Handler::Command(crate::roles::role),
+        },
+        CommandStruct {
+            _name: "friends",
+            _summary: "inspect and repair friend lists",
+            _forms: &[
+                Form { pattern: "list <user>", summary: "" },
+                Form { pattern: "add <a> <b> --mutual", summary: "--mutual also adds <a> to <b>" },
+                Form { pattern: "remove <a> <b> --mutual", summary: "--mutual also removes <a> from <b>" },
+                Form { pattern: "check", summary: "find dangling, duplicate and one-way friends" },
+                Form { pattern: "repair --unlink", summary: "fix what check finds, --unlink removes one-way friends instead of completing them" },
+            ],
+            _examples: &["friends add john jane --mutual", "friends repair"],
+            _bind: Handler::Command(crate::friends::friends),
+        },
+        CommandStruct {
+            _name: "graph",
+            _summary: "analyze the friend graph",
+            _forms: &[
+                Form { pattern: "stats", summary: "" },
+                Form { pattern: "export <file> --format <format:dot|graphml|csv-edges>", summary: "" },
+            ],
+            _examples: &["graph export friends.dot --format dot"],
+            _bind: Handler::Command(crate::graph::graph),
+        },
+        CommandStruct {
+            _name: "user",
+            _summary: "maintain user accounts",
+            _forms: &[
+                Form { pattern: "delete <user>", summary: "archive and delete the account" },
+                Form { pattern: "restore <archive_dir>", summary: "" },
+                Form { pattern: "rotate-suid <user>", summary: "" },
+                Form { pattern: "rewrite-profile-urls --check --from <old> --to <new>", summary: "" },
+                Form { pattern: "repair-joined --tz <zone:tz> --dry-run", summary: "" },
+            ],
+            _examples: &["user delete john", "user rewrite-profile-urls --check"],
+            _bind: Handler::Command(crate::users::user),
+        },
+        CommandStruct {
+            _name: "stats",
+            _summary: "signups over time",
+            _forms: &[Form { pattern: "users --since <since:date> --until <until:date> --by <by:day|week|month> --tz <zone:tz> --json", summary: "" }],
+            _examples: &["stats users --since 2024-01-01 --by week"],
+            _bind: Handler::Command(crate::stats::stats),
+        },
+        CommandStruct {
+            _name: "aggregate",
+            _summary: "run an aggregation pipeline on the current collection",
+            _forms: &[
+                Form { pattern: "group by <field> count", summary: "" },
+                Form { pattern: "distinct <field>", summary: "" },
+                Form { pattern: "top <n:int> <field>", summary: "the <n> most common values" },
+                Form { pattern: "lookup friends", summary: "resolve the friend suids" },
+                Form { pattern: "file <path>", summary: "read the pipeline from a JSON file" },
+                Form { pattern: "edit", summary: "write the pipeline in $EDITOR" },
+                Form { pattern: "<pipeline_json>...", summary: "" },
+            ],
+            _examples: &["aggregate group by role count", "aggregate [{\"$match\": {\"role\": \"admin\"}}]"],
+            _bind: Handler::Command(crate::aggregate::aggregate),
+        },
+        CommandStruct {
+            _name: "map",
+            _summary: "change every document of a collection with a script",
+            _forms: &[Form { pattern: "<collection> <script>... --dry-run", summary: "the script gets each document as <doc>, changed fields are written back" }],
+            _examples: &["map users { doc.email = doc.email.to_lowercase() }", "map users --dry-run { if doc.role == \"mod\" { doc.role = \"moderator\" } }"],
+            _bind: Handler::Session(crate::scripting::map),
+        },
+        CommandStruct {
+            _name: "script",
+            _summary: "run rhai scripts, the .acli files in scripts/ are commands too",
+            _forms: &[
+                Form { pattern: "list", summary: "the scripts that can be called by name" },
+                Form { pattern: "run <file> <args>...?", summary: "the params are in <args>" },
+                Form { pattern: "eval <code>...", summary: "" },
+            ],
+            _examples: &["script run cleanup.acli 30", "script eval { let admins = find(\"users\", #{ role: \"admin\" }); print(admins.len()) }"],
+            _bind: Handler::Session(crate::scripting::script),
+        },
+        CommandStruct {
+            _name: "seed",
+            _summary: "generate reproducible test users",
+            _forms: &[Form { pattern: "users <n:int> --seed <seed:int> --friends-avg <k:number> --roles <roles> --via <via:api|db> --password <pw> --since <since:date> --until <until:date>", summary: "" }],
+            _examples: &["seed users 100 --seed 42 --friends-avg 3 --roles admin:1,moderator:5"],
+            _bind: Handler::Command(crate::seed::seed),
+        },
+        CommandStruct {
+            _name: "api",
+            _summary: "call the API routes",
+            _forms: &[
+                Form { pattern: "create-account <email> <username> <displayname> <password>", summary: "" },
+                Form { pattern: "login <email> <password>", summary: "" },
+                Form { pattern: "profile-data <suid>", summary: "" },
+                Form { pattern: "profile-image <suid> <out_file>", summary: "" },
+                Form { pattern: "profile-upload <suid> <file>", summary: "" },
+                Form { pattern: "add-friend <suid> <friend_suid>", summary: "" },
+                Form { pattern: "get-friends-data <suid>", summary: "" },
+                Form { pattern: "selftest --junit <file>", summary: "check every route, writes a JUnit report" },
+            ],
+            _examples: &["api login john@doe.com password", "api selftest --junit report.xml"],
+            _bind: Handler::Command(crate::api::api),
+        },
+        CommandStruct {
+            _name: "mock-server",
+            _summary: "serve the API routes without the backend",
+            _forms: &[
+                Form { pattern: "start --port <port:port> --mongo --latency <ms|min-max> --error-rate <rate:rate> --malformed-rate <rate:rate> --replay <file.jsonl>", summary: "" },
+                Form { pattern: "stop", summary: "" },
+                Form { pattern: "status", summary: "" },
+            ],
+            _examples: &["mock-server start --latency 50-200 --error-rate 0.1"],
+            _bind: Handler::Command(crate::mock_server::mock_server),
+        },
+        CommandStruct {
+            _name: "bench",
+            _summary: "load and latency test an API route",
+            _forms: &[Form { pattern: "<route:create-account|login|profile-data|get-friends-data> --concurrency <n:int> --duration <duration:duration> --requests <m:int> --users <n:int> --json --out <file> --keep", summary: "" }],
+            _examples: &["bench login --concurrency 8 --duration 30s"],
+            _bind: Handler::Command(crate::bench::bench),
+        },
+        CommandStruct {
+            _name: "image",
+            _summary: "manage profile images",
+            _forms: &[
+                Form { pattern: "set <user> <file>", summary: "" },
+                Form { pattern: "show-path <user>", summary: "" },
+                Form { pattern: "reset <user>", summary: "" },
+                Form { pattern: "info <user>", summary: "" },
+                Form { pattern: "generate <user|all-missing>", summary: "generate an identicon" },
+                Form { pattern: "audit", summary: "" },
+                Form { pattern: "prune", summary: "" },
+            ],
+            _examples: &["image generate all-missing"],
+            _bind: Handler::Command(crate::images::image),
+        },
+        CommandStruct {
+            _name: "fields",
+            _summary: "(after a |) keep only these fields of every document",
+            _forms: &[Form { pattern: "<field>...", summary: "" }],
+            _examples: &["get all where role is user | fields email username"],
+            _bind: Handler::Stage(crate::pipeline::fields),
+        },
+        CommandStruct {
+            _name: "count",
+            _summary: "(after a |) the amount of documents or lines",
+            _forms: &[Form { pattern: "", summary: "" }],
+            _examples: &["get all | count"],
+            _bind: Handler::Stage(crate::pipeline::count),
+        },
+        CommandStruct {
+            _name: "export",
+            _summary: "(after a |) write the documents to a .csv, .json or .jsonl file",
+            _forms: &[Form { pattern: "<file>", summary: "" }],
+            _examples: &["get all where role is user | fields email | export users.csv"],
+            _bind: Handler::Stage(crate::pipeline::export),
+        },
+        CommandStruct {
+            _name: "sh",
+            _summary: "(after a |) send the output to a shell command, one JSON document per line",
+            _forms: &[Form { pattern: "<command>...", summary: "takes the rest of the line, including | ; and &&" }],
+            _examples: &["get all | fields role | sh sort | uniq -c"],
+            _bind: Handler::Stage(crate::pipeline::sh),
+        },
     ];
-}
\ No newline at end of file
+}
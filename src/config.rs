@@ -0,0 +1,198 @@
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::cell::RefCell;
+
+/*- Same env files as routes/Api.js, the state specific one is picked by APPLICATION_STATE -*/
+static GLOBAL_ENV_PATH: &str = "config/global.env";
+
+/*- Where the role definitions live, relative to the project root -*/
+static ROLES_PATH: &str = "config/roles.yml";
+
+/*- Shared with the API, contains the month and day names among others -*/
+static VARIABLES_PATH: &str = "data/variables.yml";
+
+/*- Shared with the API, contains the reserved usernames -*/
+static DICTIONARY_PATH: &str = "data/dict.yml";
+
+/*- Per user settings like aliases, in the home directory unless ACLI_PROFILE points elsewhere -*/
+static PROFILE_FILE: &str = ".account_api_cli.yml";
+
+/*- .acli scripts in here are commands named after the file, unless ACLI_SCRIPTS points elsewhere -*/
+static SCRIPTS_DIR: &str = "scripts";
+
+/*- Used when BACKEND_URL isn't set -*/
+static DEFAULT_BACKEND_URL: &str = "https://wss.artur.red";
+
+thread_local! {
+    /*- Set by the session running on this thread, the mock server points it at itself.
+        Changing BACKEND_URL itself isn't safe once threads are running -*/
+    static BACKEND_URL_OVERRIDE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/*- A single role as defined in roles.yml -*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct Role {
+    pub description: String,
+
+    /*- If granting this role requires a confirmation -*/
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/*- The whole roles.yml file -*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct RolesConfig {
+    pub default: String,
+    pub roles: BTreeMap<String, Role>,
+}
+
+/*- The parts of data/variables.yml that the CLI uses -*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct Variables {
+    pub username_len_max: usize,
+    pub username_len_min: usize,
+    pub months: Vec<String>,
+
+    /*- Monday first -*/
+    pub days: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct VariablesFile {
+    variables: Variables,
+}
+
+/*- Load the variables shared with the API -*/
+pub fn load_variables() -> Result<Variables, String> {
+    let file = std::fs::read_to_string(VARIABLES_PATH)
+        .map_err(|e| format!("Failed to read {}: {}", VARIABLES_PATH, e))?;
+
+    let variables = serde_yaml::from_str::<VariablesFile>(&file)
+        .map_err(|e| format!("Failed to parse {}: {}", VARIABLES_PATH, e))?
+        .variables;
+
+    if variables.months.len() != 12 || variables.days.len() != 7 {
+        return Err(format!("{} must contain 12 months and 7 days", VARIABLES_PATH));
+    }
+
+    return Ok(variables);
+}
+
+#[derive(Deserialize)]
+struct Dictionary {
+    reserved_usernames: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct DictionaryFile {
+    dictionary: Dictionary,
+}
+
+/*- Usernames that the API refuses to create -*/
+pub fn load_reserved_usernames() -> Result<Vec<String>, String> {
+    let file = std::fs::read_to_string(DICTIONARY_PATH)
+        .map_err(|e| format!("Failed to read {}: {}", DICTIONARY_PATH, e))?;
+
+    return serde_yaml::from_str::<DictionaryFile>(&file)
+        .map(|file| file.dictionary.reserved_usernames)
+        .map_err(|e| format!("Failed to parse {}: {}", DICTIONARY_PATH, e));
+}
+
+/*- Load and validate the role definitions -*/
+pub fn load_roles() -> Result<RolesConfig, String> {
+    let file = std::fs::read_to_string(ROLES_PATH)
+        .map_err(|e| format!("Failed to read {}: {}", ROLES_PATH, e))?;
+
+    let config:RolesConfig = serde_yaml::from_str(&file)
+        .map_err(|e| format!("Failed to parse {}: {}", ROLES_PATH, e))?;
+
+    /*- The default role must be one of the defined ones -*/
+    if !config.roles.contains_key(&config.default) {
+        return Err(format!("Default role <{}> is not defined in {}", config.default, ROLES_PATH));
+    }
+
+    return Ok(config);
+}
+
+/*- The profile config of the user running the CLI -*/
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Profile {
+    /*- alias name -> the command it expands to -*/
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+pub fn profile_path() -> PathBuf {
+    if let Ok(path) = std::env::var("ACLI_PROFILE") {
+        return PathBuf::from(path);
+    }
+    return home::home_dir().unwrap_or_default().join(PROFILE_FILE);
+}
+
+pub fn scripts_dir() -> PathBuf {
+    if let Ok(path) = std::env::var("ACLI_SCRIPTS") {
+        return PathBuf::from(path);
+    }
+    return PathBuf::from(SCRIPTS_DIR);
+}
+
+/*- A missing profile is an empty one -*/
+pub fn load_profile() -> Result<Profile, String> {
+    let path = profile_path();
+    let file = match std::fs::read_to_string(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Profile::default()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    return serde_yaml::from_str(&file).map_err(|e| format!("Failed to parse {}: {}", path.display(), e));
+}
+
+pub fn save_profile(profile:&Profile) -> Result<(), String> {
+    let path = profile_path();
+    let file = serde_yaml::to_string(profile).map_err(|e| format!("Failed to serialize the profile: {}", e))?;
+
+    return std::fs::write(&path, file).map_err(|e| format!("Failed to write {}: {}", path.display(), e));
+}
+
+/*- Load config/global.env and config/.env.<APPLICATION_STATE> into the environment -*/
+pub fn load_env() {
+    /*- Missing files are fine, the variables might already be set by docker -*/
+    dotenv::from_path(GLOBAL_ENV_PATH).ok();
+
+    if let Ok(state) = std::env::var("APPLICATION_STATE") {
+        dotenv::from_path(format!("config/.env.{}", state)).ok();
+    }
+}
+
+/*- The CDN url the API bakes into profile urls -*/
+pub fn cdn_url() -> Option<String> {
+    return std::env::var("CDN_URL").ok()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty());
+}
+
+/*- Timezone used when formatting dates, TIMEZONE in the env files or UTC -*/
+pub fn timezone() -> String {
+    return std::env::var("TIMEZONE").unwrap_or_else(|_| String::from("UTC"));
+}
+
+/*- Point the API commands somewhere other than BACKEND_URL, None goes back to it -*/
+pub fn set_backend_url(url:Option<String>) {
+    BACKEND_URL_OVERRIDE.with(|cell| *cell.borrow_mut() = url);
+}
+
+pub fn backend_url_override() -> Option<String> {
+    return BACKEND_URL_OVERRIDE.with(|cell| cell.borrow().clone());
+}
+
+/*- Where the Account-API is running -*/
+pub fn backend_url() -> String {
+    if let Some(url) = backend_url_override() {
+        return url;
+    }
+    return std::env::var("BACKEND_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| String::from(DEFAULT_BACKEND_URL));
+}
@@ -0,0 +1,60 @@
+use chrono::{ Datelike, TimeZone };
+use chrono_tz::Tz;
+use mongodb::bson::{ Bson, Document };
+use lazy_static::lazy_static;
+use crate::config::{ self, Variables };
+
+lazy_static! {
+    /*- Read once, every printed user needs them -*/
+    static ref DISPLAY_VARIABLES: Option<Variables> = config::load_variables().ok();
+}
+
+/*- Parse a timezone name like Europe/Stockholm -*/
+pub fn parse_timezone(name:&str) -> Result<Tz, String> {
+    return name.parse::<Tz>().map_err(|_| format!("Unknown timezone <{}>", name));
+}
+
+/*- Same format as getPrettifiedDate in routes/Api.js: "Wednesday, January 1 - 2020" -*/
+pub fn prettify(unix_ms:i64, tz:&Tz, variables:&Variables) -> Option<String> {
+    let date = tz.timestamp_millis_opt(unix_ms).single()?;
+
+    /*- The days array is Monday first -*/
+    let day = &variables.days[date.weekday().num_days_from_monday() as usize];
+    let month = &variables.months[date.month0() as usize];
+
+    return Some(format!("{}, {} {} - {}", day, month, date.day(), date.year()));
+}
+
+/*- joined.unix of a user, stored as a number by the API -*/
+pub fn joined_unix(user:&Document) -> Option<i64> {
+    return match user.get_document("joined").ok()?.get("unix")? {
+        Bson::Int64(unix) => Some(*unix),
+        Bson::Int32(unix) => Some(*unix as i64),
+        Bson::Double(unix) => Some(*unix as i64),
+        _ => None,
+    };
+}
+
+/*- The join date of a user for display. Always computed from joined.unix, never the stored string -*/
+pub fn format_joined(user:&Document, tz:&Tz, variables:&Variables) -> Option<String> {
+    return prettify(joined_unix(user)?, tz, variables);
+}
+
+/*- format_joined with the variables and the timezone from the config files -*/
+pub fn display_joined(user:&Document) -> Option<String> {
+    let tz = parse_timezone(&config::timezone()).unwrap_or(Tz::UTC);
+    return format_joined(user, &tz, DISPLAY_VARIABLES.as_ref()?);
+}
+
+/*- A user as it should be printed, joined.prettified is replaced by display_joined.
+    Documents without joined.unix are returned as they are -*/
+pub fn for_display(document:&Document) -> Document {
+    let mut document = document.clone();
+
+    if let Some(prettified) = display_joined(&document) {
+        if let Ok(joined) = document.get_document_mut("joined") {
+            joined.insert("prettified", prettified);
+        }
+    }
+    return document;
+}
@@ -0,0 +1,209 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Bson, Document };
+use mongodb::bson::oid::ObjectId;
+use similar::{ ChangeTag, TextDiff };
+use crate::{ commands, editor, output_handler, variables };
+
+/*- More than this is probably a filter that's too wide -*/
+static EDIT_LIMIT: usize = 50;
+
+/*- Removed lines of a diff. Not the error red, a diff isn't an error -*/
+static REMOVED: Color = Color::Rgb(224, 108, 117);
+
+/*- edit <objectid>, edit where <key> is <val> or edit <filter_json> -*/
+fn parse_filter(argv:&[String]) -> Result<Document, String> {
+    if argv.len() == 1 {
+        if let Ok(id) = argv[0].parse::<ObjectId>() { return Ok(doc! { "_id": id }); }
+    }
+    if let [keyword, key, is, value] = argv {
+        if keyword == "where" && is == "is" { return Ok(doc! { key: value }); }
+    }
+
+    let json:serde_json::Value = serde_json::from_str(&argv.join(" "))
+        .map_err(|e| format!("Invalid filter: {}", e))?;
+    return match Bson::try_from(json) {
+        Ok(Bson::Document(filter)) => Ok(filter),
+        Ok(_) => Err(String::from("The filter must be a JSON object")),
+        Err(e) => Err(format!("Invalid filter: {}", e)),
+    };
+}
+
+/*- Pretty relaxed extended JSON, one document as an object and more as an array -*/
+fn render(documents:&[Document]) -> String {
+    let json:Vec<serde_json::Value> = documents.iter()
+        .map(|document| Bson::Document(document.clone()).into_relaxed_extjson())
+        .collect();
+
+    let text = match json.as_slice() {
+        [document] => serde_json::to_string_pretty(document),
+        _ => serde_json::to_string_pretty(&json),
+    };
+    return text.unwrap_or_default() + "\n";
+}
+
+/*- What was saved in the editor, an object or an array of them -*/
+fn parse(text:&str) -> Result<Vec<Document>, String> {
+    let json:serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+
+    let documents = match Bson::try_from(json).map_err(|e| format!("Invalid extended JSON: {}", e))? {
+        Bson::Array(documents) => documents,
+        document => vec![document],
+    };
+    return documents.into_iter()
+        .map(|document| match document {
+            Bson::Document(document) => Ok(document),
+            other => Err(format!("{} is not a document", other)),
+        })
+        .collect();
+}
+
+/*- Same value once written as JSON, 5 from the editor is the same as the Int64 5 it was -*/
+fn same(a:&Bson, b:&Bson) -> bool {
+    return a.clone().into_relaxed_extjson() == b.clone().into_relaxed_extjson();
+}
+
+/*- Pair every edited document with its original by _id. Fields that weren't touched keep
+    their original value, so their types don't change by going through JSON -*/
+fn merge(originals:&[Document], edited:Vec<Document>) -> Result<Vec<(Document, Document)>, String> {
+    if edited.len() != originals.len() {
+        return Err(format!("There were {} document(s) and now there are {}, documents can only be edited here, not added or removed",
+            originals.len(), edited.len()
+        ));
+    }
+
+    let mut changes:Vec<(Document, Document)> = Vec::new();
+    let mut seen:Vec<usize> = Vec::new();
+    for document in edited {
+        let id = document.get("_id").ok_or("A document is missing its _id, it can't be changed")?;
+        let index = originals.iter()
+            .position(|original| original.get("_id").is_some_and(|original| same(original, id)))
+            .ok_or(format!("There is no document with _id {}, it can't be changed", variables::format_value(id)))?;
+        if seen.contains(&index) {
+            return Err(format!("The document with _id {} is in there twice", variables::format_value(id)));
+        }
+        seen.push(index);
+
+        let original = &originals[index];
+        let mut merged = Document::new();
+        for (key, value) in document {
+            match original.get(&key) {
+                Some(before) if same(before, &value) => merged.insert(key, before.clone()),
+                _ => merged.insert(key, value),
+            };
+        }
+
+        if &merged != original {
+            changes.push((original.clone(), merged));
+        }
+    }
+    return Ok(changes);
+}
+
+/*- Only the changed lines and a few around them -*/
+fn print_diff(before:&str, after:&str) {
+    let diff = TextDiff::from_lines(before, after);
+
+    for (index, group) in diff.grouped_ops(2).iter().enumerate() {
+        if index > 0 { output_handler::throw_res(Color::Cyan, "..."); }
+
+        for op in group {
+            for change in diff.iter_changes(op) {
+                let (sign, color) = match change.tag() {
+                    ChangeTag::Delete => ("-", REMOVED),
+                    ChangeTag::Insert => ("+", Color::Green),
+                    ChangeTag::Equal => (" ", Color::White),
+                };
+                output_handler::throw_res(color, format!("{}{}", sign, change.value().trim_end_matches('\n')).as_str());
+            }
+        }
+    }
+}
+
+/*- Edit documents of the current collection in $EDITOR -*/
+pub fn edit(argv:Vec<String>) {
+    let filter = match parse_filter(&argv) {
+        Ok(filter) => filter,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+
+    let coll = commands::current_collection();
+    let documents = match coll.find(filter, None) {
+        Ok(documents) => documents,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get documents: {}", e).as_str());
+            return;
+        }
+    };
+    if documents.is_empty() {
+        output_handler::throw_res(Color::Red, "Document not found!");
+        return;
+    }
+    if documents.len() > EDIT_LIMIT {
+        output_handler::throw_res(Color::Red, format!("{} documents matched, edit at most {} at a time", documents.len(), EDIT_LIMIT).as_str());
+        return;
+    }
+
+    /*- A typo shouldn't throw away the edits, open the editor again with them -*/
+    let mut text = render(&documents);
+    let changes = loop {
+        text = match editor::edit(&text, "json") {
+            Ok(text) => text,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        match parse(&text).and_then(|edited| merge(&documents, edited)) {
+            Ok(changes) => break changes,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                if !commands::confirm("Open the editor again?") { return; }
+            }
+        }
+    };
+
+    if changes.is_empty() {
+        output_handler::throw_res(Color::Cyan, "Nothing changed");
+        return;
+    }
+
+    for (original, changed) in &changes {
+        output_handler::throw_res(Color::Cyan, format!("_id {}", variables::format_value(original.get("_id").unwrap_or(&Bson::Null))).as_str());
+        print_diff(&render(std::slice::from_ref(original)), &render(std::slice::from_ref(changed)));
+    }
+
+    if !commands::confirm(format!("Save {} changed document(s)?", changes.len()).as_str()) { return; }
+
+    /*- The whole original document is the filter, so nothing that changed since it was opened gets overwritten -*/
+    let mut stale:Vec<String> = Vec::new();
+    for (original, _) in &changes {
+        match coll.count(original.clone()) {
+            Ok(0) => stale.push(variables::format_value(original.get("_id").unwrap_or(&Bson::Null))),
+            Ok(_) => {},
+            Err(e) => {
+                output_handler::throw_res(Color::Red, format!("Failed to check the documents: {}", e).as_str());
+                return;
+            }
+        }
+    }
+    if !stale.is_empty() {
+        output_handler::throw_res(Color::Red, format!("Changed since they were opened: {}. Nothing was saved, run <edit> again to edit the current version", stale.join(", ")).as_str());
+        return;
+    }
+
+    let mut saved = 0;
+    for (original, changed) in changes {
+        let id = variables::format_value(original.get("_id").unwrap_or(&Bson::Null));
+        match coll.replace_one(original, changed) {
+            Ok(count) if count.matched == 1 => saved += 1,
+            Ok(_) => output_handler::throw_res(Color::Red, format!("{} changed while saving, it was not saved", id).as_str()),
+            Err(e) => output_handler::throw_res(Color::Red, format!("Failed to save {}: {}", id, e).as_str()),
+        }
+    }
+    output_handler::throw_res(Color::Cyan, format!("Saved {} document(s)", saved).as_str());
+}
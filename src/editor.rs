@@ -0,0 +1,36 @@
+use std::process::Command;
+
+/*- Let the user edit some text in $EDITOR and return the result -*/
+pub fn edit(initial:&str, extension:&str) -> Result<String, String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+
+    /*- Unique per process and call, so that two CLIs don't share a file -*/
+    let path = std::env::temp_dir().join(format!(
+        "acli-{}-{}.{}",
+        std::process::id(),
+        mongodb::bson::DateTime::now().timestamp_millis(),
+        extension
+    ));
+    std::fs::write(&path, initial)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    /*- Go through sh so that editors with arguments like "code --wait" work -*/
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status();
+
+    let result = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        Ok(status) => Err(format!("{} exited with {}", editor, status)),
+        Err(e) => Err(format!("Failed to start {}: {}", editor, e)),
+    };
+
+    std::fs::remove_file(&path).ok();
+    return result;
+}
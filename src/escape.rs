@@ -0,0 +1,27 @@
+/*- Escaping for the file formats the CLI writes -*/
+
+/*- Escape a string for xml attributes and text -*/
+pub fn xml(input:&str) -> String {
+    return input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;");
+}
+
+/*- Quote a CSV value if it needs it -*/
+pub fn csv(value:&str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    }
+    return value.to_string();
+}
+
+/*- The inside of a quoted DOT string, backslashes first so the added ones aren't doubled -*/
+pub fn dot(input:&str) -> String {
+    return input
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
@@ -0,0 +1,294 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Document, oid::ObjectId };
+use std::collections::{ HashMap, HashSet };
+use crate::{ audit, commands, output_handler, users };
+use crate::store::DocumentStore;
+
+/*- A user in the friend graph, the friends are stored as suids -*/
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: ObjectId,
+    pub suid: String,
+    pub username: String,
+    pub friends: Vec<String>,
+}
+
+/*- Everything that can be wrong with the friend arrays -*/
+#[derive(Debug, Default)]
+struct Issues {
+    /*- (node index, suid that doesn't exist) -*/
+    dangling: Vec<(usize, String)>,
+
+    /*- Node indexes that have themselves as a friend -*/
+    self_links: Vec<usize>,
+
+    /*- (node index, suid, occurrences) -*/
+    duplicates: Vec<(usize, String, usize)>,
+
+    /*- (from, to) where <to> doesn't have <from> as a friend -*/
+    one_way: Vec<(usize, usize)>,
+}
+
+impl Issues {
+    fn len(&self) -> usize {
+        return self.dangling.len() + self.self_links.len() + self.duplicates.len() + self.one_way.len();
+    }
+}
+
+/*- Load every user that has a suid into the graph -*/
+pub fn load_nodes(coll:&dyn DocumentStore) -> Result<Vec<Node>, String> {
+    let cursor = coll.find(doc! { "suid": { "$exists": true } }, None)
+        .map_err(|e| format!("Failed to get users: {}", e))?;
+
+    let mut nodes:Vec<Node> = Vec::new();
+    for user in cursor {
+        let id = match user.get_object_id("_id") {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+
+        /*- Anything in the friends array that isn't a string can't be a suid -*/
+        let friends = user.get_array("friends")
+            .map(|arr| arr.iter().filter_map(|f| f.as_str().map(|s| s.to_string())).collect())
+            .unwrap_or_default();
+
+        nodes.push(Node {
+            id,
+            suid: user.get_str("suid").unwrap_or("").to_string(),
+            username: user.get_str("username").unwrap_or("<no username>").to_string(),
+            friends,
+        });
+    }
+
+    return Ok(nodes);
+}
+
+/*- Map suid -> node index -*/
+pub fn index_nodes(nodes:&[Node]) -> HashMap<String, usize> {
+    return nodes.iter().enumerate().map(|(i, n)| (n.suid.clone(), i)).collect();
+}
+
+/*- Walk every friend array and collect the problems -*/
+fn find_issues(nodes:&[Node]) -> Issues {
+    let index = index_nodes(nodes);
+    let mut issues = Issues::default();
+
+    for (i, node) in nodes.iter().enumerate() {
+        let mut seen:HashMap<&String, usize> = HashMap::new();
+
+        for friend in &node.friends {
+            *seen.entry(friend).or_insert(0) += 1;
+
+            /*- Only report every problem once per array -*/
+            if seen[friend] > 1 { continue; }
+
+            if friend == &node.suid {
+                issues.self_links.push(i);
+                continue;
+            }
+
+            match index.get(friend) {
+                Some(&j) => {
+                    if !nodes[j].friends.contains(&node.suid) {
+                        issues.one_way.push((i, j));
+                    }
+                },
+                None => issues.dangling.push((i, friend.clone())),
+            }
+        }
+
+        for (friend, count) in seen {
+            if count > 1 {
+                issues.duplicates.push((i, friend.clone(), count));
+            }
+        }
+    }
+
+    return issues;
+}
+
+/*- Print all issues -*/
+fn print_issues(nodes:&[Node], issues:&Issues) {
+    for (i, suid) in &issues.dangling {
+        output_handler::throw_res(Color::Green, format!("dangling   {} -> {} (no such user)", nodes[*i].username, suid).as_str());
+    }
+    for i in &issues.self_links {
+        output_handler::throw_res(Color::Green, format!("self       {} is friends with themselves", nodes[*i].username).as_str());
+    }
+    for (i, suid, count) in &issues.duplicates {
+        output_handler::throw_res(Color::Green, format!("duplicate  {} has {} {} times", nodes[*i].username, suid, count).as_str());
+    }
+    for (from, to) in &issues.one_way {
+        output_handler::throw_res(Color::Green, format!("one-way    {} -> {}", nodes[*from].username, nodes[*to].username).as_str());
+    }
+}
+
+/*- Compute the repaired friend arrays. One-way links are completed, or removed if unlink is true -*/
+fn repaired_friends(nodes:&[Node], issues:&Issues, unlink:bool) -> Vec<Vec<String>> {
+    let index = index_nodes(nodes);
+
+    /*- Drop self links, dangling suids and duplicates -*/
+    let mut fixed:Vec<Vec<String>> = nodes.iter().map(|node| {
+        let mut seen:HashSet<&String> = HashSet::new();
+        node.friends.iter()
+            .filter(|f| *f != &node.suid && index.contains_key(*f) && seen.insert(f))
+            .cloned()
+            .collect()
+    }).collect();
+
+    for (from, to) in &issues.one_way {
+        if unlink {
+            let suid = &nodes[*to].suid;
+            fixed[*from].retain(|f| f != suid);
+        }else if !fixed[*to].contains(&nodes[*from].suid) {
+            fixed[*to].push(nodes[*from].suid.clone());
+        }
+    }
+
+    return fixed;
+}
+
+/*- Inspect and repair the friend graph -*/
+pub fn friends(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let mutual = commands::take_flag(&mut argv, "--mutual");
+    let unlink = commands::take_flag(&mut argv, "--unlink");
+
+    let coll = users::users_collection();
+
+    if &argv[0] == "list" && argv.len() == 2 {
+        let user = match users::find_user(&*coll, &argv[1]) {
+            Ok(user) => user,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+        let suid = user.get_str("suid").unwrap_or("");
+
+        let friends:Vec<String> = user.get_array("friends")
+            .map(|arr| arr.iter().filter_map(|f| f.as_str().map(|s| s.to_string())).collect())
+            .unwrap_or_default();
+
+        if friends.is_empty() {
+            output_handler::throw_res(Color::Cyan, format!("{} has no friends", users::describe(&user)).as_str());
+            return;
+        }
+
+        for friend_suid in friends {
+            match coll.find_one(doc! { "suid": &friend_suid }).ok().flatten() {
+                Some(friend) => {
+                    /*- Show if the friendship goes both ways -*/
+                    let mutual = friend.get_array("friends")
+                        .map(|arr| arr.iter().any(|f| f.as_str() == Some(suid)))
+                        .unwrap_or(false);
+
+                    output_handler::throw_res(Color::Green,
+                        format!("{} {}", users::describe(&friend), if mutual { "" } else { "(one-way)" }).as_str()
+                    );
+                },
+                None => output_handler::throw_res(Color::Yellow, format!("{} (no such user)", friend_suid).as_str()),
+            }
+        }
+    }
+    else if (&argv[0] == "add" || &argv[0] == "remove") && argv.len() == 3 {
+        let a = users::find_user(&*coll, &argv[1]);
+        let b = users::find_user(&*coll, &argv[2]);
+
+        let (a, b) = match (a, b) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(e), _) | (_, Err(e)) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        let a_suid = a.get_str("suid").unwrap_or("").to_string();
+        let b_suid = b.get_str("suid").unwrap_or("").to_string();
+
+        if a_suid == b_suid {
+            output_handler::throw_res(Color::Red, "A user can't be friends with themselves!");
+            return;
+        }
+
+        /*- Add uses $addToSet so that we never create duplicates -*/
+        let update = |suid:&str| -> Document {
+            if &argv[0] == "add" { doc! { "$addToSet": { "friends": suid } } }
+            else { doc! { "$pull": { "friends": suid } } }
+        };
+
+        let mut pairs = vec![(&a_suid, &b_suid)];
+        if mutual { pairs.push((&b_suid, &a_suid)); }
+
+        for (user, friend) in pairs {
+            if let Err(e) = coll.update_one(doc! { "suid": user }, update(friend), None) {
+                output_handler::throw_res(Color::Red, format!("Failed to update friends: {}", e).as_str());
+                return;
+            }
+        }
+
+        audit::record(format!("friends.{}", &argv[0]).as_str(), &a_suid, doc! { "friend": &b_suid, "mutual": mutual });
+
+        output_handler::throw_res(Color::Green, format!("Friends updated{}!", if mutual { " (mutual)" } else { "" }).as_str());
+    }
+    else if &argv[0] == "check" || &argv[0] == "repair" {
+        let nodes = match load_nodes(&*coll) {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        let issues = find_issues(&nodes);
+        if issues.len() == 0 {
+            output_handler::throw_res(Color::Green, format!("No issues found in {} users", nodes.len()).as_str());
+            return;
+        }
+
+        print_issues(&nodes, &issues);
+        output_handler::throw_res(Color::Cyan, format!(
+            "{} dangling, {} self, {} duplicate, {} one-way",
+            issues.dangling.len(), issues.self_links.len(), issues.duplicates.len(), issues.one_way.len()
+        ).as_str());
+
+        if &argv[0] == "check" { return; }
+
+        let question = match unlink {
+            true => "Repair these issues? One-way links will be removed.",
+            false => "Repair these issues? One-way links will be made mutual.",
+        };
+        if !commands::confirm(question) { return; }
+
+        let fixed = repaired_friends(&nodes, &issues, unlink);
+        let mut updated = 0;
+
+        for (node, friends) in nodes.iter().zip(fixed) {
+            if node.friends == friends { continue; }
+
+            if let Err(e) = coll.update_one(doc! { "_id": node.id }, doc! { "$set": { "friends": &friends } }, None) {
+                output_handler::throw_res(Color::Red, format!("Failed to update {}: {}", node.username, e).as_str());
+                continue;
+            }
+            updated += 1;
+        }
+
+        audit::record("friends.repair", "", doc! {
+            "dangling": issues.dangling.len() as i64,
+            "self": issues.self_links.len() as i64,
+            "duplicates": issues.duplicates.len() as i64,
+            "one_way": issues.one_way.len() as i64,
+            "unlink": unlink,
+            "updated": updated,
+        });
+
+        output_handler::throw_res(Color::Green, format!("{} user(s) repaired!", updated).as_str());
+    }
+    else {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help friends> for further information.");
+    }
+}
@@ -0,0 +1,248 @@
+use termcolor::{ Color };
+use std::collections::{ BTreeMap, HashSet };
+use std::fmt::Write as FmtWrite;
+use crate::{ commands, escape, friends, output_handler, users };
+use crate::friends::Node;
+
+/*- How many users to show in "most connected" and similar listings -*/
+static TOP_AMOUNT: usize = 10;
+
+/*- The friend arrays as an undirected graph, plus the directed edges they came from -*/
+struct Graph {
+    nodes: Vec<Node>,
+
+    /*- Unique (from, to) pairs that point to existing users -*/
+    directed: Vec<(usize, usize)>,
+
+    /*- Undirected neighbours of every node -*/
+    neighbours: Vec<HashSet<usize>>,
+}
+
+impl Graph {
+    fn new(nodes:Vec<Node>) -> Graph {
+        let index = friends::index_nodes(&nodes);
+        let mut directed:HashSet<(usize, usize)> = HashSet::new();
+        let mut neighbours:Vec<HashSet<usize>> = vec![HashSet::new(); nodes.len()];
+
+        for (i, node) in nodes.iter().enumerate() {
+            for friend in &node.friends {
+                /*- Dangling suids and self links aren't edges -*/
+                if let Some(&j) = index.get(friend) {
+                    if i == j { continue; }
+
+                    directed.insert((i, j));
+                    neighbours[i].insert(j);
+                    neighbours[j].insert(i);
+                }
+            }
+        }
+
+        let mut directed:Vec<(usize, usize)> = directed.into_iter().collect();
+        directed.sort();
+
+        return Graph { nodes, directed, neighbours };
+    }
+
+    fn is_mutual(&self, from:usize, to:usize) -> bool {
+        return self.directed.binary_search(&(to, from)).is_ok();
+    }
+
+    /*- Connected components, largest first -*/
+    fn components(&self) -> Vec<Vec<usize>> {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut components:Vec<Vec<usize>> = Vec::new();
+
+        for start in 0..self.nodes.len() {
+            if visited[start] { continue; }
+
+            /*- Depth first search from every unvisited node -*/
+            let mut component = Vec::new();
+            let mut stack = vec![start];
+            visited[start] = true;
+
+            while let Some(node) = stack.pop() {
+                component.push(node);
+                for &next in &self.neighbours[node] {
+                    if !visited[next] {
+                        visited[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+
+            components.push(component);
+        }
+
+        components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        return components;
+    }
+}
+
+/*- Print all statistics about the friend graph -*/
+fn stats(graph:&Graph) {
+    let mutual_links = graph.directed.iter().filter(|(a, b)| graph.is_mutual(*a, *b)).count();
+    let undirected_edges:usize = graph.neighbours.iter().map(|n| n.len()).sum::<usize>() / 2;
+
+    output_handler::throw_res(Color::Green, format!("Users:                   {}", graph.nodes.len()).as_str());
+    output_handler::throw_res(Color::Green, format!("Friend links (directed): {}", graph.directed.len()).as_str());
+    output_handler::throw_res(Color::Green, format!("Friendships:             {}", undirected_edges).as_str());
+    output_handler::throw_res(Color::Green, format!("Mutual friendships:      {}", mutual_links / 2).as_str());
+    output_handler::throw_res(Color::Green, format!("One-way links:           {}", graph.directed.len() - mutual_links).as_str());
+
+    /*- Degree distribution -*/
+    let mut distribution:BTreeMap<usize, usize> = BTreeMap::new();
+    for neighbours in &graph.neighbours {
+        *distribution.entry(neighbours.len()).or_insert(0) += 1;
+    }
+
+    output_handler::throw_res(Color::Cyan, "Degree distribution (friends: users)");
+    let rows:Vec<(String, usize)> = distribution.iter().map(|(degree, amount)| (degree.to_string(), *amount)).collect();
+    output_handler::throw_histogram(&rows);
+
+    /*- Isolated users -*/
+    let isolated:Vec<&Node> = graph.nodes.iter().enumerate()
+        .filter(|(i, _)| graph.neighbours[*i].is_empty())
+        .map(|(_, n)| n)
+        .collect();
+    output_handler::throw_res(Color::Cyan, format!("Isolated users: {}", isolated.len()).as_str());
+    for node in isolated.iter().take(TOP_AMOUNT) {
+        output_handler::throw_res(Color::Green, format!("  {} ({})", node.username, node.suid).as_str());
+    }
+    if isolated.len() > TOP_AMOUNT {
+        output_handler::throw_res(Color::Green, format!("  ...and {} more", isolated.len() - TOP_AMOUNT).as_str());
+    }
+
+    /*- Connected components, isolated users count as their own component -*/
+    let components = graph.components();
+    let sizes:Vec<String> = components.iter().take(TOP_AMOUNT).map(|c| c.len().to_string()).collect();
+    output_handler::throw_res(Color::Cyan, format!("Connected components: {}", components.len()).as_str());
+    output_handler::throw_res(Color::Green, format!("  Largest sizes: {}", sizes.join(", ")).as_str());
+
+    /*- Most connected users -*/
+    let mut by_degree:Vec<usize> = (0..graph.nodes.len()).collect();
+    by_degree.sort_by_key(|&i| std::cmp::Reverse(graph.neighbours[i].len()));
+
+    output_handler::throw_res(Color::Cyan, "Most connected users (friends / mutual)");
+    for &i in by_degree.iter().take(TOP_AMOUNT) {
+        let mutual = graph.neighbours[i].iter().filter(|&&j| graph.is_mutual(i, j)).count();
+        output_handler::throw_res(Color::Green,
+            format!("  {:<20} {:>4} / {}", graph.nodes[i].username, graph.neighbours[i].len(), mutual).as_str()
+        );
+    }
+
+    /*- Pairs of friends with the most friends in common -*/
+    let mut common:Vec<(usize, usize, usize)> = Vec::new();
+    for (i, neighbours) in graph.neighbours.iter().enumerate() {
+        for &j in neighbours {
+            if j <= i { continue; }
+            let amount = neighbours.intersection(&graph.neighbours[j]).count();
+            if amount > 0 { common.push((i, j, amount)); }
+        }
+    }
+    common.sort_by_key(|c| std::cmp::Reverse(c.2));
+
+    output_handler::throw_res(Color::Cyan, "Friends with the most mutual friends");
+    for (i, j, amount) in common.iter().take(TOP_AMOUNT) {
+        output_handler::throw_res(Color::Green,
+            format!("  {} & {}: {}", graph.nodes[*i].username, graph.nodes[*j].username, amount).as_str()
+        );
+    }
+}
+
+/*- Render the graph in one of the supported formats -*/
+fn export(graph:&Graph, format:&str) -> Result<String, String> {
+    let mut out = String::new();
+
+    match format {
+        "dot" => {
+            writeln!(out, "digraph friends {{").unwrap();
+            for node in &graph.nodes {
+                writeln!(out, "    \"{}\" [label=\"{}\"];", escape::dot(&node.suid), escape::dot(&node.username)).unwrap();
+            }
+            for (a, b) in &graph.directed {
+                writeln!(out, "    \"{}\" -> \"{}\";", escape::dot(&graph.nodes[*a].suid), escape::dot(&graph.nodes[*b].suid)).unwrap();
+            }
+            writeln!(out, "}}").unwrap();
+        },
+        "graphml" => {
+            writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+            writeln!(out, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">").unwrap();
+            writeln!(out, "  <key id=\"username\" for=\"node\" attr.name=\"username\" attr.type=\"string\"/>").unwrap();
+            writeln!(out, "  <key id=\"mutual\" for=\"edge\" attr.name=\"mutual\" attr.type=\"boolean\"/>").unwrap();
+            writeln!(out, "  <graph id=\"friends\" edgedefault=\"directed\">").unwrap();
+            for node in &graph.nodes {
+                writeln!(out, "    <node id=\"{}\"><data key=\"username\">{}</data></node>", escape::xml(&node.suid), escape::xml(&node.username)).unwrap();
+            }
+            for (a, b) in &graph.directed {
+                writeln!(out, "    <edge source=\"{}\" target=\"{}\"><data key=\"mutual\">{}</data></edge>",
+                    escape::xml(&graph.nodes[*a].suid), escape::xml(&graph.nodes[*b].suid), graph.is_mutual(*a, *b)
+                ).unwrap();
+            }
+            writeln!(out, "  </graph>").unwrap();
+            writeln!(out, "</graphml>").unwrap();
+        },
+        "csv-edges" => {
+            writeln!(out, "source,target,source_username,target_username,mutual").unwrap();
+            for (a, b) in &graph.directed {
+                let (from, to) = (&graph.nodes[*a], &graph.nodes[*b]);
+                writeln!(out, "{},{},{},{},{}",
+                    escape::csv(&from.suid), escape::csv(&to.suid), escape::csv(&from.username), escape::csv(&to.username), graph.is_mutual(*a, *b)
+                ).unwrap();
+            }
+        },
+        _ => return Err(format!("Unknown format <{}>, use dot, graphml or csv-edges", format)),
+    }
+
+    return Ok(out);
+}
+
+/*- Friend graph analytics and export -*/
+pub fn graph(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let format = commands::take_option(&mut argv, "--format");
+
+    let nodes = match friends::load_nodes(&*users::users_collection()) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let graph = Graph::new(nodes);
+
+    if &argv[0] == "stats" && argv.len() == 1 {
+        stats(&graph);
+    }
+    else if &argv[0] == "export" && argv.len() == 2 {
+        let file = &argv[1];
+
+        /*- Guess the format from the file extension if it wasn't specified -*/
+        let format = format.unwrap_or_else(|| {
+            if file.ends_with(".graphml") { String::from("graphml") }
+            else if file.ends_with(".csv") { String::from("csv-edges") }
+            else { String::from("dot") }
+        });
+
+        let output = match export(&graph, &format) {
+            Ok(output) => output,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        match std::fs::write(file, output) {
+            Ok(_) => output_handler::throw_res(Color::Green,
+                format!("Exported {} users and {} links to {}", graph.nodes.len(), graph.directed.len(), file).as_str()
+            ),
+            Err(e) => output_handler::throw_res(Color::Red, format!("Failed to write {}: {}", file, e).as_str()),
+        }
+    }
+    else {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help graph> for further information.");
+    }
+}
@@ -0,0 +1,63 @@
+use image::{ DynamicImage, Rgb, RgbImage };
+
+/*- The identicon is a 5x5 grid, mirrored around the middle column -*/
+static GRID: u32 = 5;
+static CELL: u32 = 44;
+static SIZE: u32 = 256;
+static BACKGROUND: Rgb<u8> = Rgb([240, 240, 240]);
+
+/*- 64 bit FNV-1a, stable across Rust versions unlike the std hasher -*/
+fn fnv1a(input:&[u8], seed:u64) -> u64 {
+    let mut hash:u64 = 0xcbf29ce484222325 ^ seed;
+    for byte in input {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+/*- Convert a hue (0-360) with fixed saturation and lightness to rgb -*/
+fn hue_to_rgb(hue:f32) -> Rgb<u8> {
+    let (saturation, lightness) = (0.55_f32, 0.5_f32);
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let m = lightness - chroma / 2.0;
+
+    let (r, g, b) = match hue as u32 {
+        0..=59 => (chroma, x, 0.0),
+        60..=119 => (x, chroma, 0.0),
+        120..=179 => (0.0, chroma, x),
+        180..=239 => (0.0, x, chroma),
+        240..=299 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    return Rgb([((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8]);
+}
+
+/*- Render a 256x256 identicon, the same suid always gives the same image -*/
+pub fn render(suid:&str) -> DynamicImage {
+    let pattern = fnv1a(suid.as_bytes(), 0);
+    let color = hue_to_rgb((fnv1a(suid.as_bytes(), 1) % 360) as f32);
+
+    let margin = (SIZE - GRID * CELL) / 2;
+    let mut image = RgbImage::from_pixel(SIZE, SIZE, BACKGROUND);
+
+    for row in 0..GRID {
+        /*- Only the left half and the middle column are random, the rest is mirrored -*/
+        for col in 0..GRID.div_ceil(2) {
+            if pattern >> (row * 3 + col) & 1 == 0 { continue; }
+
+            for mirrored in [col, GRID - 1 - col] {
+                let (x0, y0) = (margin + mirrored * CELL, margin + row * CELL);
+                for y in y0..y0 + CELL {
+                    for x in x0..x0 + CELL {
+                        image.put_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+    }
+
+    return DynamicImage::ImageRgb8(image);
+}
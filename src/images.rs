@@ -0,0 +1,385 @@
+use termcolor::{ Color };
+use image::{ DynamicImage, ImageDecoder, ImageFormat, ImageReader, imageops::FilterType, codecs::jpeg::JpegEncoder };
+use std::collections::HashSet;
+use std::path::Path;
+use crate::{ audit, commands, identicon, output_handler, store, users };
+use mongodb::bson::doc;
+
+/*- The API serves this image when a user hasn't uploaded one -*/
+pub static DEFAULT_IMAGE: &str = "data/images/default-user.jpg";
+
+/*- Same values as the gm pipeline in /api/profile-upload -*/
+pub static PROFILE_SIZE: u32 = 256;
+pub static PROFILE_QUALITY: u8 = 50;
+
+/*- The in-memory users aren't the ones the files in uploads/ belong to, so nothing there
+    may be changed with --memory. Returns true if <what> was refused -*/
+pub fn refuse_in_memory(what:&str) -> bool {
+    if !store::is_memory() { return false; }
+
+    output_handler::throw_res(Color::Red, format!("{} changes files in {}, that isn't allowed with --memory", what, users::PROFILE_DIR).as_str());
+    return true;
+}
+
+/*- Open an image and rotate it according to its EXIF orientation -*/
+pub fn open_oriented(path:&Path) -> Result<DynamicImage, String> {
+    let mut decoder = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+        .into_decoder()
+        .map_err(|e| format!("Failed to decode {}: {}", path.display(), e))?;
+
+    let orientation = decoder.orientation()
+        .map_err(|e| format!("Failed to read orientation of {}: {}", path.display(), e))?;
+
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|e| format!("Failed to decode {}: {}", path.display(), e))?;
+    image.apply_orientation(orientation);
+
+    return Ok(image);
+}
+
+/*- Resize and compress an image like the API does. Re-encoding drops all EXIF data -*/
+pub fn encode_profile_image(image:&DynamicImage) -> Result<Vec<u8>, String> {
+    /*- Keeps the aspect ratio, same as gm's resize -*/
+    let resized = image.resize(PROFILE_SIZE, PROFILE_SIZE, FilterType::Lanczos3).to_rgb8();
+
+    let mut bytes:Vec<u8> = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, PROFILE_QUALITY)
+        .encode_image(&resized)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    return Ok(bytes);
+}
+
+/*- Write an already encoded profile image for a suid -*/
+pub fn write_profile_image(suid:&str, bytes:&[u8]) -> Result<(), String> {
+    let path = users::profile_image_path(suid);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+
+    return std::fs::write(&path, bytes)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e));
+}
+
+/*- Print information about the image that will be served for a suid -*/
+fn info(suid:&str) {
+    let path = users::profile_image_path(suid);
+
+    if !path.is_file() {
+        output_handler::throw_res(Color::Green, format!("No profile image, the API serves {}", DEFAULT_IMAGE).as_str());
+        return;
+    }
+
+    output_handler::throw_res(Color::Green, format!("Path:       {}", path.display()).as_str());
+
+    if let Ok(metadata) = std::fs::metadata(&path) {
+        output_handler::throw_res(Color::Green, format!("Size:       {} bytes", metadata.len()).as_str());
+    }
+
+    match ImageReader::open(&path).and_then(|reader| reader.with_guessed_format()) {
+        Ok(reader) => {
+            let format = reader.format().map(|f| format!("{:?}", f)).unwrap_or(String::from("unknown"));
+            output_handler::throw_res(Color::Green, format!("Format:     {}", format).as_str());
+
+            match reader.into_dimensions() {
+                Ok((width, height)) => output_handler::throw_res(Color::Green, format!("Dimensions: {}x{}", width, height).as_str()),
+                Err(e) => output_handler::throw_res(Color::Yellow, format!("Dimensions: unreadable ({})", e).as_str()),
+            }
+        },
+        Err(e) => output_handler::throw_res(Color::Red, format!("Failed to read image: {}", e).as_str()),
+    }
+}
+
+/*- Everything that can be wrong with a single file in the uploads directory -*/
+#[derive(Debug, Default)]
+struct FileReport {
+    size: u64,
+    orphan: bool,
+    unexpected_name: bool,
+    invalid: Option<String>,
+    oversized: Option<(u32, u32)>,
+    exif: bool,
+}
+
+/*- Inspect a single uploaded image -*/
+fn inspect_file(path:&Path, report:&mut FileReport) {
+    let mut decoder = match ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| e.to_string())
+        .and_then(|reader| match reader.format() {
+            Some(ImageFormat::Jpeg) => reader.into_decoder().map_err(|e| e.to_string()),
+            Some(format) => Err(format!("{:?} instead of JPEG", format)),
+            None => Err(String::from("unknown format")),
+        }) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            report.invalid = Some(e);
+            return;
+        }
+    };
+
+    let (width, height) = decoder.dimensions();
+    if width > PROFILE_SIZE || height > PROFILE_SIZE {
+        report.oversized = Some((width, height));
+    }
+
+    report.exif = matches!(decoder.exif_metadata(), Ok(Some(_)));
+
+    /*- Decode the whole image to catch truncated files -*/
+    if let Err(e) = DynamicImage::from_decoder(decoder) {
+        report.invalid = Some(e.to_string());
+    }
+}
+
+/*- Walk the uploads directory and report what's wrong, returns the orphaned files -*/
+fn audit_uploads() -> Result<Vec<std::path::PathBuf>, String> {
+    let coll = users::users_collection();
+
+    /*- Every suid that exists, and the profile urls that don't point to their own image -*/
+    let mut suids:HashSet<String> = HashSet::new();
+    let mut unexpected_profiles:Vec<String> = Vec::new();
+    let cursor = coll.find(doc! {}, None).map_err(|e| format!("Failed to get users: {}", e))?;
+    for user in cursor {
+        let suid = user.get_str("suid").unwrap_or("").to_string();
+        let profile = user.get_str("profile").unwrap_or("");
+
+        /*- Compare against the current CDN_URL if there is one -*/
+        let expected = users::expected_profile_url(&suid).unwrap_or(format!("/api/profile-data/image/{}", suid));
+        if !profile.ends_with(&expected) {
+            unexpected_profiles.push(format!("{} -> {:?}", users::describe(&user), profile));
+        }
+        suids.insert(suid);
+    }
+
+    let entries = std::fs::read_dir(users::PROFILE_DIR)
+        .map_err(|e| format!("Failed to read {}: {}", users::PROFILE_DIR, e))?;
+
+    let mut reports:Vec<(std::path::PathBuf, FileReport)> = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_file() { continue; }
+
+        let mut report = FileReport {
+            size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+            ..Default::default()
+        };
+
+        /*- Files must be named <suid>.jpg -*/
+        match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) {
+            (Some(suid), Some("jpg")) => report.orphan = !suids.contains(suid),
+            _ => report.unexpected_name = true,
+        }
+
+        inspect_file(&path, &mut report);
+        reports.push((path, report));
+    }
+    reports.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let print_section = |title:&str, lines:Vec<String>| {
+        output_handler::throw_res(Color::Cyan, format!("{} ({})", title, lines.len()).as_str());
+        for line in lines {
+            output_handler::throw_res(Color::Green, format!("  {}", line).as_str());
+        }
+    };
+    let select = |f:&dyn Fn(&FileReport) -> Option<String>| -> Vec<String> {
+        return reports.iter()
+            .filter_map(|(path, report)| f(report).map(|msg| format!("{} {}", path.display(), msg)))
+            .collect();
+    };
+
+    print_section("Orphaned images", select(&|r| if r.orphan { Some(format!("({} bytes)", r.size)) } else { None }));
+    print_section("Unexpected file names", select(&|r| if r.unexpected_name { Some(String::new()) } else { None }));
+    print_section("Invalid JPEGs", select(&|r| r.invalid.clone()));
+    print_section("Larger than 256x256", select(&|r| r.oversized.map(|(w, h)| format!("{}x{}", w, h))));
+    print_section("Still carrying EXIF", select(&|r| if r.exif { Some(String::new()) } else { None }));
+    print_section("Users with an unexpected profile url", unexpected_profiles);
+
+    let total:u64 = reports.iter().map(|(_, r)| r.size).sum();
+    let orphaned:u64 = reports.iter().filter(|(_, r)| r.orphan).map(|(_, r)| r.size).sum();
+    output_handler::throw_res(Color::Green,
+        format!("{} file(s), {} bytes in total, {} bytes orphaned", reports.len(), total, orphaned).as_str()
+    );
+
+    return Ok(reports.into_iter().filter(|(_, r)| r.orphan).map(|(path, _)| path).collect());
+}
+
+/*- Render and write an identicon for a suid -*/
+fn generate(suid:&str) -> Result<(), String> {
+    let bytes = encode_profile_image(&identicon::render(suid))?;
+    return write_profile_image(suid, &bytes);
+}
+
+/*- Manage profile images -*/
+pub fn image(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    if ["prune", "set", "reset", "generate"].contains(&argv[0].as_str()) && refuse_in_memory(&format!("<image {}>", &argv[0])) {
+        return;
+    }
+
+    /*- These work on the whole uploads directory instead of a single user -*/
+    if (&argv[0] == "audit" || &argv[0] == "prune") && argv.len() == 1 {
+        if store::is_memory() {
+            output_handler::throw_res(Color::Cyan, "Using the in-memory store, the files belong to users it doesn't have");
+        }
+
+        let orphans = match audit_uploads() {
+            Ok(orphans) => orphans,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        if &argv[0] == "audit" { return; }
+
+        if orphans.is_empty() {
+            output_handler::throw_res(Color::Cyan, "No orphaned images to prune");
+            return;
+        }
+        if !commands::confirm(format!("Delete {} orphaned image(s)?", orphans.len()).as_str()) { return; }
+
+        let mut deleted:Vec<String> = Vec::new();
+        for path in orphans {
+            match std::fs::remove_file(&path) {
+                Ok(_) => deleted.push(path.display().to_string()),
+                Err(e) => output_handler::throw_res(Color::Red, format!("Failed to delete {}: {}", path.display(), e).as_str()),
+            }
+        }
+
+        audit::record("image.prune", "", doc! { "deleted": &deleted });
+        output_handler::throw_res(Color::Green, format!("{} orphaned image(s) deleted!", deleted.len()).as_str());
+        return;
+    }
+
+    /*- Give every user without a profile image an identicon -*/
+    if &argv[0] == "generate" && argv.len() == 2 && &argv[1] == "all-missing" {
+        let cursor = match users::users_collection().find(doc! { "suid": { "$exists": true } }, None) {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, format!("Failed to get users: {}", e).as_str());
+                return;
+            }
+        };
+        let missing:Vec<String> = cursor.into_iter()
+            .filter_map(|user| user.get_str("suid").ok().map(|s| s.to_string()))
+            .filter(|suid| !suid.is_empty() && !users::profile_image_path(suid).is_file())
+            .collect();
+
+        if missing.is_empty() {
+            output_handler::throw_res(Color::Cyan, "Every user already has a profile image");
+            return;
+        }
+        if !commands::confirm(format!("Generate identicons for {} user(s)?", missing.len()).as_str()) { return; }
+
+        let mut generated = 0;
+        for suid in &missing {
+            match generate(suid) {
+                Ok(_) => generated += 1,
+                Err(e) => output_handler::throw_res(Color::Red, &e),
+            }
+        }
+
+        audit::record("image.generate", "", doc! { "generated": generated });
+        output_handler::throw_res(Color::Green, format!("{} identicon(s) generated!", generated).as_str());
+        return;
+    }
+
+    if argv.len() < 2 {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help image> for further information.");
+        return;
+    }
+
+    let user = match users::find_user(&*users::users_collection(), &argv[1]) {
+        Ok(user) => user,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let suid = match user.get_str("suid") {
+        Ok(suid) if !suid.is_empty() => suid.to_string(),
+        _ => {
+            output_handler::throw_res(Color::Red, "User has no suid!");
+            return;
+        }
+    };
+
+    if &argv[0] == "set" && argv.len() == 3 {
+        let source = Path::new(&argv[2]);
+
+        let bytes = match open_oriented(source).and_then(|image| encode_profile_image(&image)) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        if let Err(e) = write_profile_image(&suid, &bytes) {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+
+        audit::record("image.set", &suid, doc! { "source": source.display().to_string() });
+
+        output_handler::throw_res(Color::Green, format!("Profile image of {} updated!", users::describe(&user)).as_str());
+    }
+    else if &argv[0] == "show-path" && argv.len() == 2 {
+        let path = users::profile_image_path(&suid);
+
+        if path.is_file() {
+            output_handler::throw_res(Color::Green, &path.display().to_string());
+        }else {
+            output_handler::throw_res(Color::Green, format!("{} (missing, falls back to {})", path.display(), DEFAULT_IMAGE).as_str());
+        }
+    }
+    else if &argv[0] == "reset" && argv.len() == 2 {
+        let path = users::profile_image_path(&suid);
+
+        if !path.is_file() {
+            output_handler::throw_res(Color::Cyan, "User already has the default image");
+            return;
+        }
+
+        /*- Without a file the API serves the default image -*/
+        if !commands::confirm(format!("Reset the profile image of {}?", users::describe(&user)).as_str()) {
+            return;
+        }
+
+        if let Err(e) = std::fs::remove_file(&path) {
+            output_handler::throw_res(Color::Red, format!("Failed to delete {}: {}", path.display(), e).as_str());
+            return;
+        }
+
+        audit::record("image.reset", &suid, doc! {});
+
+        output_handler::throw_res(Color::Green, format!("Profile image reset, now serving {}", DEFAULT_IMAGE).as_str());
+    }
+    else if &argv[0] == "generate" && argv.len() == 2 {
+        if users::profile_image_path(&suid).is_file()
+            && !commands::confirm(format!("{} already has a profile image, overwrite it?", users::describe(&user)).as_str()) {
+            return;
+        }
+
+        if let Err(e) = generate(&suid) {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+
+        audit::record("image.generate", &suid, doc! {});
+        output_handler::throw_res(Color::Green, format!("Identicon generated for {}!", users::describe(&user)).as_str());
+    }
+    else if &argv[0] == "info" && argv.len() == 2 {
+        info(&suid);
+    }
+    else {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help image> for further information.");
+    }
+}
@@ -0,0 +1,45 @@
+/*- Everything the CLI can do, the binary in main.rs is only a REPL around Session -*/
+
+/*- The codebase prefers explicit returns and comparisons, keep clippy quiet about it -*/
+#![allow(
+    clippy::needless_return,
+    clippy::op_ref,
+    clippy::bool_comparison,
+    clippy::len_zero,
+    clippy::ptr_arg,
+    clippy::needless_borrows_for_generic_args,
+    clippy::redundant_pattern_matching,
+    clippy::unnecessary_unwrap,
+)]
+
+pub mod commands;
+pub mod output_handler;
+pub mod config;
+pub mod audit;
+pub mod users;
+pub mod roles;
+pub mod friends;
+pub mod graph;
+pub mod archive;
+pub mod images;
+pub mod identicon;
+pub mod dates;
+pub mod stats;
+pub mod editor;
+pub mod edit;
+pub mod aggregate;
+pub mod seed;
+pub mod api;
+pub mod selftest;
+pub mod mock_server;
+pub mod bench;
+pub mod store;
+pub mod memory_store;
+pub mod pipeline;
+pub mod registry;
+pub mod session;
+pub mod scripting;
+pub mod variables;
+pub mod escape;
+
+pub use session::{ CommandResult, Session };
@@ -0,0 +1,1266 @@
+use mongodb::{
+    bson::{ doc, oid::ObjectId, Bson, Document },
+    options::{ FindOptions, UpdateOptions },
+};
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::store::{ DocumentStore, UpdateCount, UpdateOp };
+
+/*- A collection kept in memory -*/
+#[derive(Debug, Clone, Default)]
+struct MemoryCollection {
+    documents: Vec<Document>,
+
+    /*- Same shape as DocumentStore::list_indexes, { name, key, unique } -*/
+    indexes: Vec<Document>,
+}
+
+lazy_static! {
+    /*- (database, collection) -> collection. Lives as long as the process -*/
+    #[allow(deprecated)]
+    static ref COLLECTIONS: Mutex<HashMap<(String, String), MemoryCollection>> = Mutex::new(HashMap::new());
+}
+
+/*- A collection in the in-memory store, filters, updates and pipelines are evaluated here -*/
+pub struct MemoryStore {
+    database: String,
+    collection: String,
+}
+
+impl MemoryStore {
+    pub fn new(database:&str, collection:&str) -> MemoryStore {
+        return MemoryStore { database: database.to_string(), collection: collection.to_string() };
+    }
+
+    /*- Run <f> on this collection, creating it if it doesn't exist -*/
+    fn with<T>(&self, f:impl FnOnce(&mut MemoryCollection) -> T) -> T {
+        let mut collections = COLLECTIONS.lock().unwrap();
+        let collection = collections.entry((self.database.clone(), self.collection.clone())).or_default();
+
+        return f(collection);
+    }
+
+    fn update(&self, filter:Document, update:Document, options:Option<UpdateOptions>, many:bool) -> Result<UpdateCount, String> {
+        return self.with(|collection| {
+            let backup = collection.documents.clone();
+            let result = update_documents(collection, &filter, &update, options.as_ref(), many);
+
+            if result.is_err() { collection.documents = backup; }
+            return result;
+        });
+    }
+}
+
+impl DocumentStore for MemoryStore {
+    fn find(&self, filter:Document, options:Option<FindOptions>) -> Result<Vec<Document>, String> {
+        let options = options.unwrap_or_default();
+
+        return self.with(|collection| {
+            let mut documents = filter_documents(&collection.documents, &filter)?;
+
+            if let Some(sort) = &options.sort {
+                sort_documents(&mut documents, sort)?;
+            }
+            let skip = options.skip.unwrap_or(0) as usize;
+            let limit = options.limit.filter(|limit| *limit != 0).map(|limit| limit.unsigned_abs() as usize).unwrap_or(usize::MAX);
+            let documents = documents.into_iter().skip(skip).take(limit);
+
+            return match &options.projection {
+                Some(projection) => documents.map(|document| project(&document, projection)).collect(),
+                None => Ok(documents.collect()),
+            };
+        });
+    }
+
+    fn count(&self, filter:Document) -> Result<u64, String> {
+        return self.with(|collection| filter_documents(&collection.documents, &filter).map(|documents| documents.len() as u64));
+    }
+
+    fn insert_one(&self, document:Document) -> Result<Bson, String> {
+        return self.with(|collection| insert(collection, document));
+    }
+
+    fn insert_many(&self, documents:Vec<Document>) -> Result<usize, String> {
+        return self.with(|collection| {
+            let backup = collection.documents.clone();
+            for document in &documents {
+                if let Err(e) = insert(collection, document.clone()) {
+                    collection.documents = backup;
+                    return Err(e);
+                }
+            }
+            return Ok(documents.len());
+        });
+    }
+
+    fn update_one(&self, filter:Document, update:Document, options:Option<UpdateOptions>) -> Result<UpdateCount, String> {
+        return self.update(filter, update, options, false);
+    }
+
+    fn update_many(&self, filter:Document, update:Document, options:Option<UpdateOptions>) -> Result<UpdateCount, String> {
+        return self.update(filter, update, options, true);
+    }
+
+    fn replace_one(&self, filter:Document, replacement:Document) -> Result<UpdateCount, String> {
+        if replacement.keys().any(|key| key.starts_with('$')) {
+            return Err(String::from("Replacement documents can't contain update operators"));
+        }
+        return self.update(filter, replacement, None, false);
+    }
+
+    fn delete_one(&self, filter:Document) -> Result<u64, String> {
+        return self.with(|collection| {
+            for (index, document) in collection.documents.iter().enumerate() {
+                if matches(document, &filter)? {
+                    collection.documents.remove(index);
+                    return Ok(1);
+                }
+            }
+            return Ok(0);
+        });
+    }
+
+    fn delete_many(&self, filter:Document) -> Result<u64, String> {
+        return self.with(|collection| {
+            let mut kept = Vec::new();
+            for document in &collection.documents {
+                if !matches(document, &filter)? { kept.push(document.clone()); }
+            }
+
+            let deleted = (collection.documents.len() - kept.len()) as u64;
+            collection.documents = kept;
+            return Ok(deleted);
+        });
+    }
+
+    fn create_index(&self, keys:Document, unique:bool) -> Result<String, String> {
+        let name = keys.iter()
+            .map(|(key, direction)| format!("{}_{}", key, direction))
+            .collect::<Vec<String>>()
+            .join("_");
+
+        return self.with(|collection| {
+            if collection.indexes.iter().any(|index| index.get_str("name") == Ok(name.as_str())) {
+                return Ok(name.clone());
+            }
+
+            let index = doc! { "name": &name, "key": keys, "unique": unique };
+            if unique {
+                for (position, document) in collection.documents.iter().enumerate() {
+                    check_unique(&collection.documents, &index, document, Some(position))?;
+                }
+            }
+
+            collection.indexes.push(index);
+            return Ok(name.clone());
+        });
+    }
+
+    fn list_indexes(&self) -> Result<Vec<Document>, String> {
+        return self.with(|collection| {
+            let mut indexes = vec![doc! { "name": "_id_", "key": { "_id": 1 }, "unique": true }];
+            indexes.extend(collection.indexes.iter().cloned());
+            return Ok(indexes);
+        });
+    }
+
+    fn aggregate(&self, pipeline:Vec<Document>) -> Result<Vec<Document>, String> {
+        let collections = COLLECTIONS.lock().unwrap();
+        let mut documents = collections.get(&(self.database.clone(), self.collection.clone()))
+            .map(|collection| collection.documents.clone())
+            .unwrap_or_default();
+
+        for stage in &pipeline {
+            documents = run_stage(documents, stage, &self.database, &collections)?;
+        }
+        return Ok(documents);
+    }
+
+    fn update_atomically(&self, updates:Vec<UpdateOp>) -> Result<bool, String> {
+        return self.with(|collection| {
+            let backup = collection.documents.clone();
+            for op in &updates {
+                if let Err(e) = update_documents(collection, &op.filter, &op.update, op.options.as_ref(), op.many) {
+                    collection.documents = backup;
+                    return Err(e);
+                }
+            }
+            return Ok(true);
+        });
+    }
+}
+
+/*- Insert a document, generating an _id like the server does -*/
+fn insert(collection:&mut MemoryCollection, mut document:Document) -> Result<Bson, String> {
+    if !document.contains_key("_id") {
+        let mut with_id = doc! { "_id": ObjectId::new() };
+        with_id.extend(document);
+        document = with_id;
+    }
+
+    let id = document.get("_id").cloned().unwrap();
+    if collection.documents.iter().any(|existing| existing.get("_id").map(|existing| bson_eq(existing, &id)).unwrap_or(false)) {
+        return Err(format!("E11000 duplicate key error, _id: {}", id));
+    }
+    for index in collection.indexes.iter().filter(|index| index.get_bool("unique") == Ok(true)) {
+        check_unique(&collection.documents, index, &document, None)?;
+    }
+
+    collection.documents.push(document);
+    return Ok(id);
+}
+
+/*- Fail if another document has the same values for a unique index -*/
+fn check_unique(documents:&[Document], index:&Document, document:&Document, skip:Option<usize>) -> Result<(), String> {
+    let keys = index.get_document("key").map_err(|e| e.to_string())?;
+    let values = |document:&Document| -> Vec<Bson> {
+        keys.keys().map(|key| lookup(document, key).into_iter().next().cloned().unwrap_or(Bson::Null)).collect()
+    };
+    let own = values(document);
+
+    for (position, other) in documents.iter().enumerate() {
+        if Some(position) == skip { continue; }
+        if values(other).iter().zip(&own).all(|(a, b)| bson_eq(a, b)) {
+            return Err(format!("E11000 duplicate key error, index: {}", index.get_str("name").unwrap_or("")));
+        }
+    }
+    return Ok(());
+}
+
+fn update_documents(collection:&mut MemoryCollection, filter:&Document, update:&Document, options:Option<&UpdateOptions>, many:bool) -> Result<UpdateCount, String> {
+    let array_filters = options.and_then(|o| o.array_filters.clone()).unwrap_or_default();
+    let mut count = UpdateCount::default();
+
+    for position in 0..collection.documents.len() {
+        if !matches(&collection.documents[position], filter)? { continue; }
+
+        count.matched += 1;
+        let mut document = collection.documents[position].clone();
+        apply_update(&mut document, update, &array_filters, false)?;
+
+        if document != collection.documents[position] {
+            for index in collection.indexes.iter().filter(|index| index.get_bool("unique") == Ok(true)) {
+                check_unique(&collection.documents, index, &document, Some(position))?;
+            }
+            collection.documents[position] = document;
+            count.modified += 1;
+        }
+
+        if !many { break; }
+    }
+
+    /*- Upserts start from the equality fields of the filter -*/
+    if count.matched == 0 && options.and_then(|o| o.upsert).unwrap_or(false) {
+        let mut document = Document::new();
+        for (key, value) in filter {
+            if key.starts_with('$') || is_operator_document(value) { continue; }
+            set_path(&mut document, key, value.clone())?;
+        }
+        apply_update(&mut document, update, &array_filters, true)?;
+        insert(collection, document)?;
+    }
+
+    return Ok(count);
+}
+
+//
+//  VALUES
+//
+
+/*- BSON type order used when sorting values of different types -*/
+fn type_rank(value:&Bson) -> u8 {
+    return match value {
+        Bson::MinKey => 0,
+        Bson::Null | Bson::Undefined => 1,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => 2,
+        Bson::String(_) | Bson::Symbol(_) => 3,
+        Bson::Document(_) => 4,
+        Bson::Array(_) => 5,
+        Bson::Binary(_) => 6,
+        Bson::ObjectId(_) => 7,
+        Bson::Boolean(_) => 8,
+        Bson::DateTime(_) => 9,
+        Bson::Timestamp(_) => 10,
+        Bson::RegularExpression(_) => 11,
+        Bson::MaxKey => 13,
+        _ => 12,
+    };
+}
+
+fn as_number(value:&Bson) -> Option<f64> {
+    return match value {
+        Bson::Int32(n) => Some(*n as f64),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    };
+}
+
+/*- Compare two values of the same kind, None if they can't be compared -*/
+fn compare(a:&Bson, b:&Bson) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (as_number(a), as_number(b)) {
+        return a.partial_cmp(&b);
+    }
+
+    return match (a, b) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::Timestamp(a), Bson::Timestamp(b)) => Some((a.time, a.increment).cmp(&(b.time, b.increment))),
+        (Bson::Null, Bson::Null) => Some(Ordering::Equal),
+        _ => None,
+    };
+}
+
+/*- Order used by $sort, values of different types are ordered by type -*/
+fn sort_order(a:&Bson, b:&Bson) -> Ordering {
+    return type_rank(a).cmp(&type_rank(b))
+        .then_with(|| compare(a, b).unwrap_or(Ordering::Equal));
+}
+
+/*- Equality where 1, 1i64 and 1.0 are the same value -*/
+fn bson_eq(a:&Bson, b:&Bson) -> bool {
+    if let (Some(a), Some(b)) = (as_number(a), as_number(b)) {
+        return a == b;
+    }
+    return match (a, b) {
+        (Bson::Array(a), Bson::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| bson_eq(a, b)),
+        (Bson::Document(a), Bson::Document(b)) => a.len() == b.len() && a.iter().zip(b).all(|((ka, va), (kb, vb))| ka == kb && bson_eq(va, vb)),
+        _ => a == b,
+    };
+}
+
+/*- All values at a dotted path. Arrays of documents are walked like mongo does -*/
+fn lookup<'a>(document:&'a Document, path:&str) -> Vec<&'a Bson> {
+    fn walk<'a>(value:&'a Bson, parts:&[&str], out:&mut Vec<&'a Bson>) {
+        if parts.is_empty() {
+            out.push(value);
+            return;
+        }
+
+        match value {
+            Bson::Document(document) => if let Some(value) = document.get(parts[0]) {
+                walk(value, &parts[1..], out);
+            },
+            Bson::Array(array) => match parts[0].parse::<usize>() {
+                Ok(index) => if let Some(value) = array.get(index) {
+                    walk(value, &parts[1..], out);
+                },
+                Err(_) => for element in array.iter().filter(|e| matches!(e, Bson::Document(_))) {
+                    walk(element, parts, out);
+                },
+            },
+            _ => {}
+        }
+    }
+
+    let parts:Vec<&str> = path.split('.').collect();
+    let mut out = Vec::new();
+    if let Some(value) = document.get(parts[0]) {
+        walk(value, &parts[1..], &mut out);
+    }
+    return out;
+}
+
+/*- Values plus the elements of array values, what comparisons are run against -*/
+fn candidates<'a>(values:&[&'a Bson]) -> Vec<&'a Bson> {
+    let mut out = Vec::new();
+    for value in values {
+        if let Bson::Array(array) = value {
+            out.extend(array.iter());
+        }
+        out.push(*value);
+    }
+    return out;
+}
+
+//
+//  FILTERS
+//
+
+fn is_operator_document(value:&Bson) -> bool {
+    return match value {
+        Bson::Document(document) => !document.is_empty() && document.keys().all(|key| key.starts_with('$')),
+        _ => false,
+    };
+}
+
+fn filter_documents(documents:&[Document], filter:&Document) -> Result<Vec<Document>, String> {
+    let mut out = Vec::new();
+    for document in documents {
+        if matches(document, filter)? { out.push(document.clone()); }
+    }
+    return Ok(out);
+}
+
+fn filter_list(value:&Bson, operator:&str) -> Result<Vec<Document>, String> {
+    return match value {
+        Bson::Array(filters) => filters.iter().map(|filter| match filter {
+            Bson::Document(filter) => Ok(filter.clone()),
+            _ => Err(format!("{} entries must be objects", operator)),
+        }).collect(),
+        _ => Err(format!("{} must be an array", operator)),
+    };
+}
+
+/*- Does a document match a query filter -*/
+pub fn matches(document:&Document, filter:&Document) -> Result<bool, String> {
+    for (key, condition) in filter {
+        let matched = match key.as_str() {
+            "$and" => filter_list(condition, key)?.iter().try_fold(true, |all, filter| Ok::<bool, String>(all && matches(document, filter)?))?,
+            "$or" => filter_list(condition, key)?.iter().try_fold(false, |any, filter| Ok::<bool, String>(any || matches(document, filter)?))?,
+            "$nor" => !filter_list(condition, key)?.iter().try_fold(false, |any, filter| Ok::<bool, String>(any || matches(document, filter)?))?,
+            "$comment" => true,
+            operator if operator.starts_with('$') => return Err(format!("{} isn't supported by the in-memory store", operator)),
+            path => {
+                let values = lookup(document, path);
+                match condition {
+                    Bson::Document(operators) if is_operator_document(condition) => match_operators(&values, operators)?,
+                    _ => match_eq(&values, condition),
+                }
+            }
+        };
+
+        if !matched { return Ok(false); }
+    }
+    return Ok(true);
+}
+
+fn regex(pattern:&str, options:&str) -> Result<regex::Regex, String> {
+    return regex::RegexBuilder::new(pattern)
+        .case_insensitive(options.contains('i'))
+        .multi_line(options.contains('m'))
+        .dot_matches_new_line(options.contains('s'))
+        .ignore_whitespace(options.contains('x'))
+        .build()
+        .map_err(|e| format!("Invalid regex {}: {}", pattern, e));
+}
+
+fn match_regex(values:&[&Bson], regex:&regex::Regex) -> bool {
+    return candidates(values).iter().any(|value| match value {
+        Bson::String(value) => regex.is_match(value),
+        _ => false,
+    });
+}
+
+/*- { field: value }, arrays match if any element is equal -*/
+fn match_eq(values:&[&Bson], target:&Bson) -> bool {
+    if let Bson::RegularExpression(target) = target {
+        return regex(&target.pattern, &target.options).map(|regex| match_regex(values, &regex)).unwrap_or(false);
+    }
+    if values.is_empty() {
+        return matches!(target, Bson::Null);
+    }
+    return candidates(values).iter().any(|value| bson_eq(value, target));
+}
+
+/*- { field: { $op: value, ... } } -*/
+fn match_operators(values:&[&Bson], operators:&Document) -> Result<bool, String> {
+    for (operator, argument) in operators {
+        let compare_with = |wanted:&[Ordering]| candidates(values).iter()
+            .any(|value| compare(value, argument).map(|ordering| wanted.contains(&ordering)).unwrap_or(false));
+
+        let matched = match operator.as_str() {
+            "$eq" => match_eq(values, argument),
+            "$ne" => !match_eq(values, argument),
+            "$gt" => compare_with(&[Ordering::Greater]),
+            "$gte" => compare_with(&[Ordering::Greater, Ordering::Equal]),
+            "$lt" => compare_with(&[Ordering::Less]),
+            "$lte" => compare_with(&[Ordering::Less, Ordering::Equal]),
+            "$in" | "$nin" => {
+                let list = argument.as_array().ok_or(format!("{} needs an array", operator))?;
+                let found = list.iter().any(|target| match_eq(values, target));
+                if operator == "$in" { found } else { !found }
+            },
+            "$exists" => {
+                let wanted = argument.as_bool().or(as_number(argument).map(|n| n != 0.0)).unwrap_or(true);
+                values.is_empty() != wanted
+            },
+            "$regex" => {
+                let options = operators.get_str("$options").unwrap_or("");
+                let (pattern, options) = match argument {
+                    Bson::String(pattern) => (pattern.clone(), options.to_string()),
+                    Bson::RegularExpression(regex) => (regex.pattern.clone(), format!("{}{}", regex.options, options)),
+                    _ => return Err(String::from("$regex needs a string")),
+                };
+                match_regex(values, &regex(&pattern, &options)?)
+            },
+            "$options" => true,
+            "$not" => match argument {
+                Bson::Document(operators) => !match_operators(values, operators)?,
+                Bson::RegularExpression(_) => !match_eq(values, argument),
+                _ => return Err(String::from("$not needs an object or a regex")),
+            },
+            "$size" => {
+                let size = as_number(argument).ok_or("$size needs a number")?;
+                values.iter().any(|value| matches!(value, Bson::Array(array) if array.len() as f64 == size))
+            },
+            "$all" => argument.as_array().ok_or("$all needs an array")?
+                .iter()
+                .all(|target| match_eq(values, target)),
+            "$elemMatch" => {
+                let condition = argument.as_document().ok_or("$elemMatch needs an object")?;
+                let mut found = false;
+                for array in values.iter().filter_map(|value| value.as_array()) {
+                    for element in array {
+                        let matched = match element {
+                            _ if is_operator_document(argument) => match_operators(&[element], condition)?,
+                            Bson::Document(element) => matches(element, condition)?,
+                            _ => false,
+                        };
+                        if matched { found = true; }
+                    }
+                }
+                found
+            },
+            operator => return Err(format!("{} isn't supported by the in-memory store", operator)),
+        };
+
+        if !matched { return Ok(false); }
+    }
+    return Ok(true);
+}
+
+//
+//  UPDATES
+//
+
+/*- Set a dotted path, creating the documents in between -*/
+fn set_path(document:&mut Document, path:&str, value:Bson) -> Result<(), String> {
+    let mut root = Bson::Document(std::mem::take(document));
+    let result = modify(&mut root, &path.split('.').collect::<Vec<&str>>(), &[], &mut |parent, key| put(parent, key, value.clone()));
+    *document = match root { Bson::Document(root) => root, _ => unreachable!() };
+    return result;
+}
+
+/*- Value at <key> of a document or array -*/
+fn get_mut<'a>(parent:&'a mut Bson, key:&str) -> Option<&'a mut Bson> {
+    return match parent {
+        Bson::Document(document) => document.get_mut(key),
+        Bson::Array(array) => key.parse::<usize>().ok().and_then(|index| array.get_mut(index)),
+        _ => None,
+    };
+}
+
+/*- Set <key> of a document or array -*/
+fn put(parent:&mut Bson, key:&str, value:Bson) -> Result<(), String> {
+    match parent {
+        Bson::Document(document) => { document.insert(key, value); },
+        Bson::Array(array) => {
+            let index = key.parse::<usize>().map_err(|_| format!("Can't use the field {} on an array", key))?;
+            while array.len() <= index { array.push(Bson::Null); }
+            array[index] = value;
+        },
+        _ => return Err(format!("Can't create the field {} on a {:?}", key, parent.element_type())),
+    }
+    return Ok(());
+}
+
+/*- Does an array element match the array filter for <identifier> -*/
+fn matches_array_filter(element:&Bson, identifier:&str, filters:&[Document]) -> Result<bool, String> {
+    let filter = filters.iter()
+        .find(|filter| filter.keys().any(|key| key == identifier || key.starts_with(&format!("{}.", identifier))))
+        .ok_or(format!("No array filter found for identifier {}", identifier))?;
+
+    return matches(&doc! { identifier: element.clone() }, filter);
+}
+
+/*- Walk to the parent of the last part of a path and call <f> with it, once per array element for $[] and $[id] -*/
+fn modify(container:&mut Bson, parts:&[&str], filters:&[Document], f:&mut dyn FnMut(&mut Bson, &str) -> Result<(), String>) -> Result<(), String> {
+    let part = parts[0];
+
+    /*- All elements, or the ones matching an array filter -*/
+    if part.starts_with("$[") && part.ends_with(']') {
+        let identifier = &part[2..part.len() - 1];
+        let length = match container {
+            Bson::Array(array) => array.len(),
+            _ => return Err(format!("{} can only be used on arrays", part)),
+        };
+
+        for index in 0..length {
+            let element = get_mut(container, &index.to_string()).unwrap();
+            if !identifier.is_empty() && !matches_array_filter(element, identifier, filters)? { continue; }
+
+            if parts.len() == 1 {
+                f(container, &index.to_string())?;
+            }else {
+                modify(get_mut(container, &index.to_string()).unwrap(), &parts[1..], filters, f)?;
+            }
+        }
+        return Ok(());
+    }
+    if part == "$" {
+        return Err(String::from("The positional operator $ isn't supported by the in-memory store, use $[<identifier>]"));
+    }
+
+    if parts.len() == 1 {
+        return f(container, part);
+    }
+
+    if get_mut(container, part).is_none() {
+        put(container, part, Bson::Document(Document::new()))?;
+    }
+    return modify(get_mut(container, part).unwrap(), &parts[1..], filters, f);
+}
+
+/*- Values of $push and $addToSet, with support for $each -*/
+fn each(value:&Bson) -> Vec<Bson> {
+    return match value {
+        Bson::Document(document) if document.contains_key("$each") => document.get_array("$each").cloned().unwrap_or_default(),
+        value => vec![value.clone()],
+    };
+}
+
+/*- Should an array element be removed by $pull -*/
+fn pull_matches(element:&Bson, condition:&Bson) -> Result<bool, String> {
+    return match (element, condition) {
+        (_, Bson::Document(operators)) if is_operator_document(condition) => match_operators(&[element], operators),
+        (Bson::Document(element), Bson::Document(condition)) => matches(element, condition),
+        _ => Ok(bson_eq(element, condition)),
+    };
+}
+
+fn add_numbers(a:&Bson, b:&Bson) -> Result<Bson, String> {
+    return match (a, b) {
+        (Bson::Int32(a), Bson::Int32(b)) => Ok(a.checked_add(*b).map(Bson::Int32).unwrap_or(Bson::Int64(*a as i64 + *b as i64))),
+        (Bson::Int32(_) | Bson::Int64(_), Bson::Int32(_) | Bson::Int64(_)) => Ok(Bson::Int64(as_number(a).unwrap() as i64 + as_number(b).unwrap() as i64)),
+        _ => match (as_number(a), as_number(b)) {
+            (Some(a), Some(b)) => Ok(Bson::Double(a + b)),
+            _ => Err(String::from("Can't apply $inc to a value that isn't a number")),
+        },
+    };
+}
+
+/*- Applies one update operator to <key> of the parent value -*/
+type FieldUpdate<'a> = Box<dyn FnMut(&mut Bson, &str) -> Result<(), String> + 'a>;
+
+/*- Apply an update document, or replace the document if it has no operators -*/
+fn apply_update(document:&mut Document, update:&Document, filters:&[Document], inserting:bool) -> Result<(), String> {
+    if !update.keys().any(|key| key.starts_with('$')) {
+        let id = document.get("_id").cloned();
+        *document = update.clone();
+        if let Some(id) = id { document.insert("_id", id); }
+        return Ok(());
+    }
+
+    let mut root = Bson::Document(std::mem::take(document));
+    let result = (|| {
+        for (operator, fields) in update {
+            let fields = fields.as_document().ok_or(format!("{} needs an object", operator))?;
+
+            for (path, value) in fields {
+                let parts:Vec<&str> = path.split('.').collect();
+                let mut f:FieldUpdate = match operator.as_str() {
+                    "$set" => Box::new(|parent, key| put(parent, key, value.clone())),
+                    "$setOnInsert" if inserting => Box::new(|parent, key| put(parent, key, value.clone())),
+                    "$setOnInsert" => continue,
+                    "$unset" => Box::new(|parent, key| {
+                        match parent {
+                            Bson::Document(document) => { document.remove(key); },
+                            Bson::Array(_) => if let Some(element) = get_mut(parent, key) { *element = Bson::Null; },
+                            _ => {}
+                        }
+                        return Ok(());
+                    }),
+                    "$inc" => Box::new(|parent, key| {
+                        let current = get_mut(parent, key).cloned().unwrap_or(Bson::Int32(0));
+                        return put(parent, key, add_numbers(&current, value)?);
+                    }),
+                    "$min" | "$max" => {
+                        let wanted = if operator == "$min" { Ordering::Less } else { Ordering::Greater };
+                        Box::new(move |parent, key| {
+                            let replace = match get_mut(parent, key) {
+                                Some(current) => sort_order(value, current) == wanted,
+                                None => true,
+                            };
+                            if replace { put(parent, key, value.clone())?; }
+                            return Ok(());
+                        })
+                    },
+                    "$push" | "$addToSet" => {
+                        let unique = operator == "$addToSet";
+                        Box::new(move |parent, key| {
+                            if get_mut(parent, key).is_none() { put(parent, key, Bson::Array(Vec::new()))?; }
+                            let array = match get_mut(parent, key) {
+                                Some(Bson::Array(array)) => array,
+                                _ => return Err(format!("The field {} must be an array", key)),
+                            };
+                            for value in each(value) {
+                                if unique && array.iter().any(|element| bson_eq(element, &value)) { continue; }
+                                array.push(value);
+                            }
+                            return Ok(());
+                        })
+                    },
+                    "$pull" | "$pullAll" => {
+                        let all = operator == "$pullAll";
+                        Box::new(move |parent, key| {
+                            let array = match get_mut(parent, key) {
+                                Some(Bson::Array(array)) => array,
+                                Some(_) => return Err(String::from("Cannot apply $pull to a non-array value")),
+                                None => return Ok(()),
+                            };
+                            let mut kept = Vec::new();
+                            for element in array.drain(..) {
+                                let remove = match all {
+                                    true => value.as_array().ok_or("$pullAll needs an array")?.iter().any(|v| bson_eq(&element, v)),
+                                    false => pull_matches(&element, value)?,
+                                };
+                                if !remove { kept.push(element); }
+                            }
+                            *array = kept;
+                            return Ok(());
+                        })
+                    },
+                    "$rename" => {
+                        let to = value.as_str().ok_or("$rename needs a string")?.to_string();
+                        let current = match &root {
+                            Bson::Document(document) => lookup(document, path).into_iter().next().cloned(),
+                            _ => None,
+                        };
+                        if let Some(current) = current {
+                            modify(&mut root, &parts, filters, &mut |parent, key| {
+                                if let Bson::Document(document) = parent { document.remove(key); }
+                                return Ok(());
+                            })?;
+                            modify(&mut root, &to.split('.').collect::<Vec<&str>>(), filters, &mut |parent, key| put(parent, key, current.clone()))?;
+                        }
+                        continue;
+                    },
+                    operator => return Err(format!("{} isn't supported by the in-memory store", operator)),
+                };
+
+                modify(&mut root, &parts, filters, &mut *f)?;
+            }
+        }
+        return Ok(());
+    })();
+
+    *document = match root { Bson::Document(root) => root, _ => unreachable!() };
+    return result;
+}
+
+//
+//  PROJECTIONS AND SORTING
+//
+
+fn sort_documents(documents:&mut [Document], sort:&Document) -> Result<(), String> {
+    let mut keys = Vec::new();
+    for (path, direction) in sort {
+        match as_number(direction) {
+            Some(direction) if direction == 1.0 || direction == -1.0 => keys.push((path.clone(), direction < 0.0)),
+            _ => return Err(format!("Invalid sort direction for {}", path)),
+        }
+    }
+
+    documents.sort_by(|a, b| {
+        for (path, descending) in &keys {
+            let a = lookup(a, path).into_iter().next().cloned().unwrap_or(Bson::Null);
+            let b = lookup(b, path).into_iter().next().cloned().unwrap_or(Bson::Null);
+            let ordering = sort_order(&a, &b);
+            let ordering = if *descending { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal { return ordering; }
+        }
+        return Ordering::Equal;
+    });
+    return Ok(());
+}
+
+fn is_truthy(value:&Bson) -> bool {
+    return match value {
+        Bson::Boolean(value) => *value,
+        Bson::Null | Bson::Undefined => false,
+        value => as_number(value).map(|n| n != 0.0).unwrap_or(true),
+    };
+}
+
+/*- Copy a dotted path from <value>, keeping the structure. Arrays of documents are projected per element -*/
+fn include(value:&Bson, parts:&[&str]) -> Option<Bson> {
+    if parts.is_empty() { return Some(value.clone()); }
+
+    return match value {
+        Bson::Document(document) => {
+            let inner = include(document.get(parts[0])?, &parts[1..])?;
+            Some(Bson::Document(doc! { parts[0]: inner }))
+        },
+        Bson::Array(array) => Some(Bson::Array(array.iter()
+            .filter(|element| matches!(element, Bson::Document(_)))
+            .filter_map(|element| include(element, parts).or(Some(Bson::Document(Document::new()))))
+            .collect())),
+        _ => None,
+    };
+}
+
+/*- Merge projected paths into the result, documents and arrays of documents are merged deeply -*/
+fn merge(target:&mut Document, key:&str, value:Bson) {
+    match (target.get_mut(key), value) {
+        (Some(Bson::Document(existing)), Bson::Document(value)) => for (k, v) in value { merge(existing, &k, v); },
+        (Some(Bson::Array(existing)), Bson::Array(value)) => for (existing, value) in existing.iter_mut().zip(value) {
+            if let (Bson::Document(existing), Bson::Document(value)) = (existing, value) {
+                for (k, v) in value { merge(existing, &k, v); }
+            }
+        },
+        (_, value) => { target.insert(key, value); },
+    }
+}
+
+/*- Remove a dotted path, through arrays of documents too -*/
+fn exclude(value:&mut Bson, parts:&[&str]) {
+    match value {
+        Bson::Document(document) if parts.len() == 1 => { document.remove(parts[0]); },
+        Bson::Document(document) => if let Some(inner) = document.get_mut(parts[0]) { exclude(inner, &parts[1..]); },
+        Bson::Array(array) => for element in array { exclude(element, parts); },
+        _ => {}
+    }
+}
+
+/*- find projections and $project -*/
+pub fn project(document:&Document, projection:&Document) -> Result<Document, String> {
+    let inclusion = projection.iter().any(|(key, value)| key != "_id" && (!matches!(value, Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Boolean(_)) || is_truthy(value)));
+    let keep_id = projection.get("_id").map(is_truthy).unwrap_or(true)
+        || matches!(projection.get("_id"), Some(value) if !matches!(value, Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Boolean(_)));
+
+    if !inclusion {
+        let mut root = Bson::Document(document.clone());
+        for (path, _) in projection {
+            exclude(&mut root, &path.split('.').collect::<Vec<&str>>());
+        }
+        return Ok(match root { Bson::Document(root) => root, _ => unreachable!() });
+    }
+
+    let mut result = Document::new();
+    if keep_id {
+        if let Some(id) = document.get("_id") { result.insert("_id", id.clone()); }
+    }
+    for (path, value) in projection {
+        if path == "_id" && matches!(value, Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Boolean(_)) { continue; }
+
+        match value {
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Boolean(_) => {
+                if !is_truthy(value) { return Err(format!("Can't exclude {} in an inclusion projection", path)); }
+
+                let parts:Vec<&str> = path.split('.').collect();
+                if let Some(included) = document.get(parts[0]).and_then(|value| include(value, &parts[1..])) {
+                    merge(&mut result, parts[0], included);
+                }
+            },
+            expression => set_path(&mut result, path, evaluate(document, expression)?)?,
+        }
+    }
+    return Ok(result);
+}
+
+//
+//  AGGREGATION
+//
+
+/*- Evaluate an aggregation expression like "$field", { $size: "$friends" } or a literal -*/
+fn evaluate(document:&Document, expression:&Bson) -> Result<Bson, String> {
+    return match expression {
+        Bson::String(path) if path == "$$ROOT" => Ok(Bson::Document(document.clone())),
+        Bson::String(path) if path.starts_with("$$") => Err(format!("The variable {} isn't supported by the in-memory store", path)),
+        Bson::String(path) if path.starts_with('$') => {
+            let values = lookup(document, &path[1..]);
+            let through_array = path[1..].split('.').count() > 1 && values.len() != 1;
+            Ok(match values.len() {
+                0 if !through_array => Bson::Null,
+                1 => values[0].clone(),
+                _ => Bson::Array(values.into_iter().cloned().collect()),
+            })
+        },
+        Bson::Array(array) => array.iter().map(|e| evaluate(document, e)).collect::<Result<Vec<Bson>, String>>().map(Bson::Array),
+        Bson::Document(object) if object.len() == 1 && object.keys().next().unwrap().starts_with('$') => {
+            let (operator, argument) = object.iter().next().unwrap();
+            let arguments = || -> Result<Vec<Bson>, String> {
+                return match argument {
+                    Bson::Array(array) => array.iter().map(|e| evaluate(document, e)).collect(),
+                    argument => Ok(vec![evaluate(document, argument)?]),
+                };
+            };
+
+            match operator.as_str() {
+                "$literal" => Ok(argument.clone()),
+                "$size" => match evaluate(document, argument)? {
+                    Bson::Array(array) => Ok(Bson::Int32(array.len() as i32)),
+                    _ => Err(String::from("The argument to $size must be an array")),
+                },
+                "$ifNull" => Ok(arguments()?.into_iter().find(|value| !matches!(value, Bson::Null | Bson::Undefined)).unwrap_or(Bson::Null)),
+                "$concat" => {
+                    let mut out = String::new();
+                    for value in arguments()? {
+                        match value {
+                            Bson::String(value) => out.push_str(&value),
+                            Bson::Null | Bson::Undefined => return Ok(Bson::Null),
+                            _ => return Err(String::from("$concat only supports strings")),
+                        }
+                    }
+                    Ok(Bson::String(out))
+                },
+                "$toLower" | "$toUpper" => match evaluate(document, argument)? {
+                    Bson::String(value) if operator == "$toLower" => Ok(Bson::String(value.to_lowercase())),
+                    Bson::String(value) => Ok(Bson::String(value.to_uppercase())),
+                    _ => Ok(Bson::String(String::new())),
+                },
+                operator => Err(format!("The expression {} isn't supported by the in-memory store", operator)),
+            }
+        },
+        Bson::Document(object) => {
+            let mut out = Document::new();
+            for (key, value) in object {
+                out.insert(key, evaluate(document, value)?);
+            }
+            Ok(Bson::Document(out))
+        },
+        literal => Ok(literal.clone()),
+    };
+}
+
+/*- Int32 if it fits, like the server -*/
+fn integer(value:i64) -> Bson {
+    return i32::try_from(value).map(Bson::Int32).unwrap_or(Bson::Int64(value));
+}
+
+/*- Compute a $group accumulator over the documents in a group -*/
+fn accumulate(documents:&[Document], accumulator:&Document) -> Result<Bson, String> {
+    let (operator, expression) = accumulator.iter().next().ok_or("Empty accumulator")?;
+    let values = documents.iter().map(|d| evaluate(d, expression)).collect::<Result<Vec<Bson>, String>>()?;
+
+    return match operator.as_str() {
+        "$sum" => {
+            let numbers:Vec<&Bson> = values.iter().filter(|v| as_number(v).is_some()).collect();
+            if numbers.iter().all(|v| matches!(v, Bson::Int32(_) | Bson::Int64(_))) {
+                Ok(integer(numbers.iter().map(|v| as_number(v).unwrap() as i64).sum()))
+            }else {
+                Ok(Bson::Double(numbers.iter().map(|v| as_number(v).unwrap()).sum()))
+            }
+        },
+        "$avg" => {
+            let numbers:Vec<f64> = values.iter().filter_map(as_number).collect();
+            if numbers.is_empty() { return Ok(Bson::Null); }
+            Ok(Bson::Double(numbers.iter().sum::<f64>() / numbers.len() as f64))
+        },
+        "$min" => Ok(values.into_iter().filter(|v| !matches!(v, Bson::Null)).min_by(sort_order).unwrap_or(Bson::Null)),
+        "$max" => Ok(values.into_iter().filter(|v| !matches!(v, Bson::Null)).max_by(sort_order).unwrap_or(Bson::Null)),
+        "$first" => Ok(values.into_iter().next().unwrap_or(Bson::Null)),
+        "$last" => Ok(values.into_iter().last().unwrap_or(Bson::Null)),
+        "$push" => Ok(Bson::Array(values)),
+        "$addToSet" => {
+            let mut set:Vec<Bson> = Vec::new();
+            for value in values {
+                if !set.iter().any(|v| bson_eq(v, &value)) { set.push(value); }
+            }
+            Ok(Bson::Array(set))
+        },
+        "$count" => Ok(integer(documents.len() as i64)),
+        operator => Err(format!("The accumulator {} isn't supported by the in-memory store", operator)),
+    };
+}
+
+fn group(documents:Vec<Document>, spec:&Document) -> Result<Vec<Document>, String> {
+    let id = spec.get("_id").ok_or("$group needs an _id")?;
+
+    /*- Keeps the order in which the groups were first seen -*/
+    let mut groups:Vec<(Bson, Vec<Document>)> = Vec::new();
+    for document in documents {
+        let key = evaluate(&document, id)?;
+        match groups.iter_mut().find(|(existing, _)| bson_eq(existing, &key)) {
+            Some((_, members)) => members.push(document),
+            None => groups.push((key, vec![document])),
+        }
+    }
+
+    let mut out = Vec::new();
+    for (key, members) in groups {
+        let mut result = doc! { "_id": key };
+        for (field, accumulator) in spec.iter().filter(|(field, _)| *field != "_id") {
+            let accumulator = accumulator.as_document().ok_or(format!("The field {} must be an accumulator object", field))?;
+            result.insert(field, accumulate(&members, accumulator)?);
+        }
+        out.push(result);
+    }
+    return Ok(out);
+}
+
+fn unwind(documents:Vec<Document>, spec:&Bson) -> Result<Vec<Document>, String> {
+    let (path, preserve) = match spec {
+        Bson::String(path) => (path.clone(), false),
+        Bson::Document(spec) => (
+            spec.get_str("path").map_err(|_| "$unwind needs a path")?.to_string(),
+            spec.get_bool("preserveNullAndEmptyArrays").unwrap_or(false),
+        ),
+        _ => return Err(String::from("$unwind needs a path")),
+    };
+    let path = path.strip_prefix('$').ok_or("The $unwind path must start with $")?;
+
+    let mut out = Vec::new();
+    for document in documents {
+        match lookup(&document, path).into_iter().next().cloned() {
+            Some(Bson::Array(array)) if !array.is_empty() => for element in array {
+                let mut copy = document.clone();
+                set_path(&mut copy, path, element)?;
+                out.push(copy);
+            },
+            Some(Bson::Array(_)) | Some(Bson::Null) | None => if preserve { out.push(document); },
+            Some(_) => out.push(document),
+        }
+    }
+    return Ok(out);
+}
+
+fn lookup_stage(documents:Vec<Document>, spec:&Document, database:&str, collections:&HashMap<(String, String), MemoryCollection>) -> Result<Vec<Document>, String> {
+    let field = |name:&str| spec.get_str(name).map_err(|_| format!("$lookup needs {}", name));
+    let (from, local, foreign, as_field) = (field("from")?, field("localField")?, field("foreignField")?, field("as")?);
+
+    let foreign_documents = collections.get(&(database.to_string(), from.to_string()))
+        .map(|collection| collection.documents.clone())
+        .unwrap_or_default();
+
+    let mut out = Vec::new();
+    for mut document in documents {
+        let values:Vec<Bson> = candidates(&lookup(&document, local)).into_iter().cloned().collect();
+        let values = if values.is_empty() { vec![Bson::Null] } else { values };
+
+        let joined:Vec<Bson> = foreign_documents.iter()
+            .filter(|foreign_document| values.iter().any(|value| match_eq(&lookup(foreign_document, foreign), value)))
+            .map(|foreign_document| Bson::Document(foreign_document.clone()))
+            .collect();
+        set_path(&mut document, as_field, Bson::Array(joined))?;
+        out.push(document);
+    }
+    return Ok(out);
+}
+
+fn run_stage(documents:Vec<Document>, stage:&Document, database:&str, collections:&HashMap<(String, String), MemoryCollection>) -> Result<Vec<Document>, String> {
+    let (name, spec) = stage.iter().next().ok_or("Empty pipeline stage")?;
+    let spec_document = || spec.as_document().ok_or(format!("{} needs an object", name));
+    let spec_number = || as_number(spec).map(|n| n as usize).ok_or(format!("{} needs a number", name));
+
+    return match name.as_str() {
+        "$match" => filter_documents(&documents, spec_document()?),
+        "$project" => documents.iter().map(|document| project(document, spec_document()?)).collect(),
+        "$addFields" | "$set" => documents.into_iter().map(|mut document| {
+            for (path, expression) in spec_document()? {
+                let value = evaluate(&document, expression)?;
+                set_path(&mut document, path, value)?;
+            }
+            return Ok(document);
+        }).collect(),
+        "$unset" => {
+            let paths:Vec<String> = match spec {
+                Bson::String(path) => vec![path.clone()],
+                Bson::Array(paths) => paths.iter().filter_map(|p| p.as_str()).map(|p| p.to_string()).collect(),
+                _ => return Err(String::from("$unset needs a field or an array of fields")),
+            };
+            Ok(documents.into_iter().map(|document| {
+                let mut root = Bson::Document(document);
+                for path in &paths { exclude(&mut root, &path.split('.').collect::<Vec<&str>>()); }
+                return match root { Bson::Document(root) => root, _ => unreachable!() };
+            }).collect())
+        },
+        "$group" => group(documents, spec_document()?),
+        "$sort" => {
+            let mut documents = documents;
+            sort_documents(&mut documents, spec_document()?)?;
+            Ok(documents)
+        },
+        "$limit" => Ok(documents.into_iter().take(spec_number()?).collect()),
+        "$skip" => Ok(documents.into_iter().skip(spec_number()?).collect()),
+        "$count" => {
+            let field = spec.as_str().ok_or("$count needs a field name")?;
+            if documents.is_empty() { return Ok(Vec::new()); }
+            Ok(vec![doc! { field: integer(documents.len() as i64) }])
+        },
+        "$sortByCount" => {
+            let mut grouped = group(documents, &doc! { "_id": spec.clone(), "count": { "$sum": 1 } })?;
+            sort_documents(&mut grouped, &doc! { "count": -1 })?;
+            Ok(grouped)
+        },
+        "$unwind" => unwind(documents, spec),
+        "$lookup" => lookup_stage(documents, spec_document()?, database, collections),
+        "$replaceRoot" | "$replaceWith" => {
+            let expression = match name.as_str() {
+                "$replaceRoot" => spec_document()?.get("newRoot").cloned().ok_or("$replaceRoot needs newRoot")?,
+                _ => spec.clone(),
+            };
+            documents.iter().map(|document| match evaluate(document, &expression)? {
+                Bson::Document(root) => Ok(root),
+                _ => Err(format!("{} must evaluate to an object", name)),
+            }).collect()
+        },
+        name => Err(format!("The stage {} isn't supported by the in-memory store", name)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+
+    /*- Every test gets its own database, the collections are shared by the whole process -*/
+    fn users(database:&str) -> MemoryStore {
+        let users = MemoryStore::new(database, "users");
+        users.insert_many(vec![
+            doc! { "username": "alice", "role": "admin", "friends": ["b", "c"], "tags": [{ "name": "x", "level": 2 }], "age": 30 },
+            doc! { "username": "bob", "role": "user", "friends": ["a"], "tags": [{ "name": "y", "level": 5 }], "nickname": Bson::Null },
+            doc! { "username": "carl", "role": "user", "friends": [], "tags": [] },
+        ]).unwrap();
+        return users;
+    }
+
+    fn usernames(documents:Vec<Document>) -> Vec<String> {
+        return documents.iter().map(|document| document.get_str("username").unwrap().to_string()).collect();
+    }
+
+    #[test]
+    fn filter_in_and_nin() {
+        let users = users("test_in_nin");
+        assert_eq!(usernames(users.find(doc! { "username": { "$in": ["alice", "carl"] } }, None).unwrap()), ["alice", "carl"]);
+        assert_eq!(usernames(users.find(doc! { "role": { "$nin": ["admin"] } }, None).unwrap()), ["bob", "carl"]);
+
+        /*- An array field matches $in when any of its elements does -*/
+        assert_eq!(usernames(users.find(doc! { "friends": { "$in": ["c"] } }, None).unwrap()), ["alice"]);
+    }
+
+    #[test]
+    fn filter_exists_and_null() {
+        let users = users("test_exists");
+        assert_eq!(usernames(users.find(doc! { "age": { "$exists": true } }, None).unwrap()), ["alice"]);
+        assert_eq!(usernames(users.find(doc! { "nickname": { "$exists": true } }, None).unwrap()), ["bob"]);
+
+        /*- null matches missing fields too, $exists tells them apart -*/
+        assert_eq!(usernames(users.find(doc! { "nickname": Bson::Null }, None).unwrap()), ["alice", "bob", "carl"]);
+        assert_eq!(usernames(users.find(doc! { "nickname": { "$exists": false } }, None).unwrap()), ["alice", "carl"]);
+    }
+
+    #[test]
+    fn filter_elem_match_and_array_paths() {
+        let users = users("test_elem_match");
+        assert_eq!(usernames(users.find(doc! { "tags": { "$elemMatch": { "name": "y", "level": { "$gt": 3 } } } }, None).unwrap()), ["bob"]);
+        assert!(users.find(doc! { "tags": { "$elemMatch": { "name": "x", "level": { "$gt": 3 } } } }, None).unwrap().is_empty());
+
+        /*- tags.name looks into every element of the array -*/
+        assert_eq!(usernames(users.find(doc! { "tags.name": "x" }, None).unwrap()), ["alice"]);
+        assert_eq!(usernames(users.find(doc! { "tags.level": { "$gte": 2 } }, None).unwrap()), ["alice", "bob"]);
+    }
+
+    #[test]
+    fn update_pull() {
+        let users = users("test_pull");
+        let count = users.update_many(doc! {}, doc! { "$pull": { "friends": { "$in": ["a", "c"] } } }, None).unwrap();
+        assert_eq!((count.matched, count.modified), (3, 2));
+
+        let alice = users.find_one(doc! { "username": "alice" }).unwrap().unwrap();
+        assert_eq!(alice.get_array("friends").unwrap(), &vec![Bson::String(String::from("b"))]);
+        let bob = users.find_one(doc! { "username": "bob" }).unwrap().unwrap();
+        assert!(bob.get_array("friends").unwrap().is_empty());
+    }
+
+    #[test]
+    fn update_upsert() {
+        let users = users("test_upsert");
+        let options = UpdateOptions::builder().upsert(true).build();
+        users.update_one(doc! { "username": "dave" }, doc! { "$set": { "role": "user" } }, Some(options.clone())).unwrap();
+
+        let dave = users.find_one(doc! { "username": "dave" }).unwrap().unwrap();
+        assert_eq!(dave.get_str("role"), Ok("user"));
+        assert!(dave.get_object_id("_id").is_ok());
+
+        /*- A second upsert updates the document it inserted -*/
+        users.update_one(doc! { "username": "dave" }, doc! { "$set": { "role": "admin" } }, Some(options)).unwrap();
+        assert_eq!(users.count(doc! { "username": "dave" }).unwrap(), 1);
+        assert_eq!(users.count(doc! { "role": "admin" }).unwrap(), 2);
+    }
+
+    #[test]
+    fn unique_index_rolls_back() {
+        let users = users("test_unique");
+        users.create_index(doc! { "username": 1 }, true).unwrap();
+
+        assert!(users.insert_one(doc! { "username": "alice" }).is_err());
+
+        /*- bob is renamed before carl fails, the whole update is undone -*/
+        assert!(users.update_many(doc! { "role": "user" }, doc! { "$set": { "username": "same" } }, None).is_err());
+        assert_eq!(usernames(users.find(doc! {}, None).unwrap()), ["alice", "bob", "carl"]);
+    }
+
+    #[test]
+    fn group_stage() {
+        let users = users("test_group");
+        let groups = users.aggregate(vec![
+            doc! { "$group": { "_id": "$role", "amount": { "$sum": 1 }, "names": { "$push": "$username" } } },
+            doc! { "$sort": { "_id": 1 } },
+        ]).unwrap();
+
+        assert_eq!(groups, vec![
+            doc! { "_id": "admin", "amount": 1, "names": ["alice"] },
+            doc! { "_id": "user", "amount": 2, "names": ["bob", "carl"] },
+        ]);
+    }
+
+    #[test]
+    fn lookup_stage() {
+        let database = "test_lookup";
+        let users = users(database);
+        MemoryStore::new(database, "roles").insert_many(vec![
+            doc! { "name": "admin", "level": 10 },
+            doc! { "name": "user", "level": 1 },
+        ]).unwrap();
+
+        let joined = users.aggregate(vec![
+            doc! { "$match": { "username": "alice" } },
+            doc! { "$lookup": { "from": "roles", "localField": "role", "foreignField": "name", "as": "role_info" } },
+            doc! { "$project": { "_id": 0, "username": 1, "role_info.level": 1 } },
+        ]).unwrap();
+
+        assert_eq!(joined, vec![doc! { "username": "alice", "role_info": [{ "level": 10 }] }]);
+    }
+
+    /*- Commands go through store::open, the session has to be in memory -*/
+    fn session(database:&str) -> Session {
+        let mut session = Session::new().in_memory();
+        session.execute(format!("database switch {}", database).as_str());
+        return session;
+    }
+
+    #[test]
+    fn get_command() {
+        users("test_get_command");
+        let mut session = session("test_get_command");
+
+        let result = session.execute("get all where role is user");
+        assert_eq!(usernames(result.documents), ["bob", "carl"]);
+
+        let result = session.execute("get length of all");
+        assert!(result.lines.iter().any(|line| line.text.contains('3')));
+    }
+
+    #[test]
+    fn delete_command() {
+        let users = users("test_delete_command");
+        let mut session = session("test_delete_command");
+
+        /*- Deleting is confirmed first, embedded sessions decline unless told otherwise -*/
+        session.execute("delete all where username is carl");
+        assert_eq!(users.count(doc! {}).unwrap(), 3);
+
+        session.set_confirm_answer(true);
+        session.execute("delete all where username is carl");
+        assert_eq!(usernames(users.find(doc! {}, None).unwrap()), ["alice", "bob"]);
+    }
+
+    #[test]
+    fn sessions_keep_their_own_collection() {
+        MemoryStore::new("test_sessions", "things").insert_one(doc! { "username": "thing" }).unwrap();
+        users("test_sessions");
+
+        let mut first = session("test_sessions");
+        let mut second = session("test_sessions");
+        first.execute("collection switch things");
+
+        assert_eq!(usernames(first.execute("get all").documents), ["thing"]);
+        assert_eq!(usernames(second.execute("get all").documents), ["alice", "bob", "carl"]);
+    }
+}
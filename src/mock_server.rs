@@ -0,0 +1,683 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Document };
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde_json::{ json, Value };
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tiny_http::{ Header, Method, Request, Response, Server };
+use crate::{ commands, config, dates, images, output_handler, seed, users };
+use crate::config::Variables;
+use crate::memory_store::MemoryStore;
+use crate::store::DocumentStore;
+
+/*- Used when --port isn't given -*/
+static DEFAULT_PORT: u16 = 4000;
+
+/*- Amount of threads handling requests -*/
+static WORKERS: usize = 4;
+
+/*- The API hardcodes the role of new accounts -*/
+static NEW_USER_ROLE: &str = "user";
+
+/*- Database name of the in-memory user list -*/
+static MOCK_DATABASE: &str = "mock-server";
+
+lazy_static! {
+    #[allow(deprecated)]
+    static ref RUNNING: Mutex<Option<MockServer>> = Mutex::new(None);
+}
+
+/*- A server started with <mock-server start> -*/
+struct MockServer {
+    server: Arc<Server>,
+    state: Arc<MockState>,
+    workers: Vec<JoinHandle<()>>,
+    url: String,
+}
+
+/*- Where the mock server keeps its accounts, uploaded images stay in memory unless it runs against the database -*/
+struct Store {
+    users: Box<dyn DocumentStore>,
+    images: Option<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl Store {
+    fn find_by(&self, field:&str, value:&str) -> Result<Option<Document>, String> {
+        return self.users.find_one(doc! { field: value });
+    }
+
+    fn find_suids(&self, suids:&[String]) -> Result<Vec<Document>, String> {
+        return self.users.find(doc! { "suid": { "$in": suids } }, None);
+    }
+
+    fn insert(&self, user:Document) -> Result<(), String> {
+        self.users.insert_one(user)?;
+        return Ok(());
+    }
+
+    /*- Same as /api/add-friend, adds <friend> or removes it if it's already there -*/
+    fn toggle_friend(&self, user:&Document, friend:&str) -> Result<(), String> {
+        let suid = user.get_str("suid").map_err(|e| e.to_string())?;
+        let is_friend = user.get_array("friends")
+            .map(|friends| friends.iter().any(|f| f.as_str() == Some(friend)))
+            .map_err(|e| e.to_string())?;
+
+        let update = if is_friend {
+            doc! { "$pull": { "friends": friend } }
+        }else {
+            doc! { "$push": { "friends": friend } }
+        };
+
+        self.users.update_one(doc! { "suid": suid }, update, None)?;
+        return Ok(());
+    }
+
+    /*- The uploaded image of a suid, None means the default image -*/
+    fn read_image(&self, suid:&str) -> Option<Vec<u8>> {
+        return match &self.images {
+            Some(images) => images.lock().unwrap().get(suid).cloned(),
+            None => std::fs::read(users::profile_image_path(suid)).ok(),
+        };
+    }
+
+    fn write_image(&self, suid:&str, bytes:Vec<u8>) -> Result<(), String> {
+        return match &self.images {
+            Some(images) => {
+                images.lock().unwrap().insert(suid.to_string(), bytes);
+                Ok(())
+            },
+            None => images::write_profile_image(suid, &bytes),
+        };
+    }
+}
+
+/*- A recorded response, one per line in the replay file -*/
+#[derive(Debug, Clone, Deserialize)]
+struct Recording {
+    method: String,
+
+    /*- Route without the api/ prefix, like "login" -*/
+    route: String,
+
+    #[serde(default = "default_status")]
+    status: u16,
+    body: Value,
+}
+
+fn default_status() -> u16 { 200 }
+
+/*- Injected faults, applied to every request before it's handled -*/
+#[derive(Debug, Clone, Default)]
+struct Faults {
+    /*- Milliseconds, a random value in the range is picked per request -*/
+    latency: Option<(u64, u64)>,
+
+    /*- Share of requests answered with HTTP 500 -*/
+    error_rate: f64,
+
+    /*- Share of requests answered with cut off JSON -*/
+    malformed_rate: f64,
+}
+
+/*- Everything the request handlers need -*/
+struct MockState {
+    store: Store,
+    faults: Faults,
+    replay: Mutex<Vec<Recording>>,
+    dictionary: serde_yaml::Value,
+    variables: Variables,
+    reserved: Vec<String>,
+    url: String,
+}
+
+impl MockState {
+    /*- A message from data/dict.yml like "error.login.missing_fields" -*/
+    fn message(&self, path:&str) -> Option<String> {
+        return path.split('.')
+            .try_fold(&self.dictionary, |value, key| value.get(key))
+            .and_then(|value| value.as_str())
+            .map(|message| message.to_string());
+    }
+
+    /*- The profile url baked into new accounts, the mock serves the images itself without a CDN_URL -*/
+    fn profile_url(&self, suid:&str) -> String {
+        return users::expected_profile_url(suid)
+            .unwrap_or_else(|| format!("{}/api/profile-data/image/{}", self.url, suid));
+    }
+
+    /*- Take the next recording for a route, if there is one left -*/
+    fn next_recording(&self, method:&str, route:&str) -> Option<Recording> {
+        let mut replay = self.replay.lock().unwrap();
+        let index = replay.iter().position(|r| r.method.eq_ignore_ascii_case(method) && r.route.trim_start_matches('/') == route)?;
+        return Some(replay.remove(index));
+    }
+}
+
+/*- What a handler responds with -*/
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(body:Value) -> Reply {
+        return Reply::json_status(200, body);
+    }
+
+    fn json_status(status:u16, body:Value) -> Reply {
+        return Reply { status, content_type: "application/json", body: body.to_string().into_bytes() };
+    }
+
+    /*- res.sendStatus(), express answers with the status text -*/
+    fn status(status:u16, text:&str) -> Reply {
+        return Reply { status, content_type: "text/plain", body: text.as_bytes().to_vec() };
+    }
+}
+
+/*- { message, status } the way the API responds with errors. Undefined messages are left out -*/
+fn envelope(status:u16, message:Option<String>) -> Value {
+    return match message {
+        Some(message) => json!({ "message": message, "status": status }),
+        None => json!({ "status": status }),
+    };
+}
+
+/*- A header of the request, tiny_http compares the names case insensitively -*/
+fn header(request:&Request, name:&str) -> Option<String> {
+    return request.headers().iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str().to_string())
+        .filter(|value| !value.is_empty());
+}
+
+/*- A cookie of the request, like req.cookies[name] -*/
+fn cookie(request:&Request, name:&str) -> Option<String> {
+    return header(request, "cookie")?
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string());
+}
+
+/*- The fields the API sends back from create-account and login -*/
+fn account_data(user:&Document) -> Value {
+    return json!({
+        "uid": user.get_str("uid").ok(),
+        "suid": user.get_str("suid").ok(),
+        "username": user.get_str("username").ok(),
+        "displayname": user.get_str("displayname").ok(),
+        "profile": user.get_str("profile").ok(),
+    });
+}
+
+/*- The SafeUser model, missing fields are left out like undefined in JS -*/
+fn safe_user(state:&MockState, user:&Document) -> Value {
+    let mut safe = serde_json::Map::new();
+
+    for field in ["suid", "username", "displayname", "joined", "role", "friends"] {
+        if let Some(value) = user.get(field) {
+            safe.insert(field.to_string(), value.clone().into_relaxed_extjson());
+        }
+    }
+
+    /*- SafeUser always generates the profile url -*/
+    let suid = user.get_str("suid").unwrap_or("undefined");
+    safe.insert(String::from("profile"), Value::String(state.profile_url(suid)));
+
+    return Value::Object(safe);
+}
+
+/*- Same checks and order as checkUsername in routes/Api.js -*/
+fn check_username(state:&MockState, username:&str) -> Result<(), String> {
+    let illegal = username.chars().any(|c| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'));
+    let error = if username.len() > state.variables.username_len_max {
+        "error.username.too_long"
+    }else if username.len() < state.variables.username_len_min {
+        "error.username.too_short"
+    }else if illegal {
+        "error.username.illegal"
+    }else if state.reserved.iter().any(|reserved| reserved == username) {
+        "error.username.reserved"
+    }else {
+        match state.store.find_by("username", username)? {
+            Some(_) => "error.username.occupied",
+            None => return Ok(()),
+        }
+    };
+
+    return Err(state.message(error).unwrap_or_default());
+}
+
+/*- api/create-account -*/
+fn create_account(state:&MockState, request:&Request) -> Result<Reply, String> {
+    let email = header(request, "email");
+    let username = header(request, "username");
+    let displayname = header(request, "displayname");
+    let password = header(request, "password");
+
+    /*- Same priority as the API, the most important missing field is reported -*/
+    let missing = [("email", &email), ("password", &password), ("username", &username), ("displayname", &displayname)]
+        .into_iter()
+        .find(|(_, value)| value.is_none())
+        .map(|(name, _)| name);
+    if let Some(missing) = missing {
+        let message = format!("{} {}", state.message("missing_fields").unwrap_or_default(), missing);
+        return Ok(Reply::json(envelope(400, Some(message))));
+    }
+
+    let (email, username, displayname, password) = (email.unwrap(), username.unwrap(), displayname.unwrap(), password.unwrap());
+
+    if let Err(message) = check_username(state, &username) {
+        return Ok(Reply::json(envelope(400, Some(message))));
+    }
+    if state.store.find_by("email", &email)?.is_some() {
+        return Ok(Reply::json(envelope(400, state.message("illegal_email"))));
+    }
+
+    let mut rng = ChaCha8Rng::from_entropy();
+    let (salt, hash) = seed::hash_password(&mut rng, &password);
+    let suid = users::generate_suid();
+    let now = chrono::Utc::now().timestamp_millis();
+    let tz = dates::parse_timezone(&config::timezone())?;
+
+    let user = doc! {
+        "uid": seed::random_uuid(&mut rng),
+        "suid": &suid,
+        "salt": salt,
+        "email": email,
+        "username": username,
+        "displayname": displayname,
+        "joined": {
+            "prettified": dates::prettify(now, &tz, &state.variables),
+            "unix": now,
+        },
+        "role": NEW_USER_ROLE,
+        "password": hash,
+        "profile": state.profile_url(&suid),
+        "friends": [],
+    };
+    state.store.insert(user.clone())?;
+
+    return Ok(Reply::json(json!({
+        "status": 200,
+        "message": state.message("account_created"),
+        "data": account_data(&user),
+    })));
+}
+
+/*- api/login -*/
+fn login(state:&MockState, request:&Request) -> Result<Reply, String> {
+    let (email, password) = match (header(request, "email"), header(request, "password")) {
+        (Some(email), Some(password)) => (email, password),
+        _ => return Ok(Reply::json(envelope(400, state.message("error.login.missing_fields")))),
+    };
+
+    let user = state.store.find_by("email", &email)?.filter(|user| {
+        let salt = user.get_str("salt").unwrap_or("");
+        return user.get_str("password") == Ok(seed::hash_with_salt(&password, salt).as_str());
+    });
+
+    return Ok(match user {
+        Some(user) => Reply::json(json!({
+            "message": state.message("status.success"),
+            "status": 200,
+            "data": account_data(&user),
+        })),
+        None => Reply::json(envelope(400, state.message("error.login.invalid_credentials"))),
+    });
+}
+
+/*- api/profile-data -*/
+fn profile_data(state:&MockState, request:&Request) -> Result<Reply, String> {
+    let suid = match header(request, "suid") {
+        Some(suid) => suid,
+        None => return Ok(Reply::json(envelope(400, state.message("error.missing_fields")))),
+    };
+
+    return Ok(match state.store.find_by("suid", &suid)? {
+        Some(user) => Reply::json(json!({ "data": safe_user(state, &user), "status": 200 })),
+        None => Reply::json(envelope(404, state.message("error.user.not_found"))),
+    });
+}
+
+/*- api/profile-data/image/:suid, falls back to the default image -*/
+fn profile_image(state:&MockState, suid:&str) -> Result<Reply, String> {
+    let body = match state.store.read_image(suid) {
+        Some(bytes) => bytes,
+        None => std::fs::read(images::DEFAULT_IMAGE).map_err(|e| format!("Failed to read {}: {}", images::DEFAULT_IMAGE, e))?,
+    };
+
+    return Ok(Reply { status: 200, content_type: "image/jpeg", body });
+}
+
+/*- Position of <needle> in <haystack> starting at <from> -*/
+fn find_bytes(haystack:&[u8], needle:&[u8], from:usize) -> Option<usize> {
+    return haystack.get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from);
+}
+
+/*- The filename and bytes of a multipart/form-data field -*/
+fn multipart_file(content_type:&str, body:&[u8], field:&str) -> Option<(String, Vec<u8>)> {
+    let boundary = format!("--{}", content_type.split("boundary=").nth(1)?.trim_matches('"'));
+    let name = format!("name=\"{}\"", field);
+
+    let mut start = find_bytes(body, boundary.as_bytes(), 0)? + boundary.len();
+    while let Some(end) = find_bytes(body, boundary.as_bytes(), start) {
+        let part = &body[start..end];
+        start = end + boundary.len();
+
+        let headers_end = match find_bytes(part, b"\r\n\r\n", 0) {
+            Some(position) => position,
+            None => continue,
+        };
+        let headers = String::from_utf8_lossy(&part[..headers_end]);
+        if !headers.contains(&name) { continue; }
+
+        let filename = headers.split("filename=\"").nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap_or("")
+            .to_string();
+
+        /*- The data ends with \r\n before the next boundary -*/
+        let data = &part[headers_end + 4..];
+        return Some((filename, data.strip_suffix(b"\r\n").unwrap_or(data).to_vec()));
+    }
+
+    return None;
+}
+
+/*- api/profile-upload, authenticated with the suid cookie -*/
+fn profile_upload(state:&MockState, request:&mut Request) -> Result<Reply, String> {
+    let suid = cookie(request, "suid");
+    let content_type = header(request, "content-type").unwrap_or_default();
+
+    let mut body = Vec::new();
+    request.as_reader().read_to_end(&mut body).map_err(|e| e.to_string())?;
+
+    let (filename, bytes) = match (suid.as_ref(), multipart_file(&content_type, &body, "profile-file")) {
+        (Some(_), Some(file)) => file,
+        _ => return Ok(Reply::status(404, "Not Found")),
+    };
+
+    /*- multer's fileFilter, express answers the error with a 500 -*/
+    let extension = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    if !["jpg", "jpeg", "png"].contains(&extension.as_str()) {
+        return Ok(Reply::status(500, "Error: File must be an image"));
+    }
+
+    let image = image::load_from_memory(&bytes).map_err(|e| format!("Failed to decode upload: {}", e))?;
+    state.store.write_image(&suid.unwrap(), images::encode_profile_image(&image)?)?;
+
+    return Ok(Reply::status(200, "OK"));
+}
+
+/*- api/add-friend -*/
+fn add_friend(state:&MockState, request:&Request) -> Result<Reply, String> {
+    let (suid, friend) = match (header(request, "suid"), header(request, "friend")) {
+        (Some(suid), Some(friend)) => (suid, friend),
+        _ => return Ok(Reply::json(envelope(400, state.message("error.missing_fields")))),
+    };
+
+    let user = match state.store.find_by("suid", &suid)? {
+        Some(user) => user,
+        None => return Ok(Reply::json(envelope(404, state.message("error.user.not_found")))),
+    };
+
+    return Ok(match state.store.toggle_friend(&user, &friend) {
+        Ok(_) => Reply::json(envelope(200, None)),
+        Err(_) => Reply::json(envelope(404, None)),
+    });
+}
+
+/*- api/get-friends-data -*/
+fn get_friends_data(state:&MockState, request:&Request) -> Result<Reply, String> {
+    let suid = match header(request, "suid") {
+        Some(suid) => suid,
+        None => return Ok(Reply::json(envelope(400, state.message("error.missing_fields")))),
+    };
+
+    let user = match state.store.find_by("suid", &suid)? {
+        Some(user) => user,
+        None => return Ok(Reply::json(envelope(404, state.message("error.user.not_found")))),
+    };
+
+    let friends:Vec<String> = user.get_array("friends")
+        .map(|friends| friends.iter().filter_map(|f| f.as_str()).map(|f| f.to_string()).collect())
+        .unwrap_or_default();
+    let data:Vec<Value> = state.store.find_suids(&friends)?
+        .iter()
+        .map(|friend| safe_user(state, friend))
+        .collect();
+
+    return Ok(Reply::json(json!({ "data": data, "status": 200 })));
+}
+
+/*- Route a request to its handler -*/
+fn route(state:&MockState, request:&mut Request) -> Reply {
+    let method = request.method().clone();
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+
+    let route = match path.strip_prefix("/api/") {
+        Some(route) => route.to_string(),
+        None => return Reply::status(404, "Not Found"),
+    };
+
+    if let Some(recording) = state.next_recording(method.as_str(), &route) {
+        return Reply::json_status(recording.status, recording.body);
+    }
+
+    let result = match (&method, route.as_str()) {
+        (Method::Post, "create-account") => create_account(state, request),
+        (Method::Get, "login") => login(state, request),
+        (Method::Post, "profile-upload") => profile_upload(state, request),
+        (Method::Get, "profile-data") => profile_data(state, request),
+        (Method::Get, image) if image.starts_with("profile-data/image/") => profile_image(state, &image["profile-data/image/".len()..]),
+        (Method::Post, "add-friend") => add_friend(state, request),
+        (Method::Get, "get-friends-data") => get_friends_data(state, request),
+        _ => return Reply::status(404, "Not Found"),
+    };
+
+    return match result {
+        Ok(reply) => reply,
+        Err(e) => Reply::json_status(500, envelope(500, Some(e))),
+    };
+}
+
+/*- Apply the faults and answer a request -*/
+fn handle(state:&MockState, mut request:Request) {
+    let mut rng = rand::thread_rng();
+
+    if let Some((min, max)) = state.faults.latency {
+        std::thread::sleep(Duration::from_millis(rng.gen_range(min..=max)));
+    }
+
+    let reply = if rng.gen_bool(state.faults.error_rate) {
+        Reply::json_status(500, envelope(500, state.message("status.internal")))
+    }else {
+        let mut reply = route(state, &mut request);
+
+        /*- Cut the body in half so that it can't be parsed -*/
+        if reply.content_type == "application/json" && rng.gen_bool(state.faults.malformed_rate) {
+            reply.body.truncate(reply.body.len() / 2);
+        }
+        reply
+    };
+
+    let response = Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(Header::from_bytes("Content-Type", reply.content_type).unwrap());
+
+    /*- The client might have given up already -*/
+    request.respond(response).ok();
+}
+
+/*- Parse --latency <ms> or --latency <min>-<max> -*/
+fn parse_latency(input:&str) -> Result<(u64, u64), String> {
+    let invalid = || format!("<{}> is not a latency like 200 or 50-500", input);
+    let (min, max) = input.split_once('-').unwrap_or((input, input));
+
+    let min = min.parse::<u64>().map_err(|_| invalid())?;
+    let max = max.parse::<u64>().map_err(|_| invalid())?;
+    if min > max { return Err(invalid()); }
+
+    return Ok((min, max));
+}
+
+/*- Parse a rate between 0 and 1 -*/
+fn parse_rate(input:&str) -> Result<f64, String> {
+    return match input.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("<{}> is not a rate between 0 and 1", input)),
+    };
+}
+
+/*- Read recorded responses, one JSON object per line -*/
+fn load_replay(path:&str) -> Result<Vec<Recording>, String> {
+    let file = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    return file.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| serde_json::from_str::<Recording>(line)
+            .map_err(|e| format!("{}:{}: {}", path, index + 1, e)))
+        .collect();
+}
+
+/*- Start the server in the background and point the API commands to it -*/
+fn start(argv:&mut Vec<String>) -> Result<String, String> {
+    let mut running = RUNNING.lock().unwrap();
+    if let Some(server) = running.as_ref() {
+        return Err(format!("The mock server is already running on {}", server.url));
+    }
+
+    let port = match commands::take_option(argv, "--port") {
+        Some(port) => port.parse::<u16>().map_err(|_| format!("<{}> is not a port", port))?,
+        None => DEFAULT_PORT,
+    };
+    let faults = Faults {
+        latency: commands::take_option(argv, "--latency").map(|l| parse_latency(&l)).transpose()?,
+        error_rate: commands::take_option(argv, "--error-rate").map(|r| parse_rate(&r)).transpose()?.unwrap_or(0.0),
+        malformed_rate: commands::take_option(argv, "--malformed-rate").map(|r| parse_rate(&r)).transpose()?.unwrap_or(0.0),
+    };
+    let replay = match commands::take_option(argv, "--replay") {
+        Some(path) => load_replay(&path)?,
+        None => Vec::new(),
+    };
+    let store = if commands::take_flag(argv, "--mongo") {
+        Store { users: users::users_collection(), images: None }
+    }else {
+        /*- Start from an empty user list, an earlier mock server may have left accounts behind -*/
+        let users = MemoryStore::new(MOCK_DATABASE, "users");
+        users.delete_many(doc! {})?;
+        Store { users: Box::new(users), images: Some(Mutex::new(HashMap::new())) }
+    };
+    if !argv.is_empty() {
+        return Err(format!("Unknown arguments: {}", argv.join(" ")));
+    }
+
+    let dictionary_file = std::fs::read_to_string("data/dict.yml")
+        .map_err(|e| format!("Failed to read data/dict.yml: {}", e))?;
+    let dictionary = serde_yaml::from_str::<serde_yaml::Value>(&dictionary_file)
+        .map_err(|e| format!("Failed to parse data/dict.yml: {}", e))?
+        .get("dictionary")
+        .cloned()
+        .ok_or("data/dict.yml has no dictionary")?;
+
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+    let url = format!("http://127.0.0.1:{}", port);
+
+    let state = Arc::new(MockState {
+        store,
+        faults,
+        replay: Mutex::new(replay),
+        dictionary,
+        variables: config::load_variables()?,
+        reserved: config::load_reserved_usernames()?,
+        url: url.clone(),
+    });
+    let server = Arc::new(server);
+    config::set_backend_url(Some(url.clone()));
+
+    let workers = (0..WORKERS).map(|_| {
+        let server = Arc::clone(&server);
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            /*- Stops when the server is unblocked -*/
+            for request in server.incoming_requests() {
+                handle(&state, request);
+            }
+        })
+    }).collect();
+
+    *running = Some(MockServer { server, state, workers, url: url.clone() });
+    return Ok(url);
+}
+
+/*- Stop the server, the API commands go back to BACKEND_URL -*/
+fn stop() -> Result<String, String> {
+    let server = RUNNING.lock().unwrap().take().ok_or("The mock server isn't running")?;
+
+    for _ in &server.workers { server.server.unblock(); }
+    for worker in server.workers { worker.join().ok(); }
+
+    config::set_backend_url(None);
+
+    return Ok(server.url);
+}
+
+/*- Remove users from the in-memory store. None if no in-memory mock server is running -*/
+pub fn remove_users(suids:&[String]) -> Option<Result<usize, String>> {
+    let running = RUNNING.lock().unwrap();
+    let state = &running.as_ref()?.state.store;
+    let images = state.images.as_ref()?;
+
+    for suid in suids { images.lock().unwrap().remove(suid); }
+    return Some(remove_from(state.users.as_ref(), suids));
+}
+
+fn remove_from(users:&dyn DocumentStore, suids:&[String]) -> Result<usize, String> {
+    users.update_many(doc! {}, doc! { "$pull": { "friends": { "$in": suids } } }, None)?;
+    return users.delete_many(doc! { "suid": { "$in": suids } }).map(|count| count as usize);
+}
+
+/*- Serve the routes of routes/Api.js locally -*/
+pub fn mock_server(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let subcommand = argv.remove(0);
+
+    match subcommand.as_str() {
+        "start" => match start(&mut argv) {
+            Ok(url) => output_handler::throw_res(Color::Green, format!("Mock server running on {}, the api commands use it", url).as_str()),
+            Err(e) => output_handler::throw_res(Color::Red, &e),
+        },
+        "stop" => match stop() {
+            Ok(url) => output_handler::throw_res(Color::Green, format!("Stopped the mock server on {}", url).as_str()),
+            Err(e) => output_handler::throw_res(Color::Red, &e),
+        },
+        "status" => match RUNNING.lock().unwrap().as_ref() {
+            Some(server) => {
+                let store = &server.state.store;
+                let backend = if store.images.is_some() { "memory" } else { "database" };
+                let users = store.users.count(doc! {}).map(|count| count.to_string()).unwrap_or(String::from("-"));
+                output_handler::throw_res(Color::Green, format!("Running on {} ({} store, {} users)", server.url, backend, users).as_str());
+                output_handler::throw_res(Color::Cyan, format!("{:?}, {} recording(s) left", server.state.faults, server.state.replay.lock().unwrap().len()).as_str());
+            },
+            None => output_handler::throw_res(Color::Yellow, "The mock server isn't running"),
+        },
+        _ => output_handler::throw_res(Color::Red, "Invalid syntax! Write <help mock-server> for further information."),
+    }
+}
@@ -2,6 +2,108 @@
 use termcolor::{ Color };
 use termcolor::{ ColorChoice, ColorSpec, StandardStream, WriteColor };
 use std::io::Write;
+use std::cell::{ Cell, RefCell };
+use mongodb::bson::Document;
+use crate::dates;
+
+/*- What a printed line is, going by the colors: green = output, cyan = status, yellow = input, red = error -*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Output,
+    Status,
+    Prompt,
+    Error,
+}
+
+impl Kind {
+    fn from_color(clr:Color) -> Kind {
+        return match clr {
+            Color::Red | Color::Rgb(255, 0, 0) => Kind::Error,
+            Color::Yellow => Kind::Prompt,
+            Color::Cyan => Kind::Status,
+            _ => Kind::Output,
+        };
+    }
+}
+
+/*- One line a command printed -*/
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub kind: Kind,
+    pub text: String,
+}
+
+/*- Everything that was printed between start_capture and finish_capture -*/
+#[derive(Debug, Clone, Default)]
+pub struct Captured {
+    pub lines: Vec<Line>,
+    pub documents: Vec<Document>,
+}
+
+/*- Output held back from the terminal, for piping it into the next stage -*/
+#[derive(Debug, Clone, Default)]
+pub struct Held {
+    pub documents: Vec<Document>,
+
+    /*- Output lines that weren't documents -*/
+    pub lines: Vec<String>,
+
+    /*- Everything in the order it was printed, for release. A line without a color was printed raw -*/
+    printed: Vec<Printed>,
+}
+
+#[derive(Debug, Clone)]
+enum Printed {
+    Line(Option<Color>, String),
+    Document(Document),
+}
+
+thread_local! {
+    /*- (what was printed so far, print it as well) -*/
+    static CAPTURE: RefCell<Option<(Captured, bool)>> = const { RefCell::new(None) };
+
+    /*- The innermost start_hold is last -*/
+    static HOLD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+
+    /*- Amount of error lines printed on this thread -*/
+    static ERRORS: Cell<usize> = const { Cell::new(0) };
+}
+
+/*- Keep output lines and documents from being printed until finish_hold, status and errors still go through -*/
+pub fn start_hold() {
+    HOLD.with(|hold| hold.borrow_mut().push(Held::default()));
+}
+
+pub fn finish_hold() -> Held {
+    return HOLD.with(|hold| hold.borrow_mut().pop()).unwrap_or_default();
+}
+
+/*- Print what was held after all, in the same order and colors as it would have been -*/
+pub fn release(held:&Held) {
+    for printed in &held.printed {
+        match printed {
+            Printed::Line(Some(clr), line) => throw_res(*clr, line),
+            Printed::Line(None, line) => throw_raw(line),
+            Printed::Document(document) => throw_document(document),
+        }
+    }
+}
+
+/*- Compare before and after running something to see if it failed -*/
+pub fn error_count() -> usize {
+    return ERRORS.with(|errors| errors.get());
+}
+
+/*- Record everything this thread prints until finish_capture, echo = still print it to the terminal -*/
+pub fn start_capture(echo:bool) {
+    CAPTURE.with(|capture| *capture.borrow_mut() = Some((Captured::default(), echo)));
+}
+
+pub fn finish_capture() -> Captured {
+    return CAPTURE.with(|capture| capture.borrow_mut().take())
+        .map(|(captured, _)| captured)
+        .unwrap_or_default();
+}
 
 /*- Because when we change the terminal color, 
     it will keep the same color for future lines -*/
@@ -12,8 +114,54 @@ fn reset_terminal_color(stdout: &mut StandardStream) {
             .unwrap();
 }
 
+/*- Amount of documents captured so far, for documents_since -*/
+pub fn capture_mark() -> usize {
+    return CAPTURE.with(|capture| capture.borrow().as_ref().map(|(captured, _)| captured.documents.len()).unwrap_or(0));
+}
+
+/*- The documents captured after capture_mark returned <mark> -*/
+pub fn documents_since(mark:usize) -> Vec<Document> {
+    return CAPTURE.with(|capture| capture.borrow().as_ref()
+        .map(|(captured, _)| captured.documents.iter().skip(mark).cloned().collect())
+        .unwrap_or_default());
+}
+
+/*- Hold or capture a line, returns if it should still be printed -*/
+fn record(clr:Option<Color>, msg:&str) -> bool {
+    let kind = clr.map(Kind::from_color).unwrap_or(Kind::Output);
+    if kind == Kind::Error {
+        ERRORS.with(|errors| errors.set(errors.get() + 1));
+    }else if kind == Kind::Output {
+        let held = HOLD.with(|hold| match hold.borrow_mut().last_mut() {
+            Some(held) => {
+                held.lines.push(msg.to_string());
+                held.printed.push(Printed::Line(clr, msg.to_string()));
+                true
+            },
+            None => false,
+        });
+        if held { return false; }
+    }
+
+    return CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+        Some((captured, echo)) => {
+            captured.lines.push(Line { kind, text: msg.to_string() });
+            *echo
+        },
+        None => true,
+    });
+}
+
+/*- Print machine readable output like JSON as is, color codes would break it for other programs -*/
+pub fn throw_raw(msg:&str) {
+    if !record(None, msg) { return; }
+    println!("{}", msg);
+}
+
 /*- Print a response with colors -*/
 pub fn throw_res(clr:Color, msg:&str) {
+    if !record(Some(clr), msg) { return; }
+
     /*- Set new standard output -*/
     let mut stdout = StandardStream::stdout(ColorChoice::Always);
 
@@ -28,4 +176,36 @@ pub fn throw_res(clr:Color, msg:&str) {
 
     /*- Reset the color -*/
     reset_terminal_color(&mut stdout);
-}
\ No newline at end of file
+}
+
+/*- Print a document, every command that outputs documents should use this -*/
+pub fn throw_document(doc:&Document) {
+    let held = HOLD.with(|hold| match hold.borrow_mut().last_mut() {
+        Some(held) => {
+            held.documents.push(doc.clone());
+            held.printed.push(Printed::Document(doc.clone()));
+            true
+        },
+        None => false,
+    });
+    if held { return; }
+
+    CAPTURE.with(|capture| if let Some((captured, _)) = capture.borrow_mut().as_mut() {
+        captured.documents.push(doc.clone());
+    });
+
+    /*- The document itself is kept as stored, only what's printed has the computed join date -*/
+    throw_res(Color::Green, &dates::for_display(doc).to_string());
+}
+
+/*- Print an ASCII histogram, the longest bar is always the same width -*/
+pub fn throw_histogram(rows:&[(String, usize)]) {
+    let max = rows.iter().map(|(_, amount)| *amount).max().unwrap_or(0).max(1);
+    let label_len = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+
+    for (label, amount) in rows {
+        throw_res(Color::Green, format!("{:>width$}: {:>5} {}",
+            label, amount, "#".repeat((amount * 40).div_ceil(max)), width = label_len
+        ).as_str());
+    }
+}
@@ -0,0 +1,262 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Bson, Document };
+use std::fmt::Write as FmtWrite;
+use crate::{ commands, escape, memory_store, output_handler, variables };
+use crate::output_handler::Held;
+
+/*- How a command is chained to the one before it -*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    /*- First command, or after ; -*/
+    Always,
+
+    /*- After &&, only runs if the command before it printed no errors -*/
+    IfOk,
+}
+
+/*- A command and the stages it's piped through, stages[0] is the command -*/
+pub type Stages = Vec<Vec<String>>;
+
+/*- Split a command into params on whitespace, except inside double quotes. Commands that join
+    their params back together like <aggregate> and <cmd> get string literals back exactly as typed -*/
+pub fn split_words(text:&str) -> Vec<String> {
+    let mut words:Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && !quoted {
+            if !current.is_empty() { words.push(std::mem::take(&mut current)); }
+            continue;
+        }
+        if c == '\\' && quoted {
+            current.push(c);
+            if let Some(escaped) = chars.next() { current.push(escaped); }
+            continue;
+        }
+        if c == '"' { quoted = !quoted; }
+        current.push(c);
+    }
+    if !current.is_empty() { words.push(current); }
+
+    return words;
+}
+
+/*- Split a line on ; && and |. Nothing inside double quotes or braces is split, so JSON and
+    script blocks stay intact, and <| sh> takes the rest of the line as the shell command -*/
+pub fn parse(line:&str) -> Result<Vec<(Chain, Stages)>, String> {
+    let mut commands:Vec<(Chain, Stages)> = Vec::new();
+    let mut stages:Stages = Vec::new();
+    let mut current = String::new();
+    let mut chain = Chain::Always;
+    let mut quoted = false;
+    let mut depth = 0usize;
+
+    /*- An empty stage is fine at the end of a command, not between two | -*/
+    fn end_stage(stages:&mut Stages, current:&mut String, piped:bool) -> Result<(), String> {
+        let argv = split_words(current);
+        current.clear();
+
+        if argv.is_empty() {
+            if piped || !stages.is_empty() { return Err(String::from("Missing a command around |")); }
+            return Ok(());
+        }
+        stages.push(argv);
+        return Ok(());
+    }
+
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let in_shell = !stages.is_empty() && current.split_whitespace().next() == Some("sh");
+
+        if quoted || in_shell {
+            if c == '\\' {
+                current.push(c);
+                if let Some(escaped) = chars.next() { current.push(escaped); }
+                continue;
+            }
+            if c == '"' && !in_shell { quoted = false; }
+            current.push(c);
+            continue;
+        }
+
+        match c {
+            '"' => { quoted = true; current.push(c); },
+            '{' => { depth += 1; current.push(c); },
+            '}' => { depth = depth.saturating_sub(1); current.push(c); },
+            _ if depth > 0 => current.push(c),
+            '|' => end_stage(&mut stages, &mut current, true)?,
+            ';' => {
+                end_stage(&mut stages, &mut current, false)?;
+                if !stages.is_empty() { commands.push((chain, std::mem::take(&mut stages))); }
+                chain = Chain::Always;
+            },
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                end_stage(&mut stages, &mut current, false)?;
+                if !stages.is_empty() { commands.push((chain, std::mem::take(&mut stages))); }
+                chain = Chain::IfOk;
+            },
+            _ => current.push(c),
+        }
+    }
+
+    end_stage(&mut stages, &mut current, false)?;
+    if !stages.is_empty() { commands.push((chain, stages)); }
+
+    return Ok(commands);
+}
+
+/*- What a stage got from the one before it, as text for files and shell commands -*/
+fn as_text(input:&Held) -> String {
+    if input.documents.is_empty() {
+        return input.lines.iter().map(|line| format!("{}\n", line)).collect();
+    }
+    return input.documents.iter()
+        .map(|document| format!("{}\n", Bson::Document(document.clone()).into_relaxed_extjson()))
+        .collect();
+}
+
+/*- | fields email username -*/
+pub fn fields(argv:Vec<String>, input:Held) {
+    if input.documents.is_empty() && !input.lines.is_empty() {
+        output_handler::throw_res(Color::Red, "<fields> needs documents, the command before it printed text");
+        return;
+    }
+
+    /*- _id is only kept if it's asked for -*/
+    let mut projection = doc! { "_id": 0 };
+    for field in &argv {
+        projection.insert(field, 1);
+    }
+
+    for document in &input.documents {
+        match memory_store::project(document, &projection) {
+            Ok(document) => output_handler::throw_document(&document),
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        }
+    }
+}
+
+/*- | count, the amount of documents or lines -*/
+pub fn count(_:Vec<String>, input:Held) {
+    let amount = if input.documents.is_empty() { input.lines.len() } else { input.documents.len() };
+    output_handler::throw_res(Color::Green, &amount.to_string());
+}
+
+/*- Every field that any document has, in the order they first show up -*/
+fn to_csv(documents:&[Document]) -> String {
+    let mut columns:Vec<&String> = Vec::new();
+    for document in documents {
+        for key in document.keys() {
+            if !columns.contains(&key) { columns.push(key); }
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "{}", columns.iter().map(|column| escape::csv(column)).collect::<Vec<String>>().join(",")).unwrap();
+    for document in documents {
+        let row:Vec<String> = columns.iter()
+            .map(|column| document.get(column.as_str()).map(|value| escape::csv(&variables::format_value(value))).unwrap_or_default())
+            .collect();
+        writeln!(out, "{}", row.join(",")).unwrap();
+    }
+    return out;
+}
+
+/*- | export users.csv, the format is picked by the extension -*/
+pub fn export(argv:Vec<String>, input:Held) {
+    let file = &argv[0];
+
+    let out = if input.documents.is_empty() {
+        as_text(&input)
+    }else if file.ends_with(".csv") {
+        to_csv(&input.documents)
+    }else if file.ends_with(".json") {
+        let documents:Vec<serde_json::Value> = input.documents.iter()
+            .map(|document| Bson::Document(document.clone()).into_relaxed_extjson())
+            .collect();
+        serde_json::to_string_pretty(&documents).unwrap_or_default() + "\n"
+    }else if file.ends_with(".jsonl") || file.ends_with(".ndjson") {
+        as_text(&input)
+    }else {
+        output_handler::throw_res(Color::Red, "Documents can be exported to .csv, .json or .jsonl files");
+        return;
+    };
+
+    match std::fs::write(file, out) {
+        Ok(_) => output_handler::throw_res(Color::Cyan, format!("Exported {} {} to {}",
+            if input.documents.is_empty() { input.lines.len() } else { input.documents.len() },
+            if input.documents.is_empty() { "line(s)" } else { "document(s)" },
+            file
+        ).as_str()),
+        Err(e) => output_handler::throw_res(Color::Red, format!("Failed to write {}: {}", file, e).as_str()),
+    }
+}
+
+/*- | sh sort | uniq -c, the output goes to the shell command as one JSON document per line -*/
+pub fn sh(argv:Vec<String>, input:Held) {
+    commands::shell(&argv.join(" "), Some(&as_text(&input)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(stage:&[&str]) -> Vec<String> {
+        return stage.iter().map(|word| word.to_string()).collect();
+    }
+
+    #[test]
+    fn chains_and_stages() {
+        assert_eq!(parse("get all | fields email ; count && get all").unwrap(), vec![
+            (Chain::Always, vec![words(&["get", "all"]), words(&["fields", "email"])]),
+            (Chain::Always, vec![words(&["count"])]),
+            (Chain::IfOk, vec![words(&["get", "all"])]),
+        ]);
+        assert_eq!(parse("get all;").unwrap().len(), 1);
+        assert!(parse("get all | | count").is_err());
+        assert!(parse("| count").is_err());
+    }
+
+    #[test]
+    fn quotes_and_braces_are_not_split() {
+        assert_eq!(parse("aggregate [{\"$match\": {\"a\": \"x | y; z\"}}] | count").unwrap()[0].1, vec![
+            words(&["aggregate", "[{\"$match\":", "{\"a\":", "\"x | y; z\"}}]"]),
+            words(&["count"]),
+        ]);
+        assert_eq!(parse("script eval { let a = 1; a && true }").unwrap(), vec![
+            (Chain::Always, vec![words(&["script", "eval", "{", "let", "a", "=", "1;", "a", "&&", "true", "}"])]),
+        ]);
+
+        /*- An escaped quote doesn't end the string -*/
+        assert_eq!(parse("create name:\"a \\\" ; b\"").unwrap()[0].1, vec![words(&["create", "name:\"a \\\" ; b\""])]);
+    }
+
+    #[test]
+    fn sh_takes_the_rest() {
+        assert_eq!(parse("get all | sh sort | uniq -c && echo done").unwrap(), vec![
+            (Chain::Always, vec![words(&["get", "all"]), words(&["sh", "sort", "|", "uniq", "-c", "&&", "echo", "done"])]),
+        ]);
+    }
+
+    #[test]
+    fn split_words_keeps_quoted_whitespace() {
+        assert_eq!(split_words("  a \"b  c\"   d "), ["a", "\"b  c\"", "d"]);
+        assert_eq!(split_words("\"a \\\" b\" c"), ["\"a \\\" b\"", "c"]);
+        assert!(split_words("   ").is_empty());
+    }
+
+    #[test]
+    fn csv_escaping() {
+        let documents = vec![
+            doc! { "name": "plain", "note": "a,b" },
+            doc! { "name": "say \"hi\"", "lines": "one\ntwo", "count": 3 },
+        ];
+        assert_eq!(to_csv(&documents), "name,note,lines,count\nplain,\"a,b\",,\n\"say \"\"hi\"\"\",,\"one\ntwo\",3\n");
+    }
+}
@@ -0,0 +1,407 @@
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use crate::{ bench, commands::CommandStruct };
+
+/*- One way to call a command. The pattern is written like the usage:
+    word                 has to be typed as is
+    <name> <name:type>   an argument, <name>? is optional and <name>... takes the rest
+    --flag               a switch, flags are always optional
+    --flag <name:type>   an option with a value
+    types are int, number, rate, duration, date, objectid, keyval, tz, port or choices like day|week|month -*/
+#[derive(Debug, Clone, Copy)]
+pub struct Form<'a> {
+    pub pattern: &'a str,
+    pub summary: &'a str,
+}
+
+/*- A parsed pattern token -*/
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Arg(Arg<'a>),
+    Flag { name:&'a str, value:Option<Arg<'a>> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Arg<'a> {
+    name: &'a str,
+    kind: &'a str,
+    optional: bool,
+    rest: bool,
+}
+
+/*- Why argv didn't match a form. The form that got furthest gives the error -*/
+struct Mismatch {
+    words: usize,
+    consumed: usize,
+
+    /*- A value had the wrong type, more useful than a word that didn't match -*/
+    typed: bool,
+    message: String,
+}
+
+fn parse_arg(token:&str) -> Option<Arg<'_>> {
+    let (token, optional) = match token.strip_suffix('?') {
+        Some(token) => (token, true),
+        None => (token, false),
+    };
+    let (token, rest) = match token.strip_suffix("...") {
+        Some(token) => (token, true),
+        None => (token, false),
+    };
+    let inner = token.strip_prefix('<')?.strip_suffix('>')?;
+    let (name, kind) = inner.split_once(':').unwrap_or((inner, "text"));
+
+    return Some(Arg { name, kind, optional, rest });
+}
+
+/*- The specs are written by hand, a typo in one is a bug so it panics -*/
+fn tokenize(pattern:&str) -> Vec<Token<'_>> {
+    let mut tokens:Vec<Token> = Vec::new();
+    let mut words = pattern.split_whitespace().peekable();
+
+    while let Some(word) = words.next() {
+        if word.starts_with("--") {
+            let value = match words.peek() {
+                Some(next) if next.starts_with('<') => words.next().and_then(parse_arg),
+                _ => None,
+            };
+            tokens.push(Token::Flag { name: word, value });
+        }else if word.starts_with('<') {
+            tokens.push(Token::Arg(parse_arg(word).unwrap_or_else(|| panic!("Invalid argument {} in <{}>", word, pattern))));
+        }else {
+            tokens.push(Token::Word(word));
+        }
+    }
+
+    return tokens;
+}
+
+/*- Check a value against an argument type -*/
+fn check_value(arg:&Arg, value:&str) -> Result<(), String> {
+    let valid = match arg.kind {
+        "text" => true,
+        "int" => value.parse::<u64>().is_ok(),
+        "number" => value.parse::<f64>().is_ok(),
+        "rate" => value.parse::<f64>().map(|rate| (0.0..=1.0).contains(&rate)).unwrap_or(false),
+        "duration" => bench::parse_duration(value).is_ok(),
+        "date" => NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+        "objectid" => value.parse::<mongodb::bson::oid::ObjectId>().is_ok(),
+        "keyval" => value.contains(':'),
+        "tz" => value.parse::<Tz>().is_ok(),
+        "port" => value.parse::<u16>().is_ok(),
+        choices => choices.split('|').any(|choice| choice == value),
+    };
+
+    if valid { return Ok(()); }
+    return Err(format!("<{}> must be {}, got <{}>", arg.name, describe_kind(arg.kind), value));
+}
+
+/*- What a type accepts, for errors and the help page -*/
+fn describe_kind(kind:&str) -> String {
+    return match kind {
+        "text" => String::from("any text"),
+        "int" => String::from("a whole number"),
+        "number" => String::from("a number"),
+        "rate" => String::from("a number from 0 to 1"),
+        "duration" => String::from("a duration like 500ms, 30s or 2m"),
+        "date" => String::from("a date like 2024-01-31"),
+        "objectid" => String::from("an ObjectId"),
+        "keyval" => String::from("a key:value pair"),
+        "tz" => String::from("a timezone like Europe/Stockholm"),
+        "port" => String::from("a port number"),
+        choices => format!("one of {}", choices.split('|').collect::<Vec<&str>>().join(", ")),
+    };
+}
+
+/*- Match argv against one form -*/
+fn match_form(tokens:&[Token], argv:&[String]) -> Result<(), Mismatch> {
+    let takes_rest = tokens.iter().any(|token| matches!(token, Token::Arg(arg) if arg.rest));
+    let mut positional:Vec<&String> = Vec::new();
+    let mut unknown_flag:Option<&String> = None;
+    let mut flag_error:Option<String> = None;
+
+    /*- Flags can be anywhere, take them out first like take_flag does -*/
+    let mut args = argv.iter();
+    while let Some(arg) = args.next() {
+        let flag = tokens.iter().find_map(|token| match token {
+            Token::Flag { name, value } if *name == arg.as_str() => Some(value),
+            _ => None,
+        });
+
+        match flag {
+            Some(Some(value)) => match args.next() {
+                Some(given) => if let Err(e) = check_value(value, given) { flag_error.get_or_insert(e); },
+                None => { flag_error.get_or_insert(format!("{} needs a value <{}>", arg, value.name)); },
+            },
+            Some(None) => {},
+
+            /*- Commands like <cmd> pass their flags on -*/
+            None if arg.starts_with("--") && arg.len() > 2 && !takes_rest => { unknown_flag.get_or_insert(arg); },
+            None => positional.push(arg),
+        }
+    }
+
+    let mut words = 0;
+    let mut consumed = 0;
+    let mismatch = |words, consumed, message| Err(Mismatch { words, consumed, typed: false, message });
+
+    for token in tokens {
+        match token {
+            Token::Word(word) => match positional.get(consumed) {
+                Some(given) if given == word => { words += 1; consumed += 1; },
+                Some(given) => return mismatch(words, consumed, format!("Expected <{}>, got <{}>", word, given)),
+                None => return mismatch(words, consumed, format!("Missing <{}>", word)),
+            },
+            Token::Arg(arg) => {
+                if consumed >= positional.len() {
+                    if arg.optional { continue; }
+                    return mismatch(words, consumed, format!("Missing <{}>", arg.name));
+                }
+
+                let values = if arg.rest { &positional[consumed..] } else { &positional[consumed..consumed + 1] };
+                for value in values {
+                    if let Err(message) = check_value(arg, value) {
+                        return Err(Mismatch { words, consumed, typed: true, message });
+                    }
+                    consumed += 1;
+                }
+            },
+            Token::Flag { .. } => {},
+        }
+    }
+
+    if let Some(extra) = positional.get(consumed) {
+        return mismatch(words, consumed, format!("Unexpected argument <{}>", extra));
+    }
+    if let Some(flag) = unknown_flag {
+        return mismatch(words, consumed, format!("Unknown flag {}", flag));
+    }
+    if let Some(e) = flag_error {
+        return mismatch(words, consumed, e);
+    }
+    return Ok(());
+}
+
+/*- Check argv against every form of the command, Err has the lines to print -*/
+pub fn validate(cmd:&CommandStruct, argv:&[String]) -> Result<(), Vec<String>> {
+    let mut mismatches:Vec<(&Form, Mismatch)> = Vec::new();
+    for form in cmd._forms {
+        match match_form(&tokenize(form.pattern), argv) {
+            Ok(_) => return Ok(()),
+            Err(mismatch) => mismatches.push((form, mismatch)),
+        }
+    }
+
+    let best = mismatches.iter().map(|(_, m)| (m.words, m.consumed, m.typed)).max().unwrap_or((0, 0, false));
+    let subcommands = subcommands(cmd);
+    let all_subcommands = cmd._forms.iter().all(|form| matches!(tokenize(form.pattern).first(), Some(Token::Word(_))));
+
+    /*- Nothing matched the first word, list what it could have been -*/
+    if best.0 == 0 && !cmd._forms.is_empty() && all_subcommands {
+        let first = match argv.first() {
+            Some(first) => format!("Unknown subcommand <{}>", first),
+            None => format!("<{}> needs a subcommand", cmd._name),
+        };
+        let mut lines = vec![format!("{}, expected one of: {}", first, subcommands.join(", "))];
+        if let Some(suggestion) = argv.first().and_then(|first| suggest(first, subcommands.iter().copied())) {
+            lines.push(format!("Did you mean <{} {}>?", cmd._name, suggestion));
+        }
+        lines.push(format!("Write <help {}> for further information.", cmd._name));
+        return Err(lines);
+    }
+
+    let closest:Vec<&(&Form, Mismatch)> = mismatches.iter()
+        .filter(|(_, m)| (m.words, m.consumed, m.typed) == best)
+        .collect();
+
+    let mut lines = vec![closest[0].1.message.clone()];
+    for (form, _) in closest {
+        lines.push(format!("Usage: {}", usage(cmd._name, form)));
+    }
+    return Err(lines);
+}
+
+/*- Amount of single character edits to turn <a> into <b> -*/
+fn edit_distance(a:&str, b:&str) -> usize {
+    let b:Vec<char> = b.chars().collect();
+    let mut row:Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + if ca == *cb { 0 } else { 1 });
+            diagonal = above;
+        }
+    }
+
+    return row[b.len()];
+}
+
+/*- The closest candidate to a mistyped word, if any is close enough to be a typo -*/
+pub fn suggest<'a>(word:&str, candidates:impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max = (word.chars().count() / 3).clamp(1, 3);
+
+    return candidates.into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate);
+}
+
+/*- The first word of every form that starts with one -*/
+pub fn subcommands<'a>(cmd:&CommandStruct<'a>) -> Vec<&'a str> {
+    let mut subcommands:Vec<&str> = Vec::new();
+    for form in cmd._forms {
+        if let Some(Token::Word(word)) = tokenize(form.pattern).first() {
+            if !subcommands.contains(word) { subcommands.push(word); }
+        }
+    }
+    return subcommands;
+}
+
+/*- Commands that can't run without arguments are marked with a * in <help> -*/
+pub fn params_required(cmd:&CommandStruct) -> bool {
+    return !cmd._forms.iter().any(|form| match_form(&tokenize(form.pattern), &[]).is_ok());
+}
+
+/*- A form the way it was written in the old usage strings: command word <arg> --flag <value>? -*/
+pub fn usage(name:&str, form:&Form) -> String {
+    let show_arg = |arg:&Arg| {
+        let shown = if arg.kind.contains('|') {
+            format!("[{}]", arg.kind.split('|').collect::<Vec<&str>>().join(", "))
+        }else {
+            format!("<{}>", arg.name)
+        };
+        return format!("{}{}{}", shown, if arg.rest { "..." } else { "" }, if arg.optional { "?" } else { "" });
+    };
+
+    let mut parts:Vec<String> = vec![name.to_string()];
+    for token in tokenize(form.pattern) {
+        parts.push(match token {
+            Token::Word(word) => word.to_string(),
+            Token::Arg(arg) => show_arg(&arg),
+            Token::Flag { name, value: Some(value) } => format!("{} {}?", name, show_arg(&Arg { optional: false, ..value })),
+            Token::Flag { name, value: None } => format!("{}?", name),
+        });
+    }
+    return parts.join(" ");
+}
+
+/*- The full usage page of <help command> -*/
+pub fn help_page(cmd:&CommandStruct) -> Vec<String> {
+    let mut lines = vec![format!("{} - {}", cmd._name, cmd._summary), String::new(), String::from("Usage:")];
+
+    let usages:Vec<String> = cmd._forms.iter().map(|form| usage(cmd._name, form)).collect();
+    let width = usages.iter().map(|u| u.len()).max().unwrap_or(0);
+    for (form, usage) in cmd._forms.iter().zip(&usages) {
+        lines.push(format!("  {:width$}  {}", usage, form.summary, width = width).trim_end().to_string());
+    }
+
+    /*- Every typed argument once -*/
+    let mut typed:Vec<(String, String)> = Vec::new();
+    for form in cmd._forms {
+        for token in tokenize(form.pattern) {
+            let arg = match token {
+                Token::Arg(arg) => arg,
+                Token::Flag { value: Some(arg), .. } => arg,
+                _ => continue,
+            };
+            if arg.kind == "text" || arg.kind.contains('|') { continue; }
+
+            let entry = (format!("<{}>", arg.name), describe_kind(arg.kind));
+            if !typed.contains(&entry) { typed.push(entry); }
+        }
+    }
+    if !typed.is_empty() {
+        lines.push(String::new());
+        lines.push(String::from("Arguments:"));
+        let width = typed.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, description) in typed {
+            lines.push(format!("  {:width$}  {}", name, description, width = width));
+        }
+    }
+
+    if !cmd._examples.is_empty() {
+        lines.push(String::new());
+        lines.push(String::from("Examples:"));
+        for example in cmd._examples {
+            lines.push(format!("  {}", example));
+        }
+    }
+
+    return lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Handler;
+
+    static FORMS: [Form; 3] = [
+        Form { pattern: "list <user>", summary: "" },
+        Form { pattern: "add <a> <b> --mutual", summary: "" },
+        Form { pattern: "top <n:int> <field> --by <by:day|week|month>", summary: "" },
+    ];
+
+    fn command() -> CommandStruct<'static> {
+        return CommandStruct { _name: "test", _summary: "", _forms: &FORMS, _examples: &[], _bind: Handler::Command(|_| {}) };
+    }
+
+    fn validate(line:&str) -> Result<(), Vec<String>> {
+        let argv:Vec<String> = line.split_whitespace().map(String::from).collect();
+        return super::validate(&command(), &argv);
+    }
+
+    #[test]
+    fn tokenize_pattern() {
+        assert_eq!(tokenize("add <a> <rest:int>...? --by <by:day|week>"), vec![
+            Token::Word("add"),
+            Token::Arg(Arg { name: "a", kind: "text", optional: false, rest: false }),
+            Token::Arg(Arg { name: "rest", kind: "int", optional: true, rest: true }),
+            Token::Flag { name: "--by", value: Some(Arg { name: "by", kind: "day|week", optional: false, rest: false }) },
+        ]);
+    }
+
+    #[test]
+    fn matching_forms() {
+        assert!(validate("list alice").is_ok());
+        assert!(validate("add alice bob").is_ok());
+
+        /*- Flags can be anywhere -*/
+        assert!(validate("add --mutual alice bob").is_ok());
+        assert!(validate("top 3 role --by week").is_ok());
+    }
+
+    #[test]
+    fn closest_form_gives_the_error() {
+        assert_eq!(validate("list").unwrap_err(), ["Missing <user>", "Usage: test list <user>"]);
+        assert_eq!(validate("add alice bob carl").unwrap_err(), ["Unexpected argument <carl>", "Usage: test add <a> <b> --mutual?"]);
+        assert_eq!(validate("list alice --mutual").unwrap_err()[0], "Unknown flag --mutual");
+    }
+
+    #[test]
+    fn typed_argument_errors() {
+        assert_eq!(validate("top three role").unwrap_err()[0], "<n> must be a whole number, got <three>");
+        assert_eq!(validate("top 3 role --by year").unwrap_err()[0], "<by> must be one of day, week, month, got <year>");
+        assert_eq!(validate("top 3 role --by").unwrap_err()[0], "--by needs a value <by>");
+    }
+
+    #[test]
+    fn unknown_subcommand() {
+        assert_eq!(validate("lst alice").unwrap_err(), [
+            "Unknown subcommand <lst>, expected one of: list, add, top",
+            "Did you mean <test list>?",
+            "Write <help test> for further information.",
+        ]);
+        assert_eq!(validate("").unwrap_err()[0], "<test> needs a subcommand, expected one of: list, add, top");
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(suggest("colection", ["collection", "database"]), Some("collection"));
+        assert_eq!(suggest("xyz", ["collection", "database"]), None);
+    }
+}
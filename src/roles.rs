@@ -0,0 +1,142 @@
+use termcolor::{ Color };
+use mongodb::bson::doc;
+use crate::{ audit, commands, config, output_handler, users };
+use crate::store::DocumentStore;
+
+/*- Manage user roles -*/
+pub fn role(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    /*- Every subcommand validates against the role definitions -*/
+    let roles = match config::load_roles() {
+        Ok(roles) => roles,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+
+    let coll = users::users_collection();
+
+    if &argv[0] == "list" {
+        for (name, role) in &roles.roles {
+            let members = coll.count(doc! { "role": name }).unwrap_or(0);
+            output_handler::throw_res(Color::Green,
+                format!("{} ({} members) - {}", name, members, role.description).as_str()
+            );
+        }
+
+        /*- Users can have roles that aren't defined (anymore), point them out -*/
+        let defined:Vec<&String> = roles.roles.keys().collect();
+        let undefined = coll.count(doc! { "role": { "$nin": defined } }).unwrap_or(0);
+        if undefined > 0 {
+            output_handler::throw_res(Color::Red,
+                format!("{} user(s) have an undefined or missing role", undefined).as_str()
+            );
+        }
+    }
+    else if &argv[0] == "members" && argv.len() == 2 {
+        let name = &argv[1];
+
+        if !roles.roles.contains_key(name) {
+            output_handler::throw_res(Color::Red, format!("Role <{}> is not defined", name).as_str());
+            return;
+        }
+
+        let members = match coll.find(doc! { "role": name }, None) {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, format!("Failed to get members: {}", e).as_str());
+                return;
+            }
+        };
+
+        let mut amount = 0;
+        for member in members {
+            output_handler::throw_res(Color::Green, &users::describe(&member));
+            amount += 1;
+        }
+
+        if amount == 0 {
+            output_handler::throw_res(Color::Cyan, format!("Role <{}> has no members", name).as_str());
+        }
+    }
+    else if &argv[0] == "grant" && argv.len() == 3 {
+        let name = &argv[2];
+
+        /*- Check that the role exists -*/
+        let role = match roles.roles.get(name) {
+            Some(role) => role,
+            None => {
+                output_handler::throw_res(Color::Red, format!("Role <{}> is not defined", name).as_str());
+                return;
+            }
+        };
+
+        let user = match users::find_user(&*coll, &argv[1]) {
+            Ok(user) => user,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        let previous = user.get_str("role").unwrap_or("").to_string();
+        if &previous == name {
+            output_handler::throw_res(Color::Cyan, format!("{} already has the role <{}>", users::describe(&user), name).as_str());
+            return;
+        }
+
+        /*- Some roles (admin) are too powerful to grant by accident -*/
+        if role.confirm && !commands::confirm(format!("Grant <{}> to {}?", name, users::describe(&user)).as_str()) {
+            return;
+        }
+
+        set_role(&*coll, &user, &previous, name, "role.grant");
+    }
+    else if &argv[0] == "revoke" && argv.len() == 2 {
+        let user = match users::find_user(&*coll, &argv[1]) {
+            Ok(user) => user,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        /*- Revoking falls back to the default role -*/
+        let previous = user.get_str("role").unwrap_or("").to_string();
+        if previous == roles.default {
+            output_handler::throw_res(Color::Cyan, format!("{} already has the default role <{}>", users::describe(&user), roles.default).as_str());
+            return;
+        }
+
+        set_role(&*coll, &user, &previous, &roles.default, "role.revoke");
+    }
+    else {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help role> for further information.");
+    }
+}
+
+/*- Update the role of a user and write it to the audit trail -*/
+fn set_role(coll:&dyn DocumentStore, user:&mongodb::bson::Document, previous:&str, role:&str, action:&str) {
+    let id = match user.get_object_id("_id") {
+        Ok(id) => id,
+        Err(_) => {
+            output_handler::throw_res(Color::Red, "User has no valid _id!");
+            return;
+        }
+    };
+
+    if let Err(e) = coll.update_one(doc! { "_id": id }, doc! { "$set": { "role": role } }, None) {
+        output_handler::throw_res(Color::Red, format!("Failed to update role: {}", e).as_str());
+        return;
+    }
+
+    audit::record(action, user.get_str("suid").unwrap_or(""), doc! { "from": previous, "to": role });
+
+    output_handler::throw_res(Color::Green,
+        format!("{} is now <{}> (was <{}>)", users::describe(user), role, previous).as_str()
+    );
+}
@@ -0,0 +1,310 @@
+use termcolor::{ Color };
+use mongodb::bson::{ doc, Bson, Document };
+use rhai::{ Array, Dynamic, Engine, EvalAltResult, Map, Scope };
+use std::panic::{ self, AssertUnwindSafe };
+use std::path::PathBuf;
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::{ commands, config, output_handler, variables };
+use crate::session::Session;
+
+/*- Scripts are <name>.acli files, the ones in config::scripts_dir() are also commands -*/
+static EXTENSION: &str = "acli";
+
+/*- How many changed documents <map> lists before asking -*/
+static MAP_PREVIEW: usize = 10;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/*- Documents are maps in scripts. They go through relaxed extended JSON,
+    so an ObjectId is #{"$oid": "..."} and survives the way back -*/
+fn to_dynamic(document:&Document) -> ScriptResult<Dynamic> {
+    return rhai::serde::to_dynamic(Bson::Document(document.clone()).into_relaxed_extjson());
+}
+
+fn to_document(value:Dynamic) -> ScriptResult<Document> {
+    let json:serde_json::Value = rhai::serde::from_dynamic(&value)?;
+    return match Bson::try_from(json) {
+        Ok(Bson::Document(document)) => Ok(document),
+        Ok(_) => Err("Expected a map like #{ name: \"john\" }".into()),
+        Err(e) => Err(e.to_string().into()),
+    };
+}
+
+fn to_array(documents:&[Document]) -> ScriptResult<Array> {
+    return documents.iter().map(to_dynamic).collect();
+}
+
+/*- Every function a script can call besides the rhai builtins -*/
+fn engine(session:&Rc<RefCell<Session>>) -> Engine {
+    let mut engine = Engine::new();
+
+    engine.on_print(|text| output_handler::throw_res(Color::Green, text));
+    engine.on_debug(|text, _, position| output_handler::throw_res(Color::Cyan, format!("{:?} {}", position, text).as_str()));
+
+    /*- Names people reach for coming from JS and Rust -*/
+    engine.register_fn("to_lowercase", |text:&str| text.to_lowercase());
+    engine.register_fn("to_uppercase", |text:&str| text.to_uppercase());
+
+    /*- The collections are in the current database, same as <collection switch> -*/
+    engine.register_fn("find", |collection:&str| -> ScriptResult<Array> {
+        return to_array(&commands::get_collection(collection).find(doc! {}, None)?);
+    });
+    engine.register_fn("find", |collection:&str, filter:Map| -> ScriptResult<Array> {
+        let filter = to_document(filter.into())?;
+        return to_array(&commands::get_collection(collection).find(filter, None)?);
+    });
+    engine.register_fn("find_one", |collection:&str, filter:Map| -> ScriptResult<Dynamic> {
+        let filter = to_document(filter.into())?;
+        return match commands::get_collection(collection).find_one(filter)? {
+            Some(document) => to_dynamic(&document),
+            None => Ok(Dynamic::UNIT),
+        };
+    });
+    engine.register_fn("count", |collection:&str| -> ScriptResult<i64> {
+        return Ok(commands::get_collection(collection).count(doc! {})? as i64);
+    });
+    engine.register_fn("count", |collection:&str, filter:Map| -> ScriptResult<i64> {
+        let filter = to_document(filter.into())?;
+        return Ok(commands::get_collection(collection).count(filter)? as i64);
+    });
+    engine.register_fn("insert", |collection:&str, document:Map| -> ScriptResult<String> {
+        let document = to_document(document.into())?;
+        let id = commands::get_collection(collection).insert_one(document)?;
+        return Ok(variables::format_value(&id));
+    });
+
+    /*- update("users", #{ role: "mod" }, #{ "$set": #{ role: "moderator" } }), returns how many changed -*/
+    engine.register_fn("update", |collection:&str, filter:Map, update:Map| -> ScriptResult<i64> {
+        let filter = to_document(filter.into())?;
+        let update = to_document(update.into())?;
+        return Ok(commands::get_collection(collection).update_many(filter, update, None)?.modified as i64);
+    });
+
+    /*- Write back a document from find(), matched by its _id -*/
+    engine.register_fn("replace", |collection:&str, document:Map| -> ScriptResult<bool> {
+        let document = to_document(document.into())?;
+        let id = document.get("_id").cloned().ok_or("The document has no _id")?;
+        return Ok(commands::get_collection(collection).replace_one(doc! { "_id": id }, document)?.matched == 1);
+    });
+    engine.register_fn("delete", |collection:&str, filter:Map| -> ScriptResult<i64> {
+        let filter = to_document(filter.into())?;
+        return Ok(commands::get_collection(collection).delete_many(filter)? as i64);
+    });
+
+    /*- run("get all where role is admin"), prints like typing it would and returns the documents -*/
+    let shared = Rc::clone(session);
+    engine.register_fn("run", move |line:&str| -> ScriptResult<Array> {
+        let mut session = shared.try_borrow_mut().map_err(|_| "run() can't be called from here")?;
+
+        output_handler::start_hold();
+        let errors = output_handler::error_count();
+        session.run_line(line);
+        let held = output_handler::finish_hold();
+
+        output_handler::release(&held);
+
+        if output_handler::error_count() != errors {
+            return Err(format!("<{}> failed", line).into());
+        }
+        return to_array(&held.documents);
+    });
+
+    return engine;
+}
+
+/*- Lend the session to the engine for run(), and get it back even if the script panics -*/
+fn with_engine(session:&mut Session, f:impl FnOnce(&Engine) -> Result<(), String>) {
+    let shared = Rc::new(RefCell::new(std::mem::take(session)));
+
+    let result = {
+        let engine = engine(&shared);
+        panic::catch_unwind(AssertUnwindSafe(|| f(&engine)))
+    };
+
+    match Rc::try_unwrap(shared) {
+        Ok(shared) => *session = shared.into_inner(),
+        Err(_) => output_handler::throw_res(Color::Red, "The script kept a reference to the session, it was reset"),
+    }
+
+    match result {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => output_handler::throw_res(Color::Red, &e),
+        Err(_) => output_handler::throw_res(Color::Red, "The script failed unexpectedly"),
+    }
+}
+
+/*- Run a script with its params as <args> -*/
+fn run_source(session:&mut Session, name:&str, source:&str, args:&[String]) {
+    let args:Array = args.iter().map(|arg| Dynamic::from(arg.to_string())).collect();
+
+    with_engine(session, |engine| {
+        let mut scope = Scope::new();
+        scope.push("args", args);
+
+        return engine.run_with_scope(&mut scope, source)
+            .map_err(|e| format!("{}: {}", name, e));
+    });
+}
+
+/*- The .acli file a command name points to, if there is one -*/
+pub fn find_script(name:&str) -> Option<PathBuf> {
+    let path = config::scripts_dir().join(format!("{}.{}", name, EXTENSION));
+    return if path.is_file() { Some(path) } else { None };
+}
+
+/*- The names of every script in the scripts directory -*/
+pub fn script_names() -> Vec<String> {
+    let mut names:Vec<String> = match std::fs::read_dir(config::scripts_dir()) {
+        Ok(entries) => entries.flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == EXTENSION))
+            .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    return names;
+}
+
+/*- The first // comment of a script is its summary -*/
+fn summary(source:&str) -> &str {
+    return source.lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .and_then(|line| line.strip_prefix("//"))
+        .map(|line| line.trim())
+        .unwrap_or("");
+}
+
+/*- Run a script from the scripts directory as if it was a command -*/
+pub fn run_script(session:&mut Session, name:&str, argv:Vec<String>) {
+    let path = match find_script(name) {
+        Some(path) => path,
+        None => {
+            output_handler::throw_res(Color::Red, format!("There is no script named <{}>", name).as_str());
+            return;
+        }
+    };
+    run_file(session, &path.to_string_lossy(), &argv);
+}
+
+fn run_file(session:&mut Session, file:&str, args:&[String]) {
+    match std::fs::read_to_string(file) {
+        Ok(source) => run_source(session, file, &source, args),
+        Err(e) => output_handler::throw_res(Color::Red, format!("Failed to read {}: {}", file, e).as_str()),
+    }
+}
+
+/*- Code typed on the command line goes in braces when it has ; or | in it, those aren't part of the script -*/
+fn unwrap_block(argv:&[String]) -> String {
+    let code = argv.join(" ");
+    let code = code.trim();
+    return code.strip_prefix('{').and_then(|code| code.strip_suffix('}')).unwrap_or(code).to_string();
+}
+
+/*- script list, script run <file> <args>..., script eval <code>... -*/
+pub fn script(session:&mut Session, argv:Vec<String>) {
+    match argv[0].as_str() {
+        "list" => {
+            let names = script_names();
+            if names.is_empty() {
+                output_handler::throw_res(Color::Cyan, format!("No scripts in {}, add a <name>.{} file to run it as <name>",
+                    config::scripts_dir().display(), EXTENSION
+                ).as_str());
+            }
+            for name in names {
+                let source = find_script(&name).and_then(|path| std::fs::read_to_string(path).ok()).unwrap_or_default();
+                let hidden = if session.is_command(&name) { " (hidden by the command with the same name)" } else { "" };
+                output_handler::throw_res(Color::Green, format!("{} {}{}", name, summary(&source), hidden).as_str());
+            }
+        },
+        "run" => run_file(session, &argv[1], &argv[2..]),
+        "eval" => run_source(session, "eval", &unwrap_block(&argv[1..]), &[]),
+        _ => {},
+    }
+}
+
+/*- map <collection> { doc.email = doc.email.to_lowercase() }. The block runs once per document
+    with it as <doc>, the fields it changed are written back after a confirmation -*/
+pub fn map(session:&mut Session, mut argv:Vec<String>) {
+    let dry_run = commands::take_flag(&mut argv, "--dry-run");
+    let collection = argv[0].to_string();
+
+    let code = unwrap_block(&argv[1..]);
+
+    with_engine(session, |engine| {
+        let ast = engine.compile(&code).map_err(|e| format!("map: {}", e))?;
+        let coll = commands::get_collection(&collection);
+        let documents = coll.find(doc! {}, None)?;
+
+        /*- Run over everything before writing anything, so an error halfway changes nothing -*/
+        let mut changes:Vec<(Bson, Document, Document)> = Vec::new();
+        for document in &documents {
+            let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+
+            let mut scope = Scope::new();
+            scope.push("doc", to_dynamic(document).map_err(|e| e.to_string())?);
+            engine.run_ast_with_scope(&mut scope, &ast)
+                .map_err(|e| format!("map: {} in {}", e, variables::format_value(&id)))?;
+
+            let value = scope.get_value::<Dynamic>("doc").unwrap_or(Dynamic::UNIT);
+            let changed = to_document(value).map_err(|e| format!("map: <doc> {}", e))?;
+
+            /*- Compared as JSON, a number that went through the script as 5 is still the same 5 -*/
+            let same = |a:&Bson, b:&Bson| a.clone().into_relaxed_extjson() == b.clone().into_relaxed_extjson();
+            if !same(changed.get("_id").unwrap_or(&Bson::Null), &id) {
+                return Err(format!("map: <doc._id> can't be changed, it was in {}", variables::format_value(&id)));
+            }
+
+            let mut set = Document::new();
+            for (key, value) in &changed {
+                if !document.get(key).is_some_and(|original| same(original, value)) {
+                    set.insert(key, value.clone());
+                }
+            }
+            let mut unset = Document::new();
+            for key in document.keys() {
+                if !changed.contains_key(key) { unset.insert(key, ""); }
+            }
+
+            if !set.is_empty() || !unset.is_empty() {
+                changes.push((id, set, unset));
+            }
+        }
+
+        if changes.is_empty() {
+            output_handler::throw_res(Color::Cyan, format!("No document in <{}> changed", collection).as_str());
+            return Ok(());
+        }
+
+        for (id, set, unset) in changes.iter().take(MAP_PREVIEW) {
+            let mut fields:Vec<String> = set.iter()
+                .map(|(key, value)| format!("{} = {}", key, variables::format_value(value)))
+                .collect();
+            fields.extend(unset.keys().map(|key| format!("{} removed", key)));
+            output_handler::throw_res(Color::Green, format!("{}: {}", variables::format_value(id), fields.join(", ")).as_str());
+        }
+        if changes.len() > MAP_PREVIEW {
+            output_handler::throw_res(Color::Green, format!("...and {} more", changes.len() - MAP_PREVIEW).as_str());
+        }
+
+        if dry_run {
+            output_handler::throw_res(Color::Cyan, format!("{} of {} document(s) would change", changes.len(), documents.len()).as_str());
+            return Ok(());
+        }
+        if !commands::confirm(format!("Update {} of {} document(s) in <{}>?", changes.len(), documents.len(), collection).as_str()) {
+            return Ok(());
+        }
+
+        let mut modified = 0;
+        for (id, set, unset) in changes {
+            let mut update = Document::new();
+            if !set.is_empty() { update.insert("$set", set); }
+            if !unset.is_empty() { update.insert("$unset", unset); }
+            modified += coll.update_one(doc! { "_id": id }, update, None)?.modified;
+        }
+        output_handler::throw_res(Color::Cyan, format!("Updated {} document(s)", modified).as_str());
+        return Ok(());
+    });
+}
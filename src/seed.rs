@@ -0,0 +1,369 @@
+use termcolor::{ Color };
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use mongodb::bson::{ doc, Document };
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use sha2::Sha512;
+use std::collections::{ BTreeMap, HashSet };
+use crate::{ api, audit, commands, config, dates, output_handler, users };
+use crate::config::Variables;
+
+/*- Documents are inserted in batches of this size -*/
+static BATCH_SIZE: usize = 500;
+
+/*- Same parameters as the API: pbkdf2, sha512, 1000 iterations, 64 bytes -*/
+static PBKDF2_ROUNDS: u32 = 1000;
+static HASH_LEN: usize = 64;
+
+static EMAIL_DOMAINS: [&str; 6] = ["example.com", "example.org", "example.net", "mail.test", "inbox.test", "corp.test"];
+
+/*- Without --since/--until the dates are fixed, so that a seed always gives the same data -*/
+static DEFAULT_SINCE: &str = "2022-01-01";
+static DEFAULT_UNTIL: &str = "2022-12-31";
+
+/*- Everything that decides what gets generated -*/
+struct SeedOptions {
+    amount: usize,
+    seed: u64,
+    friends_avg: f64,
+    roles: BTreeMap<String, usize>,
+    via_api: bool,
+    password: String,
+    since: i64,
+    until: i64,
+}
+
+/*- A generated account before it's inserted -*/
+struct SeedUser {
+    username: String,
+    displayname: String,
+    email: String,
+    suid: String,
+    uid: String,
+    joined: i64,
+    role: String,
+    friends: Vec<String>,
+}
+
+/*- Random bytes as hex -*/
+fn random_hex(rng:&mut ChaCha8Rng, len:usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rng.fill_bytes(&mut bytes);
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+/*- A version 4 uuid like crypto.randomUUID() -*/
+pub fn random_uuid(rng:&mut ChaCha8Rng) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex:String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    return format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]);
+}
+
+/*- Hash a password exactly like /api/create-account, returns (salt, hash) -*/
+pub fn hash_password(rng:&mut ChaCha8Rng, password:&str) -> (String, String) {
+    let salt = random_hex(rng, 16);
+    let hash = hash_with_salt(password, &salt);
+
+    return (salt, hash);
+}
+
+/*- Hash a password with an existing salt, used to check logins -*/
+pub fn hash_with_salt(password:&str, salt:&str) -> String {
+    let mut hash = vec![0u8; HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha512>(password.as_bytes(), salt.as_bytes(), PBKDF2_ROUNDS, &mut hash);
+
+    return hash.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+/*- Generate a username that passes checkUsername and isn't taken -*/
+fn unique_username(rng:&mut ChaCha8Rng, taken:&mut HashSet<String>, reserved:&[String], variables:&Variables) -> String {
+    loop {
+        let first = commands::NAMES.choose(rng).unwrap();
+        let last = commands::LAST_NAMES.choose(rng).unwrap();
+        let number = rng.gen_range(0..10000).to_string();
+
+        /*- Cut the name so that the number always fits -*/
+        let mut base = format!("{}_{}", first, last);
+        base.truncate(variables.username_len_max - number.len());
+        let username = format!("{}{}", base, number);
+
+        if username.len() < variables.username_len_min || reserved.contains(&username) {
+            continue;
+        }
+        if taken.insert(username.clone()) {
+            return username;
+        }
+    }
+}
+
+/*- Parse --roles admin:1,moderator:5 -*/
+fn parse_roles(input:&str, defined:&config::RolesConfig) -> Result<BTreeMap<String, usize>, String> {
+    let mut roles = BTreeMap::new();
+
+    for part in input.split(',').filter(|p| !p.is_empty()) {
+        let (role, amount) = part.split_once(':')
+            .ok_or(format!("<{}> should look like role:amount", part))?;
+
+        if !defined.roles.contains_key(role) {
+            return Err(format!("Role <{}> is not defined", role));
+        }
+        let amount = amount.parse::<usize>()
+            .map_err(|_| format!("<{}> is not a valid amount", amount))?;
+
+        roles.insert(role.to_string(), amount);
+    }
+
+    return Ok(roles);
+}
+
+/*- Generate all users, their roles and friends. Only depends on the options -*/
+fn generate(options:&SeedOptions, taken:&mut HashSet<String>, reserved:&[String], variables:&Variables, default_role:&str) -> Vec<SeedUser> {
+    let mut rng = ChaCha8Rng::seed_from_u64(options.seed);
+
+    let mut generated:Vec<SeedUser> = (0..options.amount).map(|_| {
+        let username = unique_username(&mut rng, taken, reserved, variables);
+        let domain = EMAIL_DOMAINS.choose(&mut rng).unwrap();
+
+        SeedUser {
+            displayname: username.split('_').next().unwrap_or(&username).to_string(),
+            email: format!("{}@{}", username.replace('_', "."), domain),
+            username,
+            suid: random_hex(&mut rng, 16),
+            uid: random_uuid(&mut rng),
+            joined: rng.gen_range(options.since..options.until),
+            role: default_role.to_string(),
+            friends: Vec::new(),
+        }
+    }).collect();
+
+    /*- Hand out the roles to random users -*/
+    let mut order:Vec<usize> = (0..generated.len()).collect();
+    order.shuffle(&mut rng);
+    let mut order = order.into_iter();
+    for (role, amount) in &options.roles {
+        for index in order.by_ref().take(*amount) {
+            generated[index].role = role.clone();
+        }
+    }
+
+    /*- Random mutual friendships, amount * avg / 2 edges gives the requested average -*/
+    if generated.len() > 1 {
+        let max_edges = generated.len() * (generated.len() - 1) / 2;
+        let edges = ((generated.len() as f64 * options.friends_avg / 2.0).round() as usize).min(max_edges);
+        let mut pairs:HashSet<(usize, usize)> = HashSet::new();
+
+        while pairs.len() < edges {
+            let a = rng.gen_range(0..generated.len());
+            let b = rng.gen_range(0..generated.len());
+            if a != b { pairs.insert((a.min(b), a.max(b))); }
+        }
+
+        let mut pairs:Vec<(usize, usize)> = pairs.into_iter().collect();
+        pairs.sort();
+        for (a, b) in pairs {
+            let (suid_a, suid_b) = (generated[a].suid.clone(), generated[b].suid.clone());
+            generated[a].friends.push(suid_b);
+            generated[b].friends.push(suid_a);
+        }
+    }
+
+    return generated;
+}
+
+/*- Insert the generated users straight into the database -*/
+fn insert_db(generated:&[SeedUser], options:&SeedOptions, tz:&Tz, variables:&Variables) -> Result<usize, String> {
+    let coll = users::users_collection();
+    let mut rng = ChaCha8Rng::seed_from_u64(options.seed ^ 0x5eed);
+
+    /*- Existing duplicates make this fail, the seed doesn't add any so it goes on without them -*/
+    match users::ensure_unique_indexes(&*coll) {
+        Ok(created) => for name in created {
+            output_handler::throw_res(Color::Cyan, format!("Created the unique index {}", name).as_str());
+        },
+        Err(e) => output_handler::throw_res(Color::Yellow, format!("Inserting without unique indexes: {}", e).as_str()),
+    }
+
+    let mut inserted = 0;
+
+    for batch in generated.chunks(BATCH_SIZE) {
+        let documents:Vec<Document> = batch.iter().map(|user| {
+            let (salt, password) = hash_password(&mut rng, &options.password);
+
+            doc! {
+                "uid": &user.uid,
+                "suid": &user.suid,
+                "salt": salt,
+                "email": &user.email,
+                "username": &user.username,
+                "displayname": &user.displayname,
+                "joined": {
+                    "prettified": dates::prettify(user.joined, tz, variables).unwrap_or_default(),
+                    "unix": user.joined,
+                },
+                "role": &user.role,
+                "password": password,
+                "profile": users::expected_profile_url(&user.suid).unwrap_or_default(),
+                "friends": &user.friends,
+            }
+        }).collect();
+
+        coll.insert_many(documents)
+            .map_err(|e| format!("Failed to insert users: {}", e))?;
+        inserted += batch.len();
+        output_handler::throw_res(Color::Cyan, format!("{}/{} inserted...", inserted, generated.len()).as_str());
+    }
+
+    return Ok(inserted);
+}
+
+/*- Create the users through /api/create-account, then apply roles and friends in the database -*/
+fn insert_api(generated:&[SeedUser], options:&SeedOptions) -> Result<usize, String> {
+    let client = api::ApiClient::from_config();
+
+    /*- The API generates its own suids, map ours to theirs -*/
+    let mut suids:BTreeMap<String, String> = BTreeMap::new();
+    for user in generated {
+        let account = api::CreateAccount {
+            email: user.email.clone(),
+            username: user.username.clone(),
+            displayname: user.displayname.clone(),
+            password: options.password.clone(),
+        };
+
+        match client.create_account(&account) {
+            Ok(data) => { suids.insert(user.suid.clone(), data.suid); },
+            Err(api::ApiError::Http(e)) => return Err(format!("Failed to create {}: {}", user.username, e)),
+            Err(e) => output_handler::throw_res(Color::Red, format!("Failed to create {}: {}", user.username, e).as_str()),
+        }
+    }
+
+    let coll = users::users_collection();
+    for user in generated {
+        let suid = match suids.get(&user.suid) {
+            Some(suid) => suid,
+            None => continue,
+        };
+        let friends:Vec<&String> = user.friends.iter().filter_map(|friend| suids.get(friend)).collect();
+
+        coll.update_one(doc! { "suid": suid }, doc! { "$set": { "role": &user.role, "friends": friends } }, None)
+            .map_err(|e| format!("Failed to update {}: {}", user.username, e))?;
+    }
+
+    return Ok(suids.len());
+}
+
+/*- Parse the seed options from argv -*/
+fn parse_options(argv:&mut Vec<String>, tz:&Tz, roles_config:&config::RolesConfig) -> Result<SeedOptions, String> {
+    let seed = commands::take_option(argv, "--seed")
+        .map(|seed| seed.parse::<u64>().map_err(|_| format!("<{}> is not a valid seed", seed)))
+        .transpose()?
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    let friends_avg = commands::take_option(argv, "--friends-avg")
+        .map(|avg| avg.parse::<f64>().map_err(|_| format!("<{}> is not a valid number", avg)))
+        .transpose()?
+        .unwrap_or(0.0);
+    let roles = parse_roles(&commands::take_option(argv, "--roles").unwrap_or_default(), roles_config)?;
+    let via_api = match commands::take_option(argv, "--via").as_deref() {
+        None | Some("db") => false,
+        Some("api") => true,
+        Some(other) => return Err(format!("--via must be api or db, not <{}>", other)),
+    };
+    let password = commands::take_option(argv, "--password").unwrap_or(String::from("password"));
+
+    let day = |input:&str| -> Result<i64, String> {
+        let date = NaiveDate::parse_from_str(input, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid date <{}>, use YYYY-MM-DD", input))?;
+        return chrono::TimeZone::from_local_datetime(tz, &date.and_hms_opt(0, 0, 0).unwrap())
+            .earliest()
+            .map(|date| date.timestamp_millis())
+            .ok_or(format!("<{}> doesn't exist in {}", input, tz));
+    };
+    let since = day(&commands::take_option(argv, "--since").unwrap_or(String::from(DEFAULT_SINCE)))?;
+    let until = day(&commands::take_option(argv, "--until").unwrap_or(String::from(DEFAULT_UNTIL)))?;
+    if since >= until {
+        return Err(String::from("--since must be before --until"));
+    }
+
+    /*- What's left of argv is "users <n>" -*/
+    if argv.len() != 2 {
+        return Err(String::from("Invalid syntax! Write <help seed> for further information."));
+    }
+    let amount = argv[1].parse::<usize>()
+        .map_err(|_| format!("<{}> is not a valid amount", argv[1]))?;
+    if roles.values().sum::<usize>() > amount {
+        return Err(String::from("More roles than users"));
+    }
+    if friends_avg < 0.0 {
+        return Err(String::from("--friends-avg can't be negative"));
+    }
+
+    return Ok(SeedOptions { amount, seed, friends_avg, roles, via_api, password, since, until });
+}
+
+/*- Generate test data -*/
+pub fn seed(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    if &argv[0] != "users" {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help seed> for further information.");
+        return;
+    }
+
+    let loaded = config::load_roles().and_then(|roles| {
+        let tz = dates::parse_timezone(&config::timezone())?;
+        return Ok((roles, tz, config::load_variables()?, config::load_reserved_usernames()?));
+    });
+    let (roles_config, tz, variables, reserved) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+
+    let mut argv = argv;
+    let options = match parse_options(&mut argv, &tz, &roles_config) {
+        Ok(options) => options,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+
+    /*- Don't generate usernames that already exist. Only collisions with these change the output of a seed -*/
+    let coll = users::users_collection();
+    let mut taken:HashSet<String> = match coll.find(doc! { "username": { "$exists": true } }, None) {
+        Ok(users) => users.iter().filter_map(|user| user.get_str("username").ok().map(|s| s.to_string())).collect(),
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get usernames: {}", e).as_str());
+            return;
+        }
+    };
+
+    if !commands::confirm(format!(
+        "Create {} user(s) with seed {} via {}?", options.amount, options.seed, if options.via_api { "the API" } else { "the database" }
+    ).as_str()) { return; }
+
+    let generated = generate(&options, &mut taken, &reserved, &variables, &roles_config.default);
+    let result = match options.via_api {
+        true => insert_api(&generated, &options),
+        false => insert_db(&generated, &options, &tz, &variables),
+    };
+
+    match result {
+        Ok(inserted) => {
+            audit::record("seed.users", "", doc! { "seed": options.seed as i64, "amount": inserted as i64, "via_api": options.via_api });
+            output_handler::throw_res(Color::Green,
+                format!("{} user(s) created with seed {}, password <{}>", inserted, options.seed, options.password).as_str()
+            );
+        },
+        Err(e) => output_handler::throw_res(Color::Red, &e),
+    }
+}
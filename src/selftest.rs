@@ -0,0 +1,295 @@
+use termcolor::{ Color };
+use mongodb::bson::doc;
+use std::fmt::Write as FmtWrite;
+use std::time::{ Duration, Instant };
+use crate::api::{ AccountData, ApiClient, ApiError, CreateAccount };
+use crate::{ config, escape, images, mock_server, output_handler, users };
+
+/*- Where the JUnit report is written when --junit isn't given -*/
+pub static DEFAULT_REPORT: &str = "selftest-report.xml";
+
+/*- Fields that must never leave the API -*/
+static SECRET_FIELDS: [&str; 3] = ["salt", "password", "email"];
+
+/*- How a single check went -*/
+enum Outcome {
+    Passed,
+    Failed(String),
+
+    /*- Couldn't run because something it depends on failed -*/
+    Skipped(String),
+}
+
+/*- The result of a single check -*/
+struct TestCase {
+    name: String,
+    duration: Duration,
+    outcome: Outcome,
+}
+
+/*- Runs the checks and keeps track of the results -*/
+struct Runner {
+    cases: Vec<TestCase>,
+}
+
+impl Runner {
+    fn check<F:FnOnce() -> Result<(), String>>(&mut self, name:&str, f:F) {
+        let start = Instant::now();
+        let outcome = match f() {
+            Ok(()) => Outcome::Passed,
+            Err(e) => Outcome::Failed(e),
+        };
+
+        self.cases.push(TestCase { name: name.to_string(), duration: start.elapsed(), outcome });
+    }
+
+    fn skip(&mut self, name:&str, reason:&str) {
+        self.cases.push(TestCase { name: name.to_string(), duration: Duration::ZERO, outcome: Outcome::Skipped(reason.to_string()) });
+    }
+
+    fn count(&self, f:fn(&Outcome) -> bool) -> usize {
+        return self.cases.iter().filter(|case| f(&case.outcome)).count();
+    }
+}
+
+/*- A throwaway account that passes the username rules -*/
+fn throwaway_account(tag:&str) -> CreateAccount {
+    let id:String = (0..4).map(|_| format!("{:02x}", rand::random::<u8>())).collect();
+    let username = format!("st_{}_{}", tag, id);
+
+    return CreateAccount {
+        email: format!("{}@selftest.invalid", username),
+        displayname: username.clone(),
+        username,
+        password: format!("pw-{}", id),
+    };
+}
+
+/*- Expect the API to reject a request with status 400 -*/
+fn expect_rejected<T>(result:Result<T, ApiError>) -> Result<(), String> {
+    return match result {
+        Err(ApiError::Status { status: 400, .. }) => Ok(()),
+        Err(e) => Err(format!("expected status 400, got {}", e)),
+        Ok(_) => Err(String::from("request was accepted")),
+    };
+}
+
+/*- Expect the API to refuse to create an account. One that's created anyway still has to be cleaned up -*/
+fn expect_not_created(result:Result<AccountData, ApiError>, created:&mut Vec<String>) -> Result<(), String> {
+    if let Ok(data) = &result {
+        created.push(data.suid.clone());
+    }
+    return expect_rejected(result);
+}
+
+/*- Check that a SafeUser JSON object doesn't contain any secrets -*/
+fn check_no_secrets(user:&serde_json::Value) -> Result<(), String> {
+    let leaked:Vec<&str> = SECRET_FIELDS.iter().copied().filter(|field| user.get(field).is_some()).collect();
+
+    if leaked.is_empty() { return Ok(()); }
+    return Err(format!("leaked {}", leaked.join(", ")));
+}
+
+/*- Does <suid> have <friend> as a friend according to the API -*/
+fn has_friend(client:&ApiClient, suid:&str, friend:&str) -> Result<bool, String> {
+    return client.profile_data(suid)
+        .map(|user| user.friends.iter().any(|f| f == friend))
+        .map_err(|e| e.to_string());
+}
+
+/*- Remove the throwaway accounts straight from the database, the API can't delete accounts -*/
+pub fn cleanup(suids:&[String]) -> Result<(), String> {
+
+    /*- The in-memory mock server has no database -*/
+    if let Some(removed) = mock_server::remove_users(suids) {
+        let removed = removed?;
+        if removed != suids.len() {
+            return Err(format!("deleted {} of {} accounts", removed, suids.len()));
+        }
+        return Ok(());
+    }
+
+    let coll = users::users_collection();
+
+    coll.update_many(doc! { "friends": { "$in": suids } }, doc! { "$pull": { "friends": { "$in": suids } } }, None)
+        .map_err(|e| format!("Failed to remove friend references: {}", e))?;
+    let deleted = coll.delete_many(doc! { "suid": { "$in": suids } })
+        .map_err(|e| format!("Failed to delete accounts: {}", e))?;
+
+    if deleted as usize != suids.len() {
+        return Err(format!("deleted {} of {} accounts", deleted, suids.len()));
+    }
+    return Ok(());
+}
+
+/*- Render the results as a JUnit XML report -*/
+fn junit_report(cases:&[TestCase], total:Duration) -> String {
+    let failures = cases.iter().filter(|c| matches!(c.outcome, Outcome::Failed(_))).count();
+    let skipped = cases.iter().filter(|c| matches!(c.outcome, Outcome::Skipped(_))).count();
+    let mut out = String::new();
+
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(out, "<testsuite name=\"account-api-selftest\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        cases.len(), failures, skipped, total.as_secs_f64()
+    ).unwrap();
+    for case in cases {
+        write!(out, "  <testcase classname=\"selftest\" name=\"{}\" time=\"{:.3}\"", escape::xml(&case.name), case.duration.as_secs_f64()).unwrap();
+        match &case.outcome {
+            Outcome::Passed => writeln!(out, "/>").unwrap(),
+            Outcome::Failed(e) => {
+                writeln!(out, ">").unwrap();
+                writeln!(out, "    <failure message=\"{}\"/>", escape::xml(e)).unwrap();
+                writeln!(out, "  </testcase>").unwrap();
+            },
+            Outcome::Skipped(reason) => {
+                writeln!(out, ">").unwrap();
+                writeln!(out, "    <skipped message=\"{}\"/>", escape::xml(reason)).unwrap();
+                writeln!(out, "  </testcase>").unwrap();
+            }
+        }
+    }
+    writeln!(out, "</testsuite>").unwrap();
+
+    return out;
+}
+
+/*- Run the whole scenario against the API and write a JUnit report -*/
+pub fn run(client:&ApiClient, report_path:&str) {
+    output_handler::throw_res(Color::Cyan, format!("Running selftest against {}...", config::backend_url()).as_str());
+
+    let start = Instant::now();
+    let mut runner = Runner { cases: Vec::new() };
+    let mut created:Vec<String> = Vec::new();
+
+    let account = throwaway_account("a");
+    let friend = throwaway_account("b");
+
+    /*- Everything else depends on these two accounts -*/
+    let mut a = None;
+    runner.check("create account", || {
+        let data = client.create_account(&account).map_err(|e| e.to_string())?;
+        created.push(data.suid.clone());
+        a = Some(data);
+        return Ok(());
+    });
+    let mut b = None;
+    runner.check("create second account", || {
+        let data = client.create_account(&friend).map_err(|e| e.to_string())?;
+        created.push(data.suid.clone());
+        b = Some(data);
+        return Ok(());
+    });
+
+    runner.check("reject duplicate email", || {
+        let mut duplicate = throwaway_account("c");
+        duplicate.email = account.email.clone();
+        return expect_not_created(client.create_account(&duplicate), &mut created);
+    });
+    runner.check("reject duplicate username", || {
+        let mut duplicate = throwaway_account("c");
+        duplicate.username = account.username.clone();
+        return expect_not_created(client.create_account(&duplicate), &mut created);
+    });
+    runner.check("reject reserved username", || {
+        let reserved = config::load_reserved_usernames()?;
+        let username = reserved.first().ok_or("no reserved usernames in the dictionary")?;
+
+        let mut account = throwaway_account("c");
+        account.username = username.clone();
+        return expect_not_created(client.create_account(&account), &mut created);
+    });
+    runner.check("reject illegal username", || {
+        let mut account = throwaway_account("c");
+        account.username = String::from("not-legal!");
+        return expect_not_created(client.create_account(&account), &mut created);
+    });
+
+    runner.check("login with valid credentials", || {
+        let data = client.login(&account.email, &account.password).map_err(|e| e.to_string())?;
+        if data.username != account.username {
+            return Err(format!("logged in as {} instead of {}", data.username, account.username));
+        }
+        return Ok(());
+    });
+    runner.check("reject login with wrong password", || {
+        return expect_rejected(client.login(&account.email, "definitely-wrong"));
+    });
+
+    /*- The checks that need both accounts, the same names are skipped when they couldn't be created -*/
+    type AccountCheck<'a> = Box<dyn Fn(&AccountData, &AccountData) -> Result<(), String> + 'a>;
+    let account_checks:Vec<(&str, AccountCheck)> = vec![
+        ("profile-data doesn't leak secrets", Box::new(|a, _| {
+            return check_no_secrets(&client.profile_data_raw(&a.suid).map_err(|e| e.to_string())?);
+        })),
+        ("add-friend adds the friend", Box::new(|a, b| {
+            client.add_friend(&a.suid, &b.suid).map_err(|e| e.to_string())?;
+            if !has_friend(client, &a.suid, &b.suid)? { return Err(String::from("friend wasn't added")); }
+            return Ok(());
+        })),
+        ("add-friend is one-sided", Box::new(|a, b| {
+            if has_friend(client, &b.suid, &a.suid)? { return Err(String::from("friend was added both ways")); }
+            return Ok(());
+        })),
+        ("get-friends-data doesn't leak secrets", Box::new(|a, _| {
+            let friends = client.get_friends_data_raw(&a.suid).map_err(|e| e.to_string())?;
+            if friends.len() != 1 { return Err(format!("expected 1 friend, got {}", friends.len())); }
+            return friends.iter().try_for_each(check_no_secrets);
+        })),
+        ("add-friend again removes the friend", Box::new(|a, b| {
+            client.add_friend(&a.suid, &b.suid).map_err(|e| e.to_string())?;
+            if has_friend(client, &a.suid, &b.suid)? { return Err(String::from("friend wasn't removed")); }
+            return Ok(());
+        })),
+        ("image falls back to the default image", Box::new(|a, _| {
+            let image = client.profile_image(&a.suid).map_err(|e| e.to_string())?;
+
+            /*- Compare with the local copy if the CLI runs next to the API -*/
+            return match std::fs::read(images::DEFAULT_IMAGE) {
+                Ok(default) if default != image.bytes => Err(String::from("image differs from the default image")),
+                Ok(_) => Ok(()),
+                Err(_) if image.content_type.starts_with("image/") => Ok(()),
+                Err(_) => Err(format!("unexpected content type {}", image.content_type)),
+            };
+        })),
+    ];
+
+    for (name, check) in &account_checks {
+        match (&a, &b) {
+            (Some(a), Some(b)) => runner.check(name, || check(a, b)),
+            _ => runner.skip(name, "account creation failed"),
+        }
+    }
+
+    if created.is_empty() {
+        runner.skip("cleanup", "nothing was created");
+    }else {
+        runner.check("cleanup", || cleanup(&created));
+    }
+
+    /*- The pass/fail table -*/
+    let width = runner.cases.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for case in &runner.cases {
+        match &case.outcome {
+            Outcome::Passed => output_handler::throw_res(Color::Green,
+                format!("| {:<width$} | PASS | {:>5}ms |", case.name, case.duration.as_millis(), width = width).as_str()
+            ),
+            Outcome::Failed(e) => output_handler::throw_res(Color::Red,
+                format!("| {:<width$} | FAIL | {:>5}ms | {}", case.name, case.duration.as_millis(), e, width = width).as_str()
+            ),
+            Outcome::Skipped(reason) => output_handler::throw_res(Color::Cyan,
+                format!("| {:<width$} | SKIP | {:>5}ms | {}", case.name, case.duration.as_millis(), reason, width = width).as_str()
+            ),
+        }
+    }
+
+    let failed = runner.count(|outcome| matches!(outcome, Outcome::Failed(_)));
+    let skipped = runner.count(|outcome| matches!(outcome, Outcome::Skipped(_)));
+    output_handler::throw_res(if failed == 0 { Color::Green } else { Color::Red },
+        format!("{} passed, {} failed, {} skipped", runner.cases.len() - failed - skipped, failed, skipped).as_str()
+    );
+
+    match std::fs::write(report_path, junit_report(&runner.cases, start.elapsed())) {
+        Ok(_) => output_handler::throw_res(Color::Cyan, format!("JUnit report written to {}", report_path).as_str()),
+        Err(e) => output_handler::throw_res(Color::Red, format!("Failed to write {}: {}", report_path, e).as_str()),
+    }
+}
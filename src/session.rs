@@ -0,0 +1,447 @@
+use termcolor::{ Color };
+use mongodb::bson::Document;
+use std::panic::{ self, AssertUnwindSafe };
+use std::collections::BTreeMap;
+use crate::{ commands, config, output_handler, pipeline, registry, scripting, store, variables };
+use crate::pipeline::{ Chain, Stages };
+use crate::variables::Variables;
+use crate::commands::{ CommandStruct, Handler, Location };
+use crate::output_handler::{ Kind, Line };
+
+/*- What running one command string produced -*/
+#[derive(Debug, Clone, Default)]
+pub struct CommandResult {
+    /*- The command after splitting, <prev> is already replaced by the command it repeated -*/
+    pub argv: Vec<String>,
+    pub lines: Vec<Line>,
+
+    /*- Every document the command printed -*/
+    pub documents: Vec<Document>,
+
+    /*- The command was <exit>, the frontend should stop -*/
+    pub exit: bool,
+}
+
+impl CommandResult {
+    pub fn is_error(&self) -> bool {
+        return self.lines.iter().any(|line| line.kind == Kind::Error);
+    }
+
+    /*- The text of every line of one kind -*/
+    pub fn text(&self, kind:Kind) -> Vec<&str> {
+        return self.lines.iter().filter(|line| line.kind == kind).map(|line| line.text.as_str()).collect();
+    }
+}
+
+/*- Runs command strings like the REPL does and returns what they printed -*/
+pub struct Session {
+    tag: String,
+    previous: Vec<Vec<String>>,
+    commands: Vec<CommandStruct<'static>>,
+
+    /*- Print the output while running, the REPL wants to see it live -*/
+    echo: bool,
+
+    /*- The answer to every confirmation, None = ask on stdin -*/
+    confirm: Option<bool>,
+
+    /*- Set by <exit> -*/
+    exit: bool,
+
+    /*- From the profile config, name -> command -*/
+    aliases: BTreeMap<String, String>,
+
+    /*- $last and everything assigned with <let> -*/
+    variables: Variables,
+
+    /*- The database and collection the commands work in -*/
+    location: Location,
+
+    /*- Use the in-memory store instead of mongo -*/
+    memory: bool,
+
+    /*- Where the API commands send their requests, None = BACKEND_URL -*/
+    backend_url: Option<String>,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        return Session::new();
+    }
+}
+
+impl Session {
+    /*- A session for embedding, prints nothing and declines every confirmation -*/
+    pub fn new() -> Session {
+        return Session {
+            tag: String::from("=="),
+            previous: Vec::new(),
+            commands: commands::get_commands(),
+            echo: false,
+            confirm: Some(false),
+            exit: false,
+            aliases: config::load_profile().map(|profile| profile.aliases).unwrap_or_default(),
+            variables: Variables::new(),
+            location: Location::default(),
+            memory: false,
+            backend_url: None,
+        };
+    }
+
+    /*- A session for a terminal, prints while running and asks on stdin -*/
+    pub fn interactive() -> Session {
+        return Session { echo: true, confirm: None, ..Session::new() };
+    }
+
+    /*- Run against the in-memory store, nothing is saved -*/
+    pub fn in_memory(self) -> Session {
+        return Session { memory: true, ..self };
+    }
+
+    /*- Send the API commands to <url> instead of BACKEND_URL -*/
+    pub fn with_backend_url(self, url:&str) -> Session {
+        return Session { backend_url: Some(url.trim_end_matches('/').to_string()), ..self };
+    }
+
+    /*- Answer yes or no to "dangerous" commands without asking -*/
+    pub fn set_confirm_answer(&mut self, answer:bool) {
+        self.confirm = Some(answer);
+    }
+
+    /*- A variable set with <let>, or $last -*/
+    pub fn variable(&self, name:&str) -> Option<&Vec<Document>> {
+        return self.variables.get(name);
+    }
+
+    /*- Cli "start" tag, changed with <tag> -*/
+    pub fn tag(&self) -> &str {
+        return &self.tag;
+    }
+
+    /*- Run one line of input, commands can be chained with ; and && and piped with | -*/
+    pub fn execute(&mut self, command:&str) -> CommandResult {
+        if command.trim().is_empty() { return CommandResult::default(); }
+
+        output_handler::start_capture(self.echo);
+        commands::set_confirm_answer(self.confirm);
+        commands::set_location(self.location.clone());
+        store::set_memory(self.memory);
+        config::set_backend_url(self.backend_url.clone());
+        self.exit = false;
+
+        let argv = self.run_line(command);
+
+        /*- <collection switch> and <mock-server start> change these while running -*/
+        self.location = commands::location();
+        self.backend_url = config::backend_url_override();
+        commands::set_confirm_answer(None);
+        let captured = output_handler::finish_capture();
+
+        return CommandResult { argv, lines: captured.lines, documents: captured.documents, exit: self.exit };
+    }
+
+    /*- Run the chained commands of a line without capturing, scripts call this through run() -*/
+    pub(crate) fn run_line(&mut self, line:&str) -> Vec<String> {
+        let mut argv:Vec<String> = Vec::new();
+        match pipeline::parse(line) {
+            Ok(chain) => {
+                let mut ok = true;
+                for (link, stages) in chain {
+                    if self.exit { break; }
+
+                    /*- a && b && c skips both b and c when a fails -*/
+                    if link == Chain::IfOk && !ok { continue; }
+
+                    let errors = output_handler::error_count();
+                    let mark = output_handler::capture_mark();
+                    argv = self.run_pipeline(stages);
+                    ok = output_handler::error_count() == errors;
+
+                    /*- $last is unset when the command printed nothing, so $last[0] can't point at older results -*/
+                    let documents = output_handler::documents_since(mark);
+                    if documents.is_empty() {
+                        self.variables.remove(variables::LAST);
+                    }else {
+                        self.variables.insert(variables::LAST.to_string(), documents);
+                    }
+                }
+            },
+            Err(e) => output_handler::throw_res(Color::Red, &e),
+        }
+        return argv;
+    }
+
+    /*- A built in command, scripts and aliases with the same name are hidden by it -*/
+    pub fn is_command(&self, name:&str) -> bool {
+        return self.commands.iter().any(|cmd| cmd._name == name);
+    }
+
+    /*- Run a command and the stages it's piped through, returns the command -*/
+    fn run_pipeline(&mut self, mut stages:Stages) -> Vec<String> {
+        let command_vec = stages.remove(0);
+
+        /*- let x = get all | fields email keeps what the last stage printed -*/
+        let assign_to = match command_vec.as_slice() {
+            [keyword, name, equals, _, ..] if keyword == "let" && equals == "=" && !stages.is_empty() => Some(name.clone()),
+            _ => None,
+        };
+        let command_vec = match &assign_to {
+            Some(name) => {
+                if let Err(e) = variables::valid_name(name) {
+                    output_handler::throw_res(Color::Red, &e);
+                    return command_vec;
+                }
+                command_vec[3..].to_vec()
+            },
+            None => command_vec,
+        };
+
+        let command_vec = match variables::substitute(&command_vec, &self.variables) {
+            Ok(command_vec) => command_vec,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return command_vec;
+            }
+        };
+        if stages.is_empty() {
+            return self.dispatch(command_vec);
+        }
+
+        let errors = output_handler::error_count();
+        output_handler::start_hold();
+        let ran = self.dispatch(command_vec);
+        let mut input = output_handler::finish_hold();
+
+        let last = stages.len() - 1;
+        for (index, stage) in stages.into_iter().enumerate() {
+            if output_handler::error_count() != errors { break; }
+
+            let stage = match variables::substitute(&stage, &self.variables) {
+                Ok(stage) => stage,
+                Err(e) => {
+                    output_handler::throw_res(Color::Red, &e);
+                    break;
+                }
+            };
+
+            let cmd = match self.commands.iter().find(|cmd| cmd._name == stage[0]) {
+                Some(cmd) => cmd,
+                None => {
+                    output_handler::throw_res(Color::Rgb(255, 0, 0),
+                        format!("Command <{}> was not found{}", &stage[0], self.did_you_mean(&stage[0])).as_str()
+                    );
+                    break;
+                }
+            };
+            let bind = match cmd._bind {
+                Handler::Stage(bind) => bind,
+                _ => {
+                    output_handler::throw_res(Color::Red, format!("<{}> can't be used after a |", cmd._name).as_str());
+                    break;
+                },
+            };
+            if let Err(lines) = registry::validate(cmd, &stage[1..]) {
+                for line in lines {
+                    output_handler::throw_res(Color::Red, &line);
+                }
+                break;
+            }
+
+            let mark = output_handler::capture_mark();
+            if index != last { output_handler::start_hold(); }
+
+            let given = std::mem::take(&mut input);
+            if panic::catch_unwind(AssertUnwindSafe(|| bind(stage[1..].to_vec(), given))).is_err() {
+                output_handler::throw_res(Color::Red, format!("Command <{}> failed unexpectedly", &stage[0]).as_str());
+            }
+
+            if index != last {
+                input = output_handler::finish_hold();
+            }else if let Some(name) = &assign_to {
+                let documents = output_handler::documents_since(mark);
+                output_handler::throw_res(Color::Cyan, format!("${} = {} document(s)", name, documents.len()).as_str());
+                self.variables.insert(name.to_string(), documents);
+            }
+        }
+
+        return ran;
+    }
+
+    /*- Validate the params against the spec and call the command, returns the command that ran -*/
+    fn dispatch(&mut self, command_vec:Vec<String>) -> Vec<String> {
+        self.previous.push(command_vec.clone());
+
+        /*- An alias is replaced by its command, the params after it are appended -*/
+        let command_vec:Vec<String> = match self.aliases.get(&command_vec[0]) {
+            Some(command) => pipeline::split_words(command).into_iter()
+                .chain(command_vec[1..].iter().cloned())
+                .collect(),
+            None => command_vec,
+        };
+
+        let cmd = match self.commands.iter().find(|cmd| cmd._name == command_vec[0]) {
+            Some(cmd) => cmd.clone(),
+
+            /*- Scripts in the scripts directory are read when they're called, so new ones work right away -*/
+            None if scripting::find_script(&command_vec[0]).is_some() => {
+                let name = command_vec[0].to_string();
+                if panic::catch_unwind(AssertUnwindSafe(|| scripting::run_script(self, &name, command_vec[1..].to_vec()))).is_err() {
+                    output_handler::throw_res(Color::Red, format!("Script <{}> failed unexpectedly", name).as_str());
+                }
+                return command_vec;
+            },
+            None => {
+                output_handler::throw_res(Color::Rgb(255, 0, 0),
+                    format!("Command <{}> was not found{}", &command_vec[0], self.did_you_mean(&command_vec[0])).as_str()
+                );
+                return command_vec;
+            }
+        };
+
+        let argv = command_vec[1..].to_vec();
+        if let Err(lines) = registry::validate(&cmd, &argv) {
+            for line in lines {
+                output_handler::throw_res(Color::Red, &line);
+            }
+            return command_vec;
+        }
+
+        /*- A panicking command shouldn't take the session down -*/
+        let ran = match cmd._bind {
+            Handler::Command(bind) => panic::catch_unwind(AssertUnwindSafe(|| bind(argv))).is_ok(),
+            Handler::Session(bind) => panic::catch_unwind(AssertUnwindSafe(|| bind(self, argv))).is_ok(),
+            Handler::Stage(_) => {
+                output_handler::throw_res(Color::Red, format!("<{}> only works after a |, like <get all | {}>", cmd._name, cmd._name).as_str());
+                true
+            },
+        };
+        if !ran {
+            output_handler::throw_res(Color::Red, format!("Command <{}> failed unexpectedly", cmd._name).as_str());
+        }
+
+        /*- prev ran another command, report that one -*/
+        if cmd._name == "prev" {
+            return self.previous.last().cloned().unwrap_or(command_vec);
+        }
+        return command_vec;
+    }
+
+    /*- ", did you mean <x>?" when <name> looks like a typo of a command or an alias -*/
+    fn did_you_mean(&self, name:&str) -> String {
+        let scripts = scripting::script_names();
+        let names = self.commands.iter().map(|cmd| cmd._name)
+            .chain(self.aliases.keys().map(|alias| alias.as_str()))
+            .chain(scripts.iter().map(|script| script.as_str()));
+
+        return match registry::suggest(name, names) {
+            Some(suggestion) => format!(", did you mean <{}>?", suggestion),
+            None => String::new(),
+        };
+    }
+}
+
+/*- tag <name> -*/
+pub fn tag(session:&mut Session, argv:Vec<String>) {
+    session.tag = argv[0].to_string();
+}
+
+/*- Run the command before this one again -*/
+pub fn prev(session:&mut Session, _:Vec<String>) {
+
+    /*- The last one is this prev -*/
+    session.previous.pop();
+    match session.previous.pop() {
+        Some(command_vec) => { session.dispatch(command_vec); },
+        None => output_handler::throw_res(Color::Red, "There is no previous command"),
+    }
+}
+
+/*- The frontend decides what exiting means, the REPL stops its loop -*/
+pub fn exit(session:&mut Session, _:Vec<String>) {
+    output_handler::throw_res(Color::Cyan, "Exiting...");
+    session.exit = true;
+}
+
+/*- alias list, alias <name> = <command>... -*/
+pub fn alias(session:&mut Session, argv:Vec<String>) {
+    if argv.len() == 1 {
+        if session.aliases.is_empty() {
+            output_handler::throw_res(Color::Cyan, "No aliases yet, add one with <alias name = command>");
+        }
+        for (name, command) in &session.aliases {
+            output_handler::throw_res(Color::Green, format!("{} = {}", name, command).as_str());
+        }
+        return;
+    }
+
+    let name = &argv[0];
+    let command = argv[2..].join(" ");
+
+    /*- Aliases can't hide commands, and only expand once so they have to point to a command or a script -*/
+    if session.is_command(name) {
+        output_handler::throw_res(Color::Red, format!("<{}> is already a command, pick another name", name).as_str());
+        return;
+    }
+    if !session.is_command(&argv[2]) && scripting::find_script(&argv[2]).is_none() {
+        output_handler::throw_res(Color::Red, format!("Command <{}> was not found{}", &argv[2], session.did_you_mean(&argv[2])).as_str());
+        return;
+    }
+
+    let mut profile = match config::load_profile() {
+        Ok(profile) => profile,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let previous = profile.aliases.insert(name.to_string(), command.clone());
+
+    if let Err(e) = config::save_profile(&profile) {
+        output_handler::throw_res(Color::Red, &e);
+        return;
+    }
+    session.aliases = profile.aliases;
+
+    match previous {
+        Some(previous) => output_handler::throw_res(Color::Green, format!("Alias <{}> changed from <{}> to <{}>", name, previous, command).as_str()),
+        None => output_handler::throw_res(Color::Green, format!("Alias <{}> added", name).as_str()),
+    }
+}
+
+/*- unalias <name> -*/
+pub fn unalias(session:&mut Session, argv:Vec<String>) {
+    let mut profile = match config::load_profile() {
+        Ok(profile) => profile,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+
+    if profile.aliases.remove(&argv[0]).is_none() {
+        output_handler::throw_res(Color::Red, format!("There is no alias named <{}>", &argv[0]).as_str());
+        return;
+    }
+    if let Err(e) = config::save_profile(&profile) {
+        output_handler::throw_res(Color::Red, &e);
+        return;
+    }
+    session.aliases = profile.aliases;
+
+    output_handler::throw_res(Color::Green, format!("Alias <{}> removed", &argv[0]).as_str());
+}
+
+/*- let <name> = <command>..., keeps the documents the command printed -*/
+pub fn assign(session:&mut Session, argv:Vec<String>) {
+    if let Err(e) = variables::valid_name(&argv[0]) {
+        output_handler::throw_res(Color::Red, &e);
+        return;
+    }
+
+    let mark = output_handler::capture_mark();
+    session.dispatch(argv[2..].to_vec());
+    let documents = output_handler::documents_since(mark);
+
+    output_handler::throw_res(Color::Cyan, format!("${} = {} document(s)", &argv[0], documents.len()).as_str());
+    session.variables.insert(argv[0].to_string(), documents);
+}
@@ -0,0 +1,155 @@
+use termcolor::{ Color };
+use chrono::{ Datelike, NaiveDate, TimeZone };
+use chrono_tz::Tz;
+use mongodb::bson::{ doc, Document };
+use std::collections::BTreeMap;
+use crate::{ commands, config, dates, output_handler, users };
+
+/*- How many email domains to show -*/
+static TOP_DOMAINS: usize = 10;
+
+/*- Parse YYYY-MM-DD as the start of that day (or the day after) in a timezone, in unix milliseconds -*/
+fn parse_day(input:&str, tz:&Tz, next_day:bool) -> Result<i64, String> {
+    let mut date = NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date <{}>, use YYYY-MM-DD", input))?;
+
+    if next_day {
+        date = date.succ_opt().ok_or(format!("Invalid date <{}>", input))?;
+    }
+
+    return tz.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map(|date| date.timestamp_millis())
+        .ok_or(format!("<{}> doesn't exist in {}", input, tz));
+}
+
+/*- The bucket a signup falls into -*/
+fn bucket(unix_ms:i64, tz:&Tz, by:&str) -> Option<String> {
+    let date = tz.timestamp_millis_opt(unix_ms).single()?;
+
+    return Some(match by {
+        "day" => date.format("%Y-%m-%d").to_string(),
+        "week" => format!("{}-W{:02}", date.iso_week().year(), date.iso_week().week()),
+        _ => date.format("%Y-%m").to_string(),
+    });
+}
+
+/*- Count occurrences and sort them by amount, most common first -*/
+fn ranked(counts:BTreeMap<String, usize>) -> Vec<(String, usize)> {
+    let mut rows:Vec<(String, usize)> = counts.into_iter().collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    return rows;
+}
+
+/*- Signup and account statistics -*/
+fn user_stats(since:Option<i64>, until:Option<i64>, by:&str, tz:&Tz, json:bool) {
+    let coll = users::users_collection();
+
+    /*- The window applies to joined.unix -*/
+    let mut window = Document::new();
+    if let Some(since) = since { window.insert("$gte", since); }
+    if let Some(until) = until { window.insert("$lt", until); }
+    let filter = if window.is_empty() { doc! {} } else { doc! { "joined.unix": window } };
+
+    let accounts:Vec<Document> = match coll.find(filter, None) {
+        Ok(documents) => documents,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get users: {}", e).as_str());
+            return;
+        }
+    };
+
+    let mut roles:BTreeMap<String, usize> = BTreeMap::new();
+    let mut signups:BTreeMap<String, usize> = BTreeMap::new();
+    let mut username_lengths:BTreeMap<usize, usize> = BTreeMap::new();
+    let mut domains:BTreeMap<String, usize> = BTreeMap::new();
+
+    for user in &accounts {
+        *roles.entry(user.get_str("role").unwrap_or("<none>").to_string()).or_insert(0) += 1;
+
+        if let Some(key) = dates::joined_unix(user).and_then(|unix| bucket(unix, tz, by)) {
+            *signups.entry(key).or_insert(0) += 1;
+        }
+        if let Ok(username) = user.get_str("username") {
+            *username_lengths.entry(username.chars().count()).or_insert(0) += 1;
+        }
+        if let Some((_, domain)) = user.get_str("email").unwrap_or("").rsplit_once('@') {
+            *domains.entry(domain.to_lowercase()).or_insert(0) += 1;
+        }
+    }
+
+    let domains = ranked(domains);
+
+    if json {
+        let output = serde_json::json!({
+            "total": accounts.len(),
+            "since": since,
+            "until": until,
+            "roles": roles,
+            "signups": { "by": by, "buckets": signups },
+            "username_lengths": username_lengths,
+            "email_domains": domains.iter().cloned().collect::<BTreeMap<String, usize>>(),
+        });
+        output_handler::throw_raw(&serde_json::to_string_pretty(&output).unwrap());
+        return;
+    }
+
+    output_handler::throw_res(Color::Green, format!("Total accounts: {}", accounts.len()).as_str());
+
+    output_handler::throw_res(Color::Cyan, "Accounts per role");
+    output_handler::throw_histogram(&ranked(roles));
+
+    output_handler::throw_res(Color::Cyan, format!("Signups per {}", by).as_str());
+    output_handler::throw_histogram(&signups.into_iter().collect::<Vec<(String, usize)>>());
+
+    output_handler::throw_res(Color::Cyan, "Username lengths");
+    output_handler::throw_histogram(&username_lengths.into_iter().map(|(len, amount)| (len.to_string(), amount)).collect::<Vec<(String, usize)>>());
+
+    output_handler::throw_res(Color::Cyan, "Email domains");
+    output_handler::throw_histogram(&domains.iter().take(TOP_DOMAINS).cloned().collect::<Vec<(String, usize)>>());
+    if domains.len() > TOP_DOMAINS {
+        output_handler::throw_res(Color::Green, format!("...and {} more domains", domains.len() - TOP_DOMAINS).as_str());
+    }
+}
+
+/*- Statistics reports -*/
+pub fn stats(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let since = commands::take_option(&mut argv, "--since");
+    let until = commands::take_option(&mut argv, "--until");
+    let by = commands::take_option(&mut argv, "--by").unwrap_or(String::from("month"));
+    let tz_name = commands::take_option(&mut argv, "--tz").unwrap_or_else(config::timezone);
+    let json = commands::take_flag(&mut argv, "--json");
+
+    if &argv[0] == "users" && argv.len() == 1 {
+        if !["day", "week", "month"].contains(&by.as_str()) {
+            output_handler::throw_res(Color::Red, "--by must be day, week or month");
+            return;
+        }
+
+        let tz = match dates::parse_timezone(&tz_name) {
+            Ok(tz) => tz,
+            Err(e) => {
+                output_handler::throw_res(Color::Red, &e);
+                return;
+            }
+        };
+
+        /*- --until is inclusive, so the window ends at the start of the next day -*/
+        let window = (
+            since.map(|day| parse_day(&day, &tz, false)).transpose(),
+            until.map(|day| parse_day(&day, &tz, true)).transpose(),
+        );
+        match window {
+            (Ok(since), Ok(until)) => user_stats(since, until, &by, &tz, json),
+            (Err(e), _) | (_, Err(e)) => output_handler::throw_res(Color::Red, &e),
+        }
+    }
+    else {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help stats> for further information.");
+    }
+}
@@ -0,0 +1,221 @@
+use mongodb::{
+    bson::{ doc, Bson, Document },
+    options::{ FindOptions, IndexOptions, UpdateOptions },
+    sync::{ Client, Collection },
+    IndexModel,
+};
+use std::cell::Cell;
+use crate::{ commands, memory_store::MemoryStore };
+
+thread_local! {
+    /*- Set by the session running on this thread, see Session::in_memory -*/
+    static MEMORY: Cell<bool> = const { Cell::new(false) };
+}
+
+/*- Amount of documents matched and changed by an update -*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpdateCount {
+    pub matched: u64,
+    pub modified: u64,
+}
+
+/*- One update in update_atomically -*/
+#[derive(Debug, Clone)]
+pub struct UpdateOp {
+    pub filter: Document,
+    pub update: Document,
+    pub options: Option<UpdateOptions>,
+
+    /*- update_many instead of update_one -*/
+    pub many: bool,
+}
+
+/*- Everything the commands need from a collection. Implemented for mongo and for the in-memory store -*/
+pub trait DocumentStore: Send + Sync {
+    fn find(&self, filter:Document, options:Option<FindOptions>) -> Result<Vec<Document>, String>;
+    fn count(&self, filter:Document) -> Result<u64, String>;
+
+    /*- Returns the _id of the inserted document -*/
+    fn insert_one(&self, document:Document) -> Result<Bson, String>;
+    fn insert_many(&self, documents:Vec<Document>) -> Result<usize, String>;
+
+    fn update_one(&self, filter:Document, update:Document, options:Option<UpdateOptions>) -> Result<UpdateCount, String>;
+    fn update_many(&self, filter:Document, update:Document, options:Option<UpdateOptions>) -> Result<UpdateCount, String>;
+    fn replace_one(&self, filter:Document, replacement:Document) -> Result<UpdateCount, String>;
+
+    /*- Return the amount of deleted documents -*/
+    fn delete_one(&self, filter:Document) -> Result<u64, String>;
+    fn delete_many(&self, filter:Document) -> Result<u64, String>;
+
+    /*- Returns the name of the index -*/
+    fn create_index(&self, keys:Document, unique:bool) -> Result<String, String>;
+    fn list_indexes(&self) -> Result<Vec<Document>, String>;
+
+    fn aggregate(&self, pipeline:Vec<Document>) -> Result<Vec<Document>, String>;
+
+    /*- Apply all updates or none of them. Returns false if the backend couldn't use a transaction
+        and the updates were applied one by one -*/
+    fn update_atomically(&self, updates:Vec<UpdateOp>) -> Result<bool, String>;
+
+    fn find_one(&self, filter:Document) -> Result<Option<Document>, String> {
+        let options = FindOptions::builder().limit(1).build();
+        return self.find(filter, Some(options)).map(|documents| documents.into_iter().next());
+    }
+}
+
+/*- Run the commands on this thread against the in-memory store instead of mongo -*/
+pub fn set_memory(memory:bool) {
+    MEMORY.with(|cell| cell.set(memory));
+}
+
+pub fn is_memory() -> bool {
+    return MEMORY.with(|cell| cell.get());
+}
+
+/*- Open a collection in the backend of the session running on this thread -*/
+pub fn open(database:&str, collection:&str) -> Box<dyn DocumentStore> {
+    if is_memory() {
+        return Box::new(MemoryStore::new(database, collection));
+    }
+    return Box::new(MongoStore::new(commands::get_client(), database, collection));
+}
+
+/*- A collection on the mongo server -*/
+pub struct MongoStore {
+    client: Client,
+    collection: Collection<Document>,
+}
+
+impl MongoStore {
+    pub fn new(client:Client, database:&str, collection:&str) -> MongoStore {
+        let collection = client.database(database).collection::<Document>(collection);
+        return MongoStore { client, collection };
+    }
+
+    /*- Transactions only work on replica sets and sharded clusters -*/
+    fn supports_transactions(&self) -> bool {
+        let hello = match self.client.database("admin").run_command(doc! { "isMaster": 1 }, None) {
+            Ok(hello) => hello,
+            Err(_) => return false,
+        };
+
+        return hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid");
+    }
+}
+
+impl DocumentStore for MongoStore {
+    fn find(&self, filter:Document, options:Option<FindOptions>) -> Result<Vec<Document>, String> {
+        return self.collection.find(filter, options)
+            .and_then(|cursor| cursor.collect())
+            .map_err(|e| e.to_string());
+    }
+
+    fn find_one(&self, filter:Document) -> Result<Option<Document>, String> {
+        return self.collection.find_one(filter, None).map_err(|e| e.to_string());
+    }
+
+    fn count(&self, filter:Document) -> Result<u64, String> {
+        return self.collection.count_documents(filter, None).map_err(|e| e.to_string());
+    }
+
+    fn insert_one(&self, document:Document) -> Result<Bson, String> {
+        return self.collection.insert_one(document, None)
+            .map(|result| result.inserted_id)
+            .map_err(|e| e.to_string());
+    }
+
+    fn insert_many(&self, documents:Vec<Document>) -> Result<usize, String> {
+        return self.collection.insert_many(documents, None)
+            .map(|result| result.inserted_ids.len())
+            .map_err(|e| e.to_string());
+    }
+
+    fn update_one(&self, filter:Document, update:Document, options:Option<UpdateOptions>) -> Result<UpdateCount, String> {
+        return self.collection.update_one(filter, update, options)
+            .map(|result| UpdateCount { matched: result.matched_count, modified: result.modified_count })
+            .map_err(|e| e.to_string());
+    }
+
+    fn update_many(&self, filter:Document, update:Document, options:Option<UpdateOptions>) -> Result<UpdateCount, String> {
+        return self.collection.update_many(filter, update, options)
+            .map(|result| UpdateCount { matched: result.matched_count, modified: result.modified_count })
+            .map_err(|e| e.to_string());
+    }
+
+    fn replace_one(&self, filter:Document, replacement:Document) -> Result<UpdateCount, String> {
+        return self.collection.replace_one(filter, replacement, None)
+            .map(|result| UpdateCount { matched: result.matched_count, modified: result.modified_count })
+            .map_err(|e| e.to_string());
+    }
+
+    fn delete_one(&self, filter:Document) -> Result<u64, String> {
+        return self.collection.delete_one(filter, None)
+            .map(|result| result.deleted_count)
+            .map_err(|e| e.to_string());
+    }
+
+    fn delete_many(&self, filter:Document) -> Result<u64, String> {
+        return self.collection.delete_many(filter, None)
+            .map(|result| result.deleted_count)
+            .map_err(|e| e.to_string());
+    }
+
+    fn create_index(&self, keys:Document, unique:bool) -> Result<String, String> {
+        let index = IndexModel::builder()
+            .keys(keys)
+            .options(IndexOptions::builder().unique(unique).build())
+            .build();
+
+        return self.collection.create_index(index, None)
+            .map(|result| result.index_name)
+            .map_err(|e| e.to_string());
+    }
+
+    fn list_indexes(&self) -> Result<Vec<Document>, String> {
+        let indexes:Vec<IndexModel> = self.collection.list_indexes(None)
+            .and_then(|cursor| cursor.collect())
+            .map_err(|e| e.to_string())?;
+
+        return Ok(indexes.into_iter().map(|index| {
+            let options = index.options.unwrap_or_default();
+            return doc! {
+                "name": options.name.unwrap_or_default(),
+                "key": index.keys,
+                "unique": options.unique.unwrap_or(false),
+            };
+        }).collect());
+    }
+
+
+    fn aggregate(&self, pipeline:Vec<Document>) -> Result<Vec<Document>, String> {
+        return self.collection.aggregate(pipeline, None)
+            .and_then(|cursor| cursor.collect())
+            .map_err(|e| e.to_string());
+    }
+
+    fn update_atomically(&self, updates:Vec<UpdateOp>) -> Result<bool, String> {
+        if !self.supports_transactions() {
+            for op in updates {
+                match op.many {
+                    true => self.collection.update_many(op.filter, op.update, op.options),
+                    false => self.collection.update_one(op.filter, op.update, op.options),
+                }.map_err(|e| e.to_string())?;
+            }
+            return Ok(false);
+        }
+
+        let result:mongodb::error::Result<()> = (|| {
+            let mut session = self.client.start_session(None)?;
+            session.start_transaction(None)?;
+            for op in updates {
+                match op.many {
+                    true => self.collection.update_many_with_session(op.filter, op.update, op.options, &mut session),
+                    false => self.collection.update_one_with_session(op.filter, op.update, op.options, &mut session),
+                }?;
+            }
+            return session.commit_transaction();
+        })();
+
+        return result.map(|_| true).map_err(|e| e.to_string());
+    }
+}
@@ -0,0 +1,487 @@
+use termcolor::{ Color };
+use mongodb::{
+    bson::{ doc, Bson, Document, oid::ObjectId },
+    options::UpdateOptions,
+};
+use std::path::{ Path, PathBuf };
+use crate::{ archive, audit, commands, config, dates, output_handler, store };
+use crate::store::{ DocumentStore, UpdateOp };
+
+/*- The API stores every account in this collection -*/
+pub static USERS_COLLECTION: &str = "users";
+
+/*- Profile images are stored here by the API -*/
+pub static PROFILE_DIR: &str = "uploads/profile";
+
+/*- The API refuses a second account with the same username or email -*/
+static UNIQUE_FIELDS: [&str; 2] = ["username", "email"];
+
+/*- Get the users collection in the current database -*/
+pub fn users_collection() -> Box<dyn DocumentStore> {
+    return commands::get_collection(USERS_COLLECTION);
+}
+
+/*- Create the unique indexes the users collection is missing, returns their names -*/
+pub fn ensure_unique_indexes(coll:&dyn DocumentStore) -> Result<Vec<String>, String> {
+    let existing = coll.list_indexes()?;
+    let mut created = Vec::new();
+
+    for field in UNIQUE_FIELDS {
+        let indexed = existing.iter().any(|index| {
+            index.get_bool("unique") == Ok(true)
+                && index.get_document("key").map(|key| key.len() == 1 && key.contains_key(field)).unwrap_or(false)
+        });
+        if indexed { continue; }
+
+        created.push(coll.create_index(doc! { field: 1 }, true)?);
+    }
+    return Ok(created);
+}
+
+/*- Find a user by ObjectId, suid, uid, username or email. The keys are tried in that order,
+    so a username that's also someone else's email always finds the same user -*/
+pub fn find_user(coll:&dyn DocumentStore, ident:&str) -> Result<Document, String> {
+    let mut filters = vec![
+        ("suid", doc! { "suid": ident }),
+        ("uid", doc! { "uid": ident }),
+        ("username", doc! { "username": ident }),
+        ("email", doc! { "email": ident }),
+    ];
+
+    /*- Only try the _id if it's a valid ObjectId -*/
+    if let Ok(oid) = ident.parse::<ObjectId>() {
+        filters.insert(0, ("_id", doc! { "_id": oid }));
+    }
+
+    for (key, filter) in filters {
+        let mut users = coll.find(filter, None).map_err(|e| format!("Failed to get users: {}", e))?;
+        match users.len() {
+            0 => continue,
+            1 => return Ok(users.remove(0)),
+            amount => return Err(format!("{} users have the {} {}, use their _id instead", amount, key, ident)),
+        }
+    }
+
+    return Err(String::from("User not found!"));
+}
+
+/*- With --memory the users aren't the ones the files in uploads/ belong to, so their images are left alone -*/
+fn keep_image(image:&Path) -> bool {
+    if !store::is_memory() { return false; }
+
+    output_handler::throw_res(Color::Cyan, format!("Leaving {} alone, files aren't changed with --memory", image.display()).as_str());
+    return true;
+}
+
+/*- Short description of a user, used in confirmations and listings -*/
+pub fn describe(user:&Document) -> String {
+    let joined = dates::display_joined(user).map(|joined| format!(", joined {}", joined)).unwrap_or_default();
+
+    return format!("{} ({}{})",
+        user.get_str("username").unwrap_or("<no username>"),
+        user.get_str("suid").unwrap_or("<no suid>"),
+        joined
+    );
+}
+
+/*- Where the API stores profile images, named after the suid -*/
+pub fn profile_image_path(suid:&str) -> PathBuf {
+    return Path::new(PROFILE_DIR).join(format!("{}.jpg", suid));
+}
+
+/*- The profile url the API would generate for a suid with the current CDN_URL -*/
+pub fn expected_profile_url(suid:&str) -> Option<String> {
+    return config::cdn_url().map(|cdn| format!("{}/api/profile-data/image/{}", cdn, suid));
+}
+
+/*- List users whose stored profile url doesn't match the current CDN_URL -*/
+fn check_profile_urls(coll:&dyn DocumentStore) {
+    let cdn = match config::cdn_url() {
+        Some(cdn) => cdn,
+        None => {
+            output_handler::throw_res(Color::Red, "CDN_URL is not set in the config!");
+            return;
+        }
+    };
+
+    let users = match coll.find(doc! {}, None) {
+        Ok(users) => users,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get users: {}", e).as_str());
+            return;
+        }
+    };
+
+    let mut mismatches = 0;
+    for user in users {
+        let suid = user.get_str("suid").unwrap_or("");
+        let profile = user.get_str("profile").unwrap_or("");
+
+        if Some(profile.to_string()) != expected_profile_url(suid) {
+            output_handler::throw_res(Color::Green, format!("{} -> {:?}", describe(&user), profile).as_str());
+            mismatches += 1;
+        }
+    }
+
+    output_handler::throw_res(Color::Cyan, format!("{} user(s) don't point to {}", mismatches, cdn).as_str());
+}
+
+/*- Replace the <from> prefix of every stored profile url with <to> -*/
+fn rewrite_profile_urls(coll:&dyn DocumentStore, from:&str, to:&str) {
+    /*- --from http://a.com must not match http://a.community, the prefix has to end at a /.
+        The caller trims the trailing / of both urls -*/
+    let pattern = format!("^{}(/|$)", regex::escape(from));
+    let matching:Vec<Document> = match coll.find(doc! { "profile": { "$regex": &pattern } }, None) {
+        Ok(users) => users,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get users: {}", e).as_str());
+            return;
+        }
+    };
+
+    if matching.is_empty() {
+        output_handler::throw_res(Color::Cyan, format!("No profile urls start with {}", from).as_str());
+        return;
+    }
+
+    /*- Show an example so that typos in --to are caught before anything is written -*/
+    let example = matching[0].get_str("profile").unwrap_or("");
+    output_handler::throw_res(Color::Cyan, format!("{}\n  -> {}{}", example, to, &example[from.len()..]).as_str());
+    if !commands::confirm(format!("Rewrite the profile url of {} user(s)?", matching.len()).as_str()) { return; }
+
+    let mut updated = 0;
+    for user in &matching {
+        let profile = user.get_str("profile").unwrap_or("");
+        let rewritten = format!("{}{}", to, &profile[from.len()..]);
+
+        match coll.update_one(doc! { "_id": user.get("_id").cloned().unwrap_or(Bson::Null) }, doc! { "$set": { "profile": rewritten } }, None) {
+            Ok(_) => updated += 1,
+            Err(e) => output_handler::throw_res(Color::Red, format!("Failed to update {}: {}", describe(user), e).as_str()),
+        }
+    }
+
+    audit::record("user.rewrite-profile-urls", "", doc! { "from": from, "to": to, "updated": updated });
+    output_handler::throw_res(Color::Green, format!("{} profile url(s) rewritten!", updated).as_str());
+}
+
+/*- Recompute joined.prettified from joined.unix, the API used to get the weekday wrong -*/
+fn repair_joined(coll:&dyn DocumentStore, tz_name:&str, dry_run:bool) {
+    let tz = match dates::parse_timezone(tz_name) {
+        Ok(tz) => tz,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let variables = match config::load_variables() {
+        Ok(variables) => variables,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+
+    let users = match coll.find(doc! {}, None) {
+        Ok(users) => users,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get users: {}", e).as_str());
+            return;
+        }
+    };
+
+    /*- (_id, new value), and the users that can't be repaired -*/
+    let mut changes:Vec<(Bson, String)> = Vec::new();
+    let mut missing = 0;
+    for user in users {
+        let new = match dates::format_joined(&user, &tz, &variables) {
+            Some(new) => new,
+            None => {
+                output_handler::throw_res(Color::Red, format!("{} has no valid joined.unix", describe(&user)).as_str());
+                missing += 1;
+                continue;
+            }
+        };
+        let old = user.get_document("joined").ok()
+            .and_then(|joined| joined.get_str("prettified").ok())
+            .unwrap_or("");
+
+        if old != new {
+            output_handler::throw_res(Color::Green, format!("{}\n  - {}\n  + {}", describe(&user), old, new).as_str());
+            changes.push((user.get("_id").cloned().unwrap_or(Bson::Null), new));
+        }
+    }
+
+    output_handler::throw_res(Color::Cyan,
+        format!("{} user(s) to repair in {}, {} without joined.unix", changes.len(), tz_name, missing).as_str()
+    );
+    if dry_run || changes.is_empty() { return; }
+    if !commands::confirm(format!("Repair {} joined date(s)?", changes.len()).as_str()) { return; }
+
+    let mut updated = 0;
+    for (id, prettified) in &changes {
+        match coll.update_one(doc! { "_id": id }, doc! { "$set": { "joined.prettified": prettified } }, None) {
+            Ok(_) => updated += 1,
+            Err(e) => output_handler::throw_res(Color::Red, format!("Failed to update {}: {}", id, e).as_str()),
+        }
+    }
+
+    audit::record("user.repair-joined", "", doc! { "timezone": tz_name, "updated": updated });
+    output_handler::throw_res(Color::Green, format!("{} joined date(s) repaired!", updated).as_str());
+}
+
+/*- Delete a user, their friend references and profile image. Everything is archived first -*/
+fn delete_user(coll:&dyn DocumentStore, ident:&str) {
+    let user = match find_user(coll, ident) {
+        Ok(user) => user,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let suid = user.get_str("suid").unwrap_or("").to_string();
+
+    /*- Everyone that has this user as a friend -*/
+    let referenced_by:Vec<Document> = match coll.find(doc! { "friends": &suid }, None) {
+        Ok(users) => users,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, format!("Failed to get friend references: {}", e).as_str());
+            return;
+        }
+    };
+    let image = profile_image_path(&suid);
+    let has_image = !suid.is_empty() && image.is_file() && !keep_image(&image);
+
+    /*- Show the whole impact before asking -*/
+    output_handler::throw_res(Color::Cyan, format!("Deleting {} will:", describe(&user)).as_str());
+    output_handler::throw_res(Color::Cyan, "  - remove the user document");
+    output_handler::throw_res(Color::Cyan, format!("  - remove them from the friends of {} user(s)", referenced_by.len()).as_str());
+    for friend in &referenced_by {
+        output_handler::throw_res(Color::Cyan, format!("      {}", describe(friend)).as_str());
+    }
+    if has_image {
+        output_handler::throw_res(Color::Cyan, format!("  - delete {}", image.display()).as_str());
+    }
+    if !commands::confirm("Continue?") { return; }
+
+    /*- Archive everything before touching anything -*/
+    let dir = match archive::create_dir(&suid) {
+        Ok(dir) => dir,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let image_name = String::from("profile.jpg");
+    if has_image {
+        if let Err(e) = std::fs::copy(&image, dir.join(&image_name)) {
+            output_handler::throw_res(Color::Red, format!("Failed to archive profile image: {}", e).as_str());
+            return;
+        }
+    }
+
+    let referenced_suids:Vec<String> = referenced_by.iter()
+        .filter_map(|friend| friend.get_str("suid").ok().map(|s| s.to_string()))
+        .collect();
+    let manifest = archive::UserArchive::new(&user, referenced_suids, if has_image { Some(image_name) } else { None }, audit::operator());
+    if let Err(e) = archive::write(&dir, &manifest) {
+        output_handler::throw_res(Color::Red, &e);
+        return;
+    }
+
+    /*- Remove the user, and then every reference to them -*/
+    if let Err(e) = coll.delete_one(doc! { "_id": user.get("_id").cloned().unwrap_or(Bson::Null) }) {
+        output_handler::throw_res(Color::Red, format!("Failed to delete user: {}", e).as_str());
+        return;
+    }
+    let pulled = coll.update_many(doc! { "friends": &suid }, doc! { "$pull": { "friends": &suid } }, None)
+        .map(|count| count.modified)
+        .unwrap_or_else(|e| {
+            output_handler::throw_res(Color::Red, format!("Failed to remove friend references: {}", e).as_str());
+            0
+        });
+    if has_image {
+        if let Err(e) = std::fs::remove_file(&image) {
+            output_handler::throw_res(Color::Red, format!("Failed to delete {}: {}", image.display(), e).as_str());
+        }
+    }
+
+    audit::record("user.delete", &suid, doc! { "archive": dir.display().to_string(), "friend_references": pulled as i64 });
+
+    output_handler::throw_res(Color::Green, format!("User deleted! Archived to {}", dir.display()).as_str());
+    output_handler::throw_res(Color::Green, format!("Undo with <user restore {}>", dir.display()).as_str());
+}
+
+/*- Undo a user deletion using its archive -*/
+fn restore_user(coll:&dyn DocumentStore, dir:&str) {
+    let dir = Path::new(dir);
+    let manifest = match archive::read(dir) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let user = match manifest.user_document() {
+        Ok(user) => user,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let suid = user.get_str("suid").unwrap_or("").to_string();
+
+    /*- The suid might have been taken again in the meantime -*/
+    if coll.count(doc! { "suid": &suid }).unwrap_or(0) > 0 {
+        output_handler::throw_res(Color::Red, format!("A user with suid {} already exists!", suid).as_str());
+        return;
+    }
+
+    if !commands::confirm(format!("Restore {} and {} friend reference(s)?", describe(&user), manifest.referenced_by.len()).as_str()) {
+        return;
+    }
+
+    if let Err(e) = coll.insert_one(user.clone()) {
+        output_handler::throw_res(Color::Red, format!("Failed to restore user: {}", e).as_str());
+        return;
+    }
+
+    /*- Friends that got deleted after the archive was made are simply skipped -*/
+    if let Err(e) = coll.update_many(
+        doc! { "suid": { "$in": &manifest.referenced_by } },
+        doc! { "$addToSet": { "friends": &suid } },
+        None
+    ) {
+        output_handler::throw_res(Color::Red, format!("Failed to restore friend references: {}", e).as_str());
+    }
+
+    let target = profile_image_path(&suid);
+    if let Some(image) = manifest.image.as_ref().filter(|_| !keep_image(&target)) {
+        if let Err(e) = std::fs::copy(dir.join(image), &target) {
+            output_handler::throw_res(Color::Red, format!("Failed to restore {}: {}", target.display(), e).as_str());
+        }
+    }
+
+    audit::record("user.restore", &suid, doc! { "archive": dir.display().to_string() });
+
+    output_handler::throw_res(Color::Green, format!("Restored {}!", describe(&user)).as_str());
+}
+
+/*- Generate a suid the same way the API does, 16 random bytes as hex -*/
+pub fn generate_suid() -> String {
+    let bytes:[u8; 16] = rand::random();
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+/*- Replace the suid of a user and every reference to it -*/
+fn rotate_suid(ident:&str) {
+    let coll = users_collection();
+
+    let user = match find_user(&*coll, ident) {
+        Ok(user) => user,
+        Err(e) => {
+            output_handler::throw_res(Color::Red, &e);
+            return;
+        }
+    };
+    let id = user.get("_id").cloned().unwrap_or(Bson::Null);
+    let old = match user.get_str("suid") {
+        Ok(suid) if !suid.is_empty() => suid.to_string(),
+        _ => {
+            output_handler::throw_res(Color::Red, "User has no suid to rotate!");
+            return;
+        }
+    };
+    let new = generate_suid();
+
+    /*- The profile url contains the suid -*/
+    let profile = user.get_str("profile").unwrap_or("").replace(&old, &new);
+
+    let references = coll.count(doc! { "friends": &old }).unwrap_or(0);
+    if !commands::confirm(format!(
+        "Rotate the suid of {}? {} friend reference(s) will be updated.", describe(&user), references
+    ).as_str()) { return; }
+
+    /*- Move the profile image first, it's moved back if the database update fails -*/
+    let old_image = profile_image_path(&old);
+    let new_image = profile_image_path(&new);
+    let has_image = old_image.is_file() && !keep_image(&old_image);
+    if has_image {
+        if let Err(e) = std::fs::rename(&old_image, &new_image) {
+            output_handler::throw_res(Color::Red, format!("Failed to rename {}: {}", old_image.display(), e).as_str());
+            return;
+        }
+    }
+
+    let user_update = UpdateOp {
+        filter: doc! { "_id": &id },
+        update: doc! { "$set": { "suid": &new, "profile": &profile } },
+        options: None,
+        many: false,
+    };
+    /*- An array filter replaces every occurrence, "friends.$" would only replace the first -*/
+    let friends_update = UpdateOp {
+        filter: doc! { "friends": &old },
+        update: doc! { "$set": { "friends.$[friend]": &new } },
+        options: Some(UpdateOptions::builder().array_filters(vec![doc! { "friend": &old }]).build()),
+        many: true,
+    };
+
+    let result = coll.update_atomically(vec![user_update, friends_update]);
+    if let Ok(false) = result {
+        output_handler::throw_res(Color::Yellow, "Server doesn't support transactions, updated without one.");
+    }
+
+    if let Err(e) = result {
+        output_handler::throw_res(Color::Red, format!("Failed to rotate suid: {}", e).as_str());
+
+        if has_image {
+            if let Err(e) = std::fs::rename(&new_image, &old_image) {
+                output_handler::throw_res(Color::Red, format!("Failed to move {} back: {}", new_image.display(), e).as_str());
+            }
+        }
+        return;
+    }
+
+    audit::record("user.rotate-suid", &new, doc! { "from": &old, "friend_references": references as i64, "transaction": result.unwrap_or(false) });
+
+    output_handler::throw_res(Color::Green, format!("Suid rotated! New suid: {}", new).as_str());
+}
+
+/*- Account level operations that touch more than one document -*/
+pub fn user(argv:Vec<String>) {
+
+    /*- Validate the input -*/
+    if !commands::check_argv(&argv) { return; }
+
+    let mut argv = argv;
+    let from = commands::take_option(&mut argv, "--from");
+    let to = commands::take_option(&mut argv, "--to");
+    let check = commands::take_flag(&mut argv, "--check");
+    let dry_run = commands::take_flag(&mut argv, "--dry-run");
+    let tz = commands::take_option(&mut argv, "--tz").unwrap_or_else(config::timezone);
+
+    let coll = users_collection();
+
+    if &argv[0] == "delete" && argv.len() == 2 {
+        delete_user(&*coll, &argv[1]);
+    }
+    else if &argv[0] == "restore" && argv.len() == 2 {
+        restore_user(&*coll, &argv[1]);
+    }
+    else if &argv[0] == "rotate-suid" && argv.len() == 2 {
+        rotate_suid(&argv[1]);
+    }
+    else if &argv[0] == "repair-joined" && argv.len() == 1 {
+        repair_joined(&*coll, &tz, dry_run);
+    }
+    else if &argv[0] == "rewrite-profile-urls" && argv.len() == 1 {
+        match (check, from, to) {
+            (true, _, _) => check_profile_urls(&*coll),
+            (false, Some(from), Some(to)) => rewrite_profile_urls(&*coll, from.trim_end_matches('/'), to.trim_end_matches('/')),
+            _ => output_handler::throw_res(Color::Red, "Either --check or both --from and --to are required"),
+        }
+    }
+    else {
+        output_handler::throw_res(Color::Red, "Invalid syntax! Write <help user> for further information.");
+    }
+}
@@ -0,0 +1,175 @@
+use mongodb::bson::{ Bson, Document };
+use regex::{ Captures, Regex };
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+/*- The documents of the last command, unset when it printed none -*/
+pub static LAST: &str = "last";
+
+lazy_static! {
+    /*- $name followed by any amount of [index] and .field -*/
+    static ref REFERENCE: Regex = Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)((?:\[[0-9]+\]|\.[A-Za-z0-9_]+)*)").unwrap();
+}
+
+/*- Variables of a session, every variable is a result set -*/
+pub type Variables = HashMap<String, Vec<Document>>;
+
+/*- Names for <let>, $last is set by the session -*/
+pub fn valid_name(name:&str) -> Result<(), String> {
+    if name == LAST {
+        return Err(format!("<{}> is set automatically, pick another name", LAST));
+    }
+    let valid = name.starts_with(|c:char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("<{}> is not a valid name, use letters, digits and _", name));
+    }
+    return Ok(());
+}
+
+/*- How a value is written into the command. Strings and ObjectIds as is, the rest as JSON -*/
+pub fn format_value(value:&Bson) -> String {
+    return match value {
+        Bson::String(s) => s.to_string(),
+        Bson::ObjectId(id) => id.to_hex(),
+        Bson::Int32(n) => n.to_string(),
+        Bson::Int64(n) => n.to_string(),
+        Bson::Double(n) => n.to_string(),
+        Bson::Boolean(b) => b.to_string(),
+        Bson::Null => String::from("null"),
+        other => other.clone().into_relaxed_extjson().to_string(),
+    };
+}
+
+/*- Follow the [index] and .field accessors of a reference -*/
+fn resolve(name:&str, accessors:&str, documents:&[Document]) -> Result<String, String> {
+    let mut value = Bson::Array(documents.iter().cloned().map(Bson::Document).collect());
+    let mut path = format!("${}", name);
+
+    /*- $admin.suid is short for $admin[0].suid when the variable has one document -*/
+    let mut rest = accessors;
+    if rest.starts_with('.') {
+        if documents.len() != 1 {
+            return Err(format!("{} has {} documents, pick one with {}[0]", path, documents.len(), path));
+        }
+        value = Bson::Document(documents[0].clone());
+    }
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').unwrap_or(after.len());
+            let index = after[..end].parse::<usize>().unwrap_or(usize::MAX);
+            value = match &value {
+                Bson::Array(items) => items.get(index).cloned()
+                    .ok_or(format!("{} has {} item(s), there is no [{}]", path, items.len(), &after[..end]))?,
+                _ => return Err(format!("{} is not a list", path)),
+            };
+            path.push_str(&rest[..end + 2]);
+            rest = &after[(end + 1).min(after.len())..];
+        }else {
+            let after = &rest[1..];
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let field = &after[..end];
+            value = match &value {
+                Bson::Document(document) => document.get(field).cloned()
+                    .ok_or(format!("{} has no field <{}>", path, field))?,
+                _ => return Err(format!("{} is not a document", path)),
+            };
+            path.push('.');
+            path.push_str(field);
+            rest = &after[end..];
+        }
+    }
+
+    return Ok(format_value(&value));
+}
+
+/*- Replace the references to defined variables in every param. Unknown $names are left alone
+    because aggregation pipelines are full of them, and so is anything right after a quote like "$last" -*/
+pub fn substitute(argv:&[String], variables:&Variables) -> Result<Vec<String>, String> {
+    let mut substituted:Vec<String> = Vec::new();
+
+    for arg in argv {
+        let mut error:Option<String> = None;
+        let replaced = REFERENCE.replace_all(arg, |caps:&Captures| {
+            let whole = caps.get(0).unwrap();
+            let quoted = arg[..whole.start()].ends_with('"');
+
+            return match variables.get(&caps[1]) {
+                Some(documents) if !quoted => resolve(&caps[1], &caps[2], documents).unwrap_or_else(|e| {
+                    error.get_or_insert(e);
+                    return String::new();
+                }),
+                None if !quoted && !caps[2].is_empty() => {
+                    error.get_or_insert(format!("Unknown variable ${}", &caps[1]));
+                    return String::new();
+                },
+                _ => whole.as_str().to_string(),
+            };
+        });
+
+        if let Some(e) = error { return Err(e); }
+        substituted.push(replaced.into_owned());
+    }
+
+    return Ok(substituted);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{ doc, oid::ObjectId };
+
+    fn variables() -> Variables {
+        let mut variables = Variables::new();
+        variables.insert(String::from("admin"), vec![doc! { "username": "artur", "suid": "s1", "profile": { "age": 30 }, "friends": ["s2", "s3"] }]);
+        variables.insert(String::from(LAST), vec![doc! { "username": "bob" }, doc! { "username": "carl", "active": true }]);
+        return variables;
+    }
+
+    fn substitute(arg:&str) -> Result<String, String> {
+        return super::substitute(&[arg.to_string()], &variables()).map(|argv| argv[0].to_string());
+    }
+
+    #[test]
+    fn index_and_field() {
+        assert_eq!(substitute("$last[0].username"), Ok(String::from("bob")));
+        assert_eq!(substitute("$last[1].active"), Ok(String::from("true")));
+        assert_eq!(substitute("$admin[0].friends[1]"), Ok(String::from("s3")));
+        assert_eq!(substitute("$admin.profile.age"), Ok(String::from("30")));
+        assert_eq!(substitute("suid:$admin.suid"), Ok(String::from("suid:s1")));
+    }
+
+    #[test]
+    fn reference_errors() {
+        assert_eq!(substitute("$last[2].username"), Err(String::from("$last has 2 item(s), there is no [2]")));
+        assert_eq!(substitute("$last.username"), Err(String::from("$last has 2 documents, pick one with $last[0]")));
+        assert_eq!(substitute("$last[0].email"), Err(String::from("$last[0] has no field <email>")));
+        assert_eq!(substitute("$admin.username[0]"), Err(String::from("$admin.username is not a list")));
+        assert_eq!(substitute("$nobody[0]"), Err(String::from("Unknown variable $nobody")));
+    }
+
+    #[test]
+    fn left_alone() {
+        /*- Aggregation pipelines use $field and $operators -*/
+        assert_eq!(substitute("{\"$group\":{\"_id\":\"$role\"}}"), Ok(String::from("{\"$group\":{\"_id\":\"$role\"}}")));
+        assert_eq!(substitute("$nobody"), Ok(String::from("$nobody")));
+        assert_eq!(substitute("\"$last[0]\""), Ok(String::from("\"$last[0]\"")));
+    }
+
+    #[test]
+    fn formatted_values() {
+        let id = ObjectId::new();
+        assert_eq!(format_value(&Bson::ObjectId(id)), id.to_hex());
+        assert_eq!(format_value(&Bson::Null), "null");
+        assert_eq!(format_value(&Bson::Array(vec![Bson::Int32(1), Bson::String(String::from("a"))])), "[1,\"a\"]");
+    }
+
+    #[test]
+    fn names() {
+        assert!(valid_name("admin_2").is_ok());
+        assert!(valid_name(LAST).is_err());
+        assert!(valid_name("2admin").is_err());
+        assert!(valid_name("ad-min").is_err());
+    }
+}
@@ -94,7 +94,7 @@ const checkFN__checkUsername = async () => {
 /*- Prettify date test -*/
 const checkFN__getPrettifiedDate = () => {
     try {
-        assert.equal(getPrettifiedDate(1577836800000), "Thursday, January 1 - 2020");
+        assert.equal(getPrettifiedDate(1577836800000), "Wednesday, January 1 - 2020");
     } catch (e) {
         return fail("getPrettifiedDate")
     }